@@ -0,0 +1,80 @@
+//! The storage the engine keeps its files in. All SSTs, WALs, value logs and manifests are
+//! accessed through a `StorageBackend`, so that the database can live on the local filesystem, in
+//! memory for fast tests, or behind a wrapper that injects faults.
+
+mod fault_injection;
+mod local;
+mod memory;
+
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+pub use fault_injection::FaultInjectionBackend;
+pub use local::LocalBackend;
+pub use memory::MemoryBackend;
+
+/// A file opened through a `StorageBackend`. Files are only ever appended to, and may be read at
+/// any offset concurrently.
+pub trait StorageFile: Send + Sync {
+    /// Reads exactly `buf.len()` bytes starting at `offset`.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()>;
+
+    /// Appends data to the end of the file.
+    fn append(&self, data: &[u8]) -> Result<()>;
+
+    /// Makes the data appended so far durable.
+    fn sync(&self) -> Result<()>;
+
+    /// The number of bytes in the file.
+    fn size(&self) -> Result<u64>;
+
+    /// Shrinks the file to `len` bytes and makes it durable.
+    fn truncate(&self, len: u64) -> Result<()>;
+
+    /// Reads the whole file.
+    fn read_all(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![0; self.size()? as usize];
+        self.read_at(&mut buf, 0)?;
+        Ok(buf)
+    }
+}
+
+/// A filesystem-like store of files, organized in directories.
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Creates a directory and all of its missing parents.
+    fn create_dir_all(&self, dir: &Path) -> Result<()>;
+
+    /// Creates a new empty file, failing if it already exists.
+    fn create(&self, path: &Path) -> Result<Arc<dyn StorageFile>>;
+
+    /// Opens an existing file for reading and appending.
+    fn open(&self, path: &Path) -> Result<Arc<dyn StorageFile>>;
+
+    /// Whether a file or directory exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Lists the paths of the files in a directory.
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Deletes a file. Files that are still open can be read until they are dropped.
+    fn delete(&self, path: &Path) -> Result<()>;
+
+    /// Atomically renames a file or a directory. A file at the target is replaced.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Makes the creation, deletion and renaming of the files in a directory durable.
+    fn sync_dir(&self, dir: &Path) -> Result<()>;
+
+    /// Makes `to` another name of the file `from`. Callers fall back to copying the file if the
+    /// backend does not support it.
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        bail!(
+            "cannot link {} to {}: hard links are not supported",
+            from.display(),
+            to.display()
+        )
+    }
+}
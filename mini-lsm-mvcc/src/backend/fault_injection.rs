@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use super::{StorageBackend, StorageFile};
+
+/// Wraps another backend to test crash consistency. It can fail writes on demand, and drop
+/// everything that was not synced to simulate a power loss.
+#[derive(Debug, Clone)]
+pub struct FaultInjectionBackend {
+    inner: Arc<dyn StorageBackend>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    /// The number of writes that may still succeed, or `None` if writes do not fail.
+    writes_left: Option<usize>,
+    /// The kind of the injected errors, `ErrorKind::Other` if `None`.
+    error_kind: Option<io::ErrorKind>,
+    /// The length of each file written through this backend as of its last sync, or `None` if
+    /// it was created and never synced.
+    synced_len: HashMap<PathBuf, Option<u64>>,
+}
+
+impl FaultState {
+    /// Counts a write, or fails it if writes are failing.
+    fn check_write(&mut self) -> Result<()> {
+        match &mut self.writes_left {
+            Some(0) => Err(io::Error::new(
+                self.error_kind.unwrap_or(io::ErrorKind::Other),
+                "injected write error",
+            )
+            .into()),
+            Some(writes_left) => {
+                *writes_left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+struct FaultInjectionFile {
+    path: PathBuf,
+    inner: Arc<dyn StorageFile>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl StorageFile for FaultInjectionFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.inner.read_at(buf, offset)
+    }
+
+    fn append(&self, data: &[u8]) -> Result<()> {
+        self.state.lock().check_write()?;
+        self.inner.append(data)
+    }
+
+    fn sync(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.inner.sync()?;
+        let len = self.inner.size()?;
+        state.synced_len.insert(self.path.clone(), Some(len));
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.inner.size()
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.inner.truncate(len)?;
+        state.synced_len.insert(self.path.clone(), Some(len));
+        Ok(())
+    }
+}
+
+impl FaultInjectionBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            inner,
+            state: Default::default(),
+        }
+    }
+
+    /// Fails every write from now on if `fail` is set, and lets writes succeed again otherwise.
+    pub fn set_fail_writes(&self, fail: bool) {
+        self.state.lock().writes_left = fail.then_some(0);
+    }
+
+    /// Sets the kind of the I/O errors returned by failing writes, e.g. `ErrorKind::StorageFull` to
+    /// simulate a full disk.
+    pub fn set_write_error_kind(&self, kind: io::ErrorKind) {
+        self.state.lock().error_kind = Some(kind);
+    }
+
+    /// Lets the next `writes` writes succeed, and fails every write after them. Creating, deleting
+    /// and renaming files, appending to them and syncing them all count as writes.
+    pub fn fail_writes_after(&self, writes: usize) {
+        self.state.lock().writes_left = Some(writes);
+    }
+
+    /// Simulates a power loss by dropping everything written since the last sync of each file.
+    /// Files that were created and never synced are deleted. The database must not be open.
+    pub fn drop_unsynced_writes(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        for (path, synced_len) in state.synced_len.drain() {
+            match synced_len {
+                Some(len) => {
+                    let file = self.inner.open(&path)?;
+                    if file.size()? > len {
+                        file.truncate(len)?;
+                    }
+                }
+                None => self.inner.delete(&path)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn wrap(&self, path: &Path, inner: Arc<dyn StorageFile>) -> Arc<dyn StorageFile> {
+        Arc::new(FaultInjectionFile {
+            path: path.to_path_buf(),
+            inner,
+            state: self.state.clone(),
+        })
+    }
+}
+
+impl StorageBackend for FaultInjectionBackend {
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        self.state.lock().check_write()?;
+        self.inner.create_dir_all(dir)
+    }
+
+    fn create(&self, path: &Path) -> Result<Arc<dyn StorageFile>> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        let file = self.inner.create(path)?;
+        state.synced_len.insert(path.to_path_buf(), None);
+        Ok(self.wrap(path, file))
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn StorageFile>> {
+        let mut state = self.state.lock();
+        let file = self.inner.open(path)?;
+        // What is in the file when it is opened is durable.
+        if !state.synced_len.contains_key(path) {
+            state
+                .synced_len
+                .insert(path.to_path_buf(), Some(file.size()?));
+        }
+        Ok(self.wrap(path, file))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.list(dir)
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.inner.delete(path)?;
+        state.synced_len.remove(path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.inner.rename(from, to)?;
+        // Keep track of the renamed file, or of the files in a renamed directory.
+        let moved = state
+            .synced_len
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved {
+            let synced_len = state.synced_len.remove(&path).unwrap();
+            let target = to.join(path.strip_prefix(from).unwrap());
+            state.synced_len.insert(target, synced_len);
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        self.state.lock().check_write()?;
+        self.inner.sync_dir(dir)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.inner.hard_link(from, to)?;
+        let synced_len = match state.synced_len.get(from) {
+            Some(synced_len) => *synced_len,
+            None => Some(self.inner.open(to)?.size()?),
+        };
+        state.synced_len.insert(to.to_path_buf(), synced_len);
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+
+use super::{StorageBackend, StorageFile};
+
+/// Stores the files on the local filesystem.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalBackend;
+
+struct LocalFile {
+    file: File,
+    /// Serializes appends, which may take several writes.
+    append_lock: Mutex<()>,
+}
+
+impl StorageFile for LocalFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.file.read_exact_at(buf, offset)?;
+        Ok(())
+    }
+
+    fn append(&self, data: &[u8]) -> Result<()> {
+        let _lck = self.append_lock.lock();
+        (&self.file).write_all(data)?;
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let _lck = self.append_lock.lock();
+        self.file.set_len(len)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+impl LocalBackend {
+    fn open_file(path: &Path, options: &OpenOptions) -> Result<Arc<dyn StorageFile>> {
+        let file = options
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(Arc::new(LocalFile {
+            file,
+            append_lock: Mutex::new(()),
+        }))
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create dir {}", dir.display()))
+    }
+
+    fn create(&self, path: &Path) -> Result<Arc<dyn StorageFile>> {
+        Self::open_file(
+            path,
+            OpenOptions::new().read(true).append(true).create_new(true),
+        )
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn StorageFile>> {
+        Self::open_file(path, OpenOptions::new().read(true).append(true))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        Ok(paths)
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(path).with_context(|| format!("failed to delete {}", path.display()))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::rename(from, to)
+            .with_context(|| format!("failed to rename {} to {}", from.display(), to.display()))
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::hard_link(from, to)?;
+        Ok(())
+    }
+}
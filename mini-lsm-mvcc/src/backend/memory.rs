@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use parking_lot::{Mutex, RwLock};
+
+use super::{StorageBackend, StorageFile};
+
+/// Keeps the files in memory, which makes tests fast and leaves nothing behind. Everything written
+/// is considered durable.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    /// The files by path. A hard link is another path to the same file.
+    files: Mutex<BTreeMap<PathBuf, Arc<MemoryFile>>>,
+    dirs: Mutex<BTreeSet<PathBuf>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFile {
+    data: RwLock<Vec<u8>>,
+}
+
+impl StorageFile for MemoryFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let data = self.data.read();
+        let offset = offset as usize;
+        if offset + buf.len() > data.len() {
+            bail!("read past the end of the file");
+        }
+        buf.copy_from_slice(&data[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn append(&self, data: &[u8]) -> Result<()> {
+        self.data.write().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.data.read().len() as u64)
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.data.write().truncate(len as usize);
+        Ok(())
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() && !self.dirs.lock().contains(dir) => {
+                bail!("dir {} does not exist", dir.display())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        let mut dirs = self.dirs.lock();
+        dirs.extend(dir.ancestors().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    fn create(&self, path: &Path) -> Result<Arc<dyn StorageFile>> {
+        self.check_parent(path)?;
+        let mut files = self.files.lock();
+        if files.contains_key(path) {
+            bail!("file {} already exists", path.display());
+        }
+        let file = Arc::new(MemoryFile::default());
+        files.insert(path.to_path_buf(), file.clone());
+        Ok(file)
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn StorageFile>> {
+        match self.files.lock().get(path) {
+            Some(file) => Ok(file.clone()),
+            None => bail!("file {} does not exist", path.display()),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.lock().contains_key(path) || self.dirs.lock().contains(path)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        if !self.dirs.lock().contains(dir) {
+            bail!("dir {} does not exist", dir.display());
+        }
+        Ok(self
+            .files
+            .lock()
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        match self.files.lock().remove(path) {
+            Some(_) => Ok(()),
+            None => bail!("file {} does not exist", path.display()),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.check_parent(to)?;
+        let mut files = self.files.lock();
+        if let Some(file) = files.remove(from) {
+            files.insert(to.to_path_buf(), file);
+            return Ok(());
+        }
+        let mut dirs = self.dirs.lock();
+        if !dirs.contains(from) {
+            bail!("{} does not exist", from.display());
+        }
+        // Move the directory along with everything in it.
+        let moved = |path: &Path| to.join(path.strip_prefix(from).unwrap());
+        let moved_dirs = dirs
+            .iter()
+            .filter(|dir| dir.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dir in moved_dirs {
+            dirs.remove(&dir);
+            dirs.insert(moved(&dir));
+        }
+        let moved_files = files
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved_files {
+            let file = files.remove(&path).unwrap();
+            files.insert(moved(&path), file);
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        self.check_parent(to)?;
+        let mut files = self.files.lock();
+        let Some(file) = files.get(from).cloned() else {
+            bail!("file {} does not exist", from.display());
+        };
+        if files.contains_key(to) {
+            bail!("file {} already exists", to.display());
+        }
+        files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+}
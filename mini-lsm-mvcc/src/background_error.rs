@@ -0,0 +1,170 @@
+//! The error state of flushes and compactions. Work that fails with a transient error, such as a
+//! full disk, is retried with exponential backoff, and writes fail until it succeeds or the DB is
+//! resumed. Any other error may leave the files of the DB out of sync with its state in memory, so
+//! it makes the DB read-only until it is reopened.
+
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+
+use crate::event_listener::BackgroundErrorReason;
+
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSeverity {
+    /// The work is retried, and writes resume once it succeeds.
+    Transient,
+    /// The DB is read-only until it is reopened.
+    Fatal,
+}
+
+impl ErrorSeverity {
+    /// I/O errors that may go away by themselves are transient, unless the manifest failed to be
+    /// written. All other errors, such as corrupted SSTs, are fatal.
+    pub(crate) fn of(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<ManifestWriteError>().is_some() {
+            return ErrorSeverity::Fatal;
+        }
+        let transient = error
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<io::Error>())
+            .any(|error| {
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::StorageFull
+                        | io::ErrorKind::QuotaExceeded
+                        | io::ErrorKind::Interrupted
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                )
+            });
+        if transient {
+            ErrorSeverity::Transient
+        } else {
+            ErrorSeverity::Fatal
+        }
+    }
+}
+
+/// Marks an error of a manifest write, after which the manifest may not match the state in memory.
+#[derive(Debug)]
+pub(crate) struct ManifestWriteError;
+
+impl fmt::Display for ManifestWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to write the manifest")
+    }
+}
+
+/// The error that stopped writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackgroundError {
+    pub reason: BackgroundErrorReason,
+    /// The column family of the compaction that failed.
+    pub column_family: Option<String>,
+    pub severity: ErrorSeverity,
+    pub message: String,
+    /// How many times the work failed again since the error was first set.
+    pub retries: u32,
+}
+
+impl fmt::Display for BackgroundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            ErrorSeverity::Transient => "transient",
+            ErrorSeverity::Fatal => "fatal",
+        };
+        write!(
+            f,
+            "{} error in {:?}: {}",
+            severity, self.reason, self.message
+        )
+    }
+}
+
+/// Keeps the background error of a DB, and when the failed work may be retried.
+#[derive(Default)]
+pub(crate) struct BackgroundErrorHandler {
+    state: Mutex<Option<(BackgroundError, Instant)>>,
+}
+
+impl BackgroundErrorHandler {
+    pub(crate) fn error(&self) -> Option<BackgroundError> {
+        self.state.lock().as_ref().map(|(error, _)| error.clone())
+    }
+
+    /// Records a failure. A fatal error replaces a transient one, and another transient error
+    /// doubles the delay before the next retry.
+    pub(crate) fn set(
+        &self,
+        reason: BackgroundErrorReason,
+        column_family: Option<&str>,
+        error: &anyhow::Error,
+    ) {
+        let severity = ErrorSeverity::of(error);
+        let mut state = self.state.lock();
+        let retries = match &*state {
+            Some((current, _)) if current.severity == ErrorSeverity::Fatal => return,
+            Some((current, _)) if severity == ErrorSeverity::Transient => current.retries + 1,
+            _ => 0,
+        };
+        let delay = MIN_RETRY_DELAY
+            .saturating_mul(1 << retries.min(16))
+            .min(MAX_RETRY_DELAY);
+        let error = BackgroundError {
+            reason,
+            column_family: column_family.map(str::to_string),
+            severity,
+            message: format!("{:#}", error),
+            retries,
+        };
+        *state = Some((error, Instant::now() + delay));
+    }
+
+    /// Clears a transient error once the work that failed succeeded.
+    pub(crate) fn clear(&self, reason: BackgroundErrorReason, column_family: Option<&str>) {
+        let mut state = self.state.lock();
+        if let Some((error, _)) = &*state {
+            if error.severity == ErrorSeverity::Transient
+                && error.reason == reason
+                && error.column_family.as_deref() == column_family
+            {
+                log::info!("recovered from {}", error);
+                *state = None;
+            }
+        }
+    }
+
+    /// Whether flushes and compactions may run now. They stop after a fatal error, and wait for
+    /// the next retry after a transient one.
+    pub(crate) fn may_run(&self) -> bool {
+        match &*self.state.lock() {
+            None => true,
+            Some((error, _)) if error.severity == ErrorSeverity::Fatal => false,
+            Some((_, retry_at)) => Instant::now() >= *retry_at,
+        }
+    }
+
+    /// Fails if writes are stopped by a background error.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if let Some((error, _)) = &*self.state.lock() {
+            bail!("writes are stopped by a background {}", error);
+        }
+        Ok(())
+    }
+
+    /// Fails if the DB is read-only after a fatal error.
+    pub(crate) fn check_not_read_only(&self) -> Result<()> {
+        match &*self.state.lock() {
+            Some((error, _)) if error.severity == ErrorSeverity::Fatal => {
+                bail!("the DB is read-only after a background {}", error)
+            }
+            _ => Ok(()),
+        }
+    }
+}
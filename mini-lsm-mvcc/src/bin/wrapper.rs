@@ -2,5 +2,25 @@ pub mod mini_lsm_wrapper {
     pub use mini_lsm_mvcc::*;
 }
 
+/// The statistics of the DB as text.
+#[allow(dead_code)]
+pub fn statistics(lsm: &mini_lsm_wrapper::lsm_storage::MiniLsm) -> Option<String> {
+    Some(lsm.statistics().to_string())
+}
+
+/// Limits the write rate of flushes and compactions.
+#[allow(dead_code)]
+pub fn set_rate_limit(
+    options: &mut mini_lsm_wrapper::lsm_storage::LsmStorageOptions,
+    bytes_per_second: u64,
+) -> anyhow::Result<()> {
+    use mini_lsm_wrapper::rate_limiter::{RateLimiter, RateLimiterMode};
+    options.rate_limiter = Some(std::sync::Arc::new(RateLimiter::new(
+        bytes_per_second,
+        RateLimiterMode::WritesOnly,
+    )));
+    Ok(())
+}
+
 #[allow(dead_code)]
 fn main() {}
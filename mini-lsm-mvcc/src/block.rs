@@ -1,17 +1,80 @@
 mod builder;
 mod iterator;
 
+use anyhow::{bail, Result};
 pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::table::{SST_FORMAT_CURRENT, SST_FORMAT_LEGACY, SST_FORMAT_V2, SST_FORMAT_V3};
+
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U32: usize = std::mem::size_of::<u32>();
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
 pub struct Block {
     pub(crate) data: Vec<u8>,
-    pub(crate) offsets: Vec<u16>,
+    pub(crate) offsets: Vec<u32>,
+}
+
+/// How the value of a block entry is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    /// The value is stored in the block.
+    Inline,
+    /// The block stores an encoded `ValuePointer` into the value log.
+    Pointer,
+}
+
+impl ValueKind {
+    pub(crate) fn encode(self) -> u8 {
+        match self {
+            ValueKind::Inline => 0,
+            ValueKind::Pointer => 1,
+        }
+    }
+
+    pub(crate) fn decode(kind: u8) -> Self {
+        match kind {
+            0 => ValueKind::Inline,
+            1 => ValueKind::Pointer,
+            _ => panic!("invalid value kind {}", kind),
+        }
+    }
+}
+
+/// Returns the number of bytes `value` takes when encoded as a varint.
+pub(crate) fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Appends `value` to the buffer as a LEB128 varint.
+pub(crate) fn put_varint(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Reads a LEB128 varint from the buffer and advances it.
+pub(crate) fn get_varint(buf: &mut impl Buf) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
 }
 
 impl Block {
@@ -19,25 +82,79 @@ impl Block {
         let mut buf = self.data.clone();
         let offsets_len = self.offsets.len();
         for offset in &self.offsets {
-            buf.put_u16(*offset);
+            buf.put_u32(*offset);
         }
         // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        buf.put_u32(offsets_len as u32);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
         // get number of elements in the block
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        let entry_offsets_len = (&data[data.len() - SIZEOF_U32..]).get_u32() as usize;
+        let data_end = data.len() - SIZEOF_U32 - entry_offsets_len * SIZEOF_U32;
+        let offsets_raw = &data[data_end..data.len() - SIZEOF_U32];
         // get offset array
         let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
         // retrieve data
         let data = data[0..data_end].to_vec();
         Self { data, offsets }
     }
+
+    /// Decode a block written in the given SST format version. Blocks written by older versions
+    /// are transcoded into the current in-memory layout.
+    pub fn decode_with_version(data: &[u8], version: u32) -> Result<Self> {
+        type GetLen = fn(&mut &[u8]) -> u64;
+        let (offset_size, get_offset, get_len): (usize, GetLen, GetLen) = match version {
+            // The layout of blocks has not changed since v3.
+            SST_FORMAT_V3..=SST_FORMAT_CURRENT => return Ok(Self::decode(data)),
+            // v1 stores all lengths and offsets as `u16`.
+            SST_FORMAT_LEGACY => (
+                SIZEOF_U16,
+                |buf| buf.get_u16() as u64,
+                |buf| buf.get_u16() as u64,
+            ),
+            // v2 has the current layout except for the value kind of each entry.
+            SST_FORMAT_V2 => (
+                SIZEOF_U32,
+                |buf| buf.get_u32() as u64,
+                |buf| get_varint(buf),
+            ),
+            _ => bail!("unsupported block format version {}", version),
+        };
+        if data.len() < offset_size {
+            bail!("block too small");
+        }
+        let num_of_entries = get_offset(&mut &data[data.len() - offset_size..]) as usize;
+        let data_end = data
+            .len()
+            .checked_sub(offset_size + num_of_entries * offset_size)
+            .ok_or_else(|| anyhow::anyhow!("block offsets out of range"))?;
+        let mut new_data = Vec::with_capacity(data_end + num_of_entries);
+        let mut offsets = Vec::with_capacity(num_of_entries);
+        for mut raw_offset in data[data_end..data.len() - offset_size].chunks(offset_size) {
+            let mut entry = &data[get_offset(&mut raw_offset) as usize..data_end];
+            // The key overlap is computed against the first key of the block in all formats, so
+            // the entry can be transcoded field by field.
+            let overlap_len = get_len(&mut entry);
+            let key_len = get_len(&mut entry) as usize;
+            offsets.push(new_data.len() as u32);
+            put_varint(&mut new_data, overlap_len);
+            put_varint(&mut new_data, key_len as u64);
+            new_data.put_slice(&entry[..key_len]);
+            entry.advance(key_len);
+            new_data.put_u64(entry.get_u64());
+            new_data.put_u8(ValueKind::Inline.encode());
+            let value_len = get_len(&mut entry) as usize;
+            put_varint(&mut new_data, value_len as u64);
+            new_data.put_slice(&entry[..value_len]);
+        }
+        Ok(Self {
+            data: new_data,
+            offsets,
+        })
+    }
 }
@@ -2,12 +2,12 @@ use bytes::BufMut;
 
 use crate::key::{KeySlice, KeyVec};
 
-use super::{Block, SIZEOF_U16};
+use super::{put_varint, varint_len, Block, ValueKind, SIZEOF_U32};
 
 /// Builds a block.
 pub struct BlockBuilder {
     /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    offsets: Vec<u32>,
     /// All serialized key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
@@ -42,32 +42,54 @@ impl BlockBuilder {
     }
 
     fn estimated_size(&self) -> usize {
-        SIZEOF_U16 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U16 /* offsets */ + self.data.len()
+        SIZEOF_U32 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U32 /* offsets */ + self.data.len()
         // key-value pairs
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// The encoded size of an entry, including its offset.
+    fn entry_size(overlap: usize, key: KeySlice, value: &[u8]) -> usize {
+        let rest_len = key.key_len() - overlap;
+        varint_len(overlap as u64)
+            + varint_len(rest_len as u64)
+            + rest_len
+            + std::mem::size_of::<u64>()
+            + std::mem::size_of::<u8>()
+            + varint_len(value.len() as u64)
+            + value.len()
+            + SIZEOF_U32
+    }
+
+    /// Adds a key-value pair to the block. Returns false when the block is full. An entry larger
+    /// than the block size is always accepted by an empty block.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_with_kind(key, ValueKind::Inline, value)
+    }
+
+    /// Adds an entry whose value is stored as `kind`. Returns false when the block is full.
+    #[must_use]
+    pub(crate) fn add_with_kind(&mut self, key: KeySlice, kind: ValueKind, value: &[u8]) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
+        let overlap = compute_overlap(self.first_key.as_key_slice(), key);
+        if self.estimated_size() + Self::entry_size(overlap, key, value) > self.block_size
             && !self.is_empty()
         {
             return false;
         }
         // Add the offset of the data into the offset array.
-        self.offsets.push(self.data.len() as u16);
-        let overlap = compute_overlap(self.first_key.as_key_slice(), key);
+        self.offsets.push(self.data.len() as u32);
         // Encode key overlap.
-        self.data.put_u16(overlap as u16);
+        put_varint(&mut self.data, overlap as u64);
         // Encode key length.
-        self.data.put_u16((key.key_len() - overlap) as u16);
+        put_varint(&mut self.data, (key.key_len() - overlap) as u64);
         // Encode key content.
         self.data.put(&key.key_ref()[overlap..]);
         // Encode key ts
         self.data.put_u64(key.ts());
+        // Encode value kind.
+        self.data.put_u8(kind.encode());
         // Encode value length.
-        self.data.put_u16(value.len() as u16);
+        put_varint(&mut self.data, value.len() as u64);
         // Encode value content.
         self.data.put(value);
 
@@ -2,12 +2,9 @@ use std::sync::Arc;
 
 use bytes::Buf;
 
-use crate::{
-    block::SIZEOF_U16,
-    key::{KeySlice, KeyVec},
-};
+use crate::key::{KeySlice, KeyVec};
 
-use super::Block;
+use super::{get_varint, Block, ValueKind};
 
 /// Iterates on a block.
 pub struct BlockIterator {
@@ -17,6 +14,8 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the value range from the block
     value_range: (usize, usize),
+    /// how the current value is stored
+    value_kind: ValueKind,
     /// the current index at the iterator position
     idx: usize,
     /// the first key in the block
@@ -26,8 +25,8 @@ pub struct BlockIterator {
 impl Block {
     fn get_first_key(&self) -> KeyVec {
         let mut buf = &self.data[..];
-        buf.get_u16();
-        let key_len = buf.get_u16() as usize;
+        get_varint(&mut buf);
+        let key_len = get_varint(&mut buf) as usize;
         let key = &buf[..key_len];
         buf.advance(key_len);
         KeyVec::from_vec_with_ts(key.to_vec(), buf.get_u64())
@@ -41,6 +40,7 @@ impl BlockIterator {
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
+            value_kind: ValueKind::Inline,
             idx: 0,
         }
     }
@@ -52,6 +52,13 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
     /// Creates a block iterator and seek to the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: KeySlice) -> Self {
         let mut iter = Self::new(block);
@@ -71,6 +78,12 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns how the value of the current entry is stored.
+    pub fn value_kind(&self) -> ValueKind {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.value_kind
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -81,6 +94,11 @@ impl BlockIterator {
         self.seek_to(0);
     }
 
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        self.seek_to(self.block.offsets.len() - 1);
+    }
+
     /// Seeks to the idx-th key in the block.
     fn seek_to(&mut self, idx: usize) {
         if idx >= self.block.offsets.len() {
@@ -99,14 +117,25 @@ impl BlockIterator {
         self.seek_to(self.idx);
     }
 
+    /// Move to the previous key in the block. The iterator becomes invalid when moving before the
+    /// first key.
+    pub fn prev(&mut self) {
+        if self.idx == 0 {
+            self.seek_to(self.block.offsets.len());
+            return;
+        }
+        self.idx -= 1;
+        self.seek_to(self.idx);
+    }
+
     /// Seek to the specified position and update the current `key` and `value`
     /// Index update will be handled by caller
     fn seek_to_offset(&mut self, offset: usize) {
         let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
+        // Since `get_varint()` will automatically move the ptr ahead here,
         // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
-        let key_len = entry.get_u16() as usize;
+        let overlap_len = get_varint(&mut entry) as usize;
+        let key_len = get_varint(&mut entry) as usize;
         let key = &entry[..key_len];
         self.key.clear();
         self.key.append(&self.first_key.key_ref()[..overlap_len]);
@@ -114,10 +143,10 @@ impl BlockIterator {
         entry.advance(key_len);
         let ts = entry.get_u64();
         self.key.set_ts(ts);
-        let value_len = entry.get_u16() as usize;
-        // REMEMBER TO CHANGE THIS every time you change the encoding!
-        let value_offset_begin =
-            offset + SIZEOF_U16 + SIZEOF_U16 + std::mem::size_of::<u64>() + key_len + SIZEOF_U16;
+        self.value_kind = ValueKind::decode(entry.get_u8());
+        let value_len = get_varint(&mut entry) as usize;
+        // Lengths are variable-sized, so derive the value position from what is left to read.
+        let value_offset_begin = self.block.data.len() - entry.remaining();
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
         entry.advance(value_len);
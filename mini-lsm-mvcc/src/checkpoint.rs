@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::backend::{StorageBackend, StorageFile};
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::Manifest;
+
+/// The size of the chunks in which files are copied.
+const COPY_CHUNK_SIZE: u64 = 1 << 20;
+
+/// A file of the database to include in a checkpoint. It is opened while the state lock is held,
+/// and the open file stays readable even if the database deletes it before it is linked or copied.
+pub(crate) struct PinnedFile {
+    path: PathBuf,
+    file: Arc<dyn StorageFile>,
+    size: u64,
+}
+
+impl PinnedFile {
+    pub(crate) fn open(backend: &dyn StorageBackend, path: PathBuf) -> Result<Self> {
+        let file = backend.open(&path)?;
+        let size = file.size()?;
+        Ok(Self { path, file, size })
+    }
+
+    /// Hard-links the file into `dir`, or copies it if the target is on another filesystem, the
+    /// backend does not support hard links or the file was already deleted.
+    pub(crate) fn link_or_copy(&self, backend: &dyn StorageBackend, dir: &Path) -> Result<()> {
+        let to = dir.join(self.path.file_name().context("invalid file name")?);
+        if backend.hard_link(&self.path, &to).is_ok() {
+            return Ok(());
+        }
+        let copy = || {
+            let file = backend.create(&to)?;
+            let mut buf = vec![0; COPY_CHUNK_SIZE.min(self.size) as usize];
+            let mut offset = 0;
+            while offset < self.size {
+                let len = COPY_CHUNK_SIZE.min(self.size - offset) as usize;
+                self.file.read_at(&mut buf[..len], offset)?;
+                file.append(&buf[..len])?;
+                offset += len as u64;
+            }
+            file.sync()
+        };
+        copy()
+            .with_context(|| format!("failed to copy {} to {}", self.path.display(), to.display()))
+    }
+}
+
+impl LsmStorageInner {
+    /// Creates a copy of the database in `dir`, which must not exist yet, that can be opened with
+    /// the same options.
+    ///
+    /// The memtables are flushed first, so that the checkpoint mostly consists of SSTs, the value
+    /// log files they reference and a manifest describing them. Memtables frozen while the
+    /// checkpoint flushes are included through their WALs, which are no longer written to. The
+    /// files are pinned while the state lock is held, and linked or copied after releasing it.
+    pub(crate) fn checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        let backend = self.backend();
+        if backend.exists(dir) {
+            bail!("checkpoint dir {} already exists", dir.display());
+        }
+        // Build the checkpoint next to the target, so that a failed checkpoint is never mistaken
+        // for a complete one.
+        let mut tmp_name = dir
+            .file_name()
+            .context("invalid checkpoint dir")?
+            .to_owned();
+        tmp_name.push(".tmp");
+        let tmp_dir = dir.with_file_name(tmp_name);
+        if backend.exists(&tmp_dir) {
+            for path in backend.list(&tmp_dir)? {
+                backend.delete(&path)?;
+            }
+        }
+        backend
+            .create_dir_all(&tmp_dir)
+            .context("failed to create checkpoint dir")?;
+
+        let first_unfrozen_id = {
+            let state_lock = self.state_lock.lock();
+            if self.has_unfrozen_data() {
+                self.freeze_memtables(None, &state_lock)?;
+            }
+            self.column_families
+                .iter()
+                .map(|cf| cf.state.read().memtable.id())
+                .min()
+                .unwrap()
+        };
+        let (records, files) = loop {
+            let state_lock = self.state_lock.lock();
+            // Without a WAL, memtables frozen in the meantime must be flushed as well.
+            let unflushed = self.column_families.iter().find(|cf| {
+                cf.state
+                    .read()
+                    .imm_memtables
+                    .last()
+                    .is_some_and(|memtable| {
+                        !self.options.enable_wal || memtable.id() < first_unfrozen_id
+                    })
+            });
+            if let Some(cf) = unflushed {
+                self.flush_next_imm_memtable_with_lock(cf, &state_lock)?;
+                continue;
+            }
+            break (
+                self.manifest_snapshot(&state_lock),
+                self.pin_checkpoint_files()?,
+            );
+        };
+
+        Manifest::create(self.options.backend.clone(), &tmp_dir, records)?;
+        for file in files {
+            file.link_or_copy(backend, &tmp_dir)?;
+        }
+
+        backend.sync_dir(&tmp_dir)?;
+        backend
+            .rename(&tmp_dir, dir)
+            .context("failed to rename checkpoint dir")?;
+        if let Some(parent) = dir.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            backend.sync_dir(parent)?;
+        }
+        Ok(())
+    }
+
+    /// Opens the SSTs, the value log files they reference and the WALs of the immutable memtables.
+    /// The caller must hold the state lock, so that none of them is deleted before it is opened.
+    fn pin_checkpoint_files(&self) -> Result<Vec<PinnedFile>> {
+        let backend = self.backend();
+        let mut files = Vec::new();
+        let mut value_log_files = BTreeSet::new();
+        let mut wal_files = BTreeSet::new();
+        for cf in &self.column_families {
+            let snapshot = cf.state.read().clone();
+            for sst in snapshot.sstables.values() {
+                files.push(PinnedFile::open(backend, self.path_of_sst(sst.sst_id()))?);
+                value_log_files.extend(sst.value_log_refs().iter().map(|(id, _)| *id));
+            }
+            if self.options.enable_wal {
+                wal_files.extend(
+                    snapshot
+                        .imm_memtables
+                        .iter()
+                        .map(|memtable| memtable.wal_id()),
+                );
+            }
+        }
+        for id in value_log_files {
+            files.push(PinnedFile::open(backend, self.path_of_vlog(id))?);
+        }
+        for id in wal_files {
+            files.push(PinnedFile::open(backend, self.path_of_wal(id))?);
+        }
+        Ok(files)
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::compact::{CompactionController, CompactionOptions};
+use crate::lsm_storage::LsmStorageState;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::table::CompressionType;
+
+/// The name of the column family that always exists and is used by the methods without a column
+/// family argument.
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+pub(crate) const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// The options of a column family. The options of the default column family are the ones of the
+/// same name in `LsmStorageOptions`.
+#[derive(Debug, Clone)]
+pub struct ColumnFamilyOptions {
+    // Block size in bytes
+    pub block_size: usize,
+    // SST size in bytes, also the approximate memtable capacity limit
+    pub target_sst_size: usize,
+    // Maximum number of memtables in memory, flush to L0 when exceeding this limit
+    pub num_memtable_limit: usize,
+    pub compaction_options: CompactionOptions,
+    // Build per-SST bloom filters over key prefixes for `scan_prefix`, disabled if `None`
+    pub prefix_extractor: Option<PrefixExtractor>,
+    // Block compression of the SSTs in each level, starting with L0. Levels past the end use the
+    // last entry, and blocks are not compressed if it is empty
+    pub compression_per_level: Vec<CompressionType>,
+}
+
+impl ColumnFamilyOptions {
+    /// The block compression of SSTs written to `level`.
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        self.compression_per_level
+            .get(level)
+            .or(self.compression_per_level.last())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// An independent keyspace with its own memtables, SSTs and compaction. All column families of a
+/// storage engine share the WAL, manifest, block cache and commit timestamps, so that a write batch
+/// or transaction across column families is atomic.
+///
+/// When the memtable of a column family is frozen, the non-empty memtables of all other column
+/// families are frozen with it, so that a WAL can be removed as soon as its memtables are flushed.
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+    pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
+    pub(crate) options: ColumnFamilyOptions,
+    pub(crate) compaction_controller: CompactionController,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: String, options: ColumnFamilyOptions) -> Self {
+        Self {
+            id,
+            name,
+            state: Arc::new(RwLock::new(Arc::new(LsmStorageState::create(
+                &options.compaction_options,
+            )))),
+            compaction_controller: CompactionController::new(&options.compaction_options),
+            options,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
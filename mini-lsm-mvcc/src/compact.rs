@@ -1,4 +1,5 @@
 mod leveled;
+mod scheduler;
 mod simple_leveled;
 mod tiered;
 
@@ -6,24 +7,33 @@ use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+pub(crate) use scheduler::RunningCompactions;
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
 
+use crate::column_family::ColumnFamily;
+use crate::compaction_filter::CompactionDecision;
+use crate::event_listener::{BackgroundErrorReason, CompactionJobInfo};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
 use crate::manifest::ManifestRecord;
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::range_tombstone::{RangeTombstone, RangeTombstones};
+use crate::rate_limiter::{IoOp, IoPriority};
+use crate::table::{SsTable, SsTableIterator};
+use crate::ttl::{self, TtlOptions};
+use crate::value_log::ValuePointer;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
@@ -43,6 +53,181 @@ impl CompactionTask {
             CompactionTask::Tiered(task) => task.bottom_tier_included,
         }
     }
+
+    /// The level the output of the task is written to, used to choose its compression. Tiers have
+    /// no level; the output is written to the bottom level if it includes the bottom tier and to L1
+    /// otherwise.
+    fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(task) if task.bottom_tier_included => usize::MAX,
+            CompactionTask::Tiered(_) => 1,
+        }
+    }
+
+    /// Whether the task reads L0 SSTs.
+    fn reads_l0(&self) -> bool {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => true,
+            CompactionTask::Leveled(task) => task.upper_level.is_none(),
+            CompactionTask::Simple(task) => task.upper_level.is_none(),
+            CompactionTask::Tiered(_) => false,
+        }
+    }
+
+    /// The ids of the SSTs read by the task.
+    fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => [l0_sstables.as_slice(), l1_sstables].concat(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => [upper_level_sst_ids.as_slice(), lower_level_sst_ids].concat(),
+            CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => tiers
+                .iter()
+                .flat_map(|(_, ssts)| ssts.iter().copied())
+                .collect(),
+        }
+    }
+}
+
+/// The range tombstones relevant to a compaction.
+struct CompactionRangeTombstones {
+    /// The range tombstones of all SSTs, used to drop the versions they cover.
+    all: Vec<RangeTombstone>,
+    /// The range tombstones of the input SSTs, which are written to the output. Each is paired
+    /// with whether no SST outside of the compaction overlaps it, in which case it may be dropped
+    /// when compacting to the bottom level.
+    input: Vec<(RangeTombstone, bool)>,
+}
+
+impl CompactionRangeTombstones {
+    fn new(snapshot: &LsmStorageState, task: &CompactionTask) -> Self {
+        let input_ids = task.input_sst_ids().into_iter().collect::<HashSet<_>>();
+        let mut all = Vec::new();
+        let mut input = Vec::new();
+        for (id, sst) in &snapshot.sstables {
+            all.extend(sst.range_tombstones().iter().cloned());
+            if !input_ids.contains(id) {
+                continue;
+            }
+            for tombstone in sst.range_tombstones() {
+                let isolated = snapshot
+                    .sstables
+                    .iter()
+                    .filter(|(other_id, _)| !input_ids.contains(other_id))
+                    .all(|(_, other)| {
+                        other.last_key().key_ref() < tombstone.start.as_ref()
+                            || other.first_key().key_ref() >= tombstone.end.as_ref()
+                    });
+                input.push((tombstone.clone(), isolated));
+            }
+        }
+        Self { all, input }
+    }
+
+    fn below_watermark(&self, watermark: u64) -> RangeTombstones {
+        RangeTombstones::new(
+            self.all
+                .iter()
+                .filter(|tombstone| tombstone.ts <= watermark)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// The range tombstones of a subcompaction of the keys from `start` up to `end`. Input range
+    /// tombstones are cut to the range, so that each subcompaction writes its part of them.
+    fn clip(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Self {
+        let input = self
+            .input
+            .iter()
+            .filter_map(|(tombstone, isolated)| {
+                let start = start.map_or(tombstone.start.as_ref(), |start| {
+                    start.max(tombstone.start.as_ref())
+                });
+                let end = end.map_or(tombstone.end.as_ref(), |end| {
+                    end.min(tombstone.end.as_ref())
+                });
+                (start < end).then(|| (RangeTombstone::new(start, end, tombstone.ts), *isolated))
+            })
+            .collect();
+        Self {
+            all: self.all.clone(),
+            input,
+        }
+    }
+}
+
+/// The info of a compaction that is about to begin. All its input SSTs must be in `snapshot`.
+fn compaction_job_info(
+    cf: &ColumnFamily,
+    task: &CompactionTask,
+    snapshot: &LsmStorageState,
+) -> CompactionJobInfo {
+    let input_sst_ids = task.input_sst_ids();
+    CompactionJobInfo {
+        column_family: cf.name().to_string(),
+        task: task.clone(),
+        input_bytes: input_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum(),
+        input_sst_ids,
+        output_sst_ids: Vec::new(),
+        output_bytes: 0,
+    }
+}
+
+/// Splits a task into subcompactions of about the same number of input SSTs. Returns the keys
+/// each subcompaction after the first starts at, which are first keys of input SSTs.
+fn subcompaction_boundaries(
+    snapshot: &LsmStorageState,
+    task: &CompactionTask,
+    max_subcompactions: usize,
+) -> Vec<Vec<u8>> {
+    let mut keys = task
+        .input_sst_ids()
+        .iter()
+        .map(|id| snapshot.sstables[id].first_key().key_ref().to_vec())
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    let subcompactions = max_subcompactions.min(keys.len());
+    // The smallest key does not split anything, so it is never picked.
+    (1..subcompactions)
+        .map(|i| keys[i * keys.len() / subcompactions].clone())
+        .collect()
+}
+
+/// Creates an iterator over an SST that starts at `start`, and returns value pointers as they are
+/// stored.
+fn sst_iter(sst: Arc<SsTable>, start: Option<&[u8]>) -> Result<Box<SsTableIterator>> {
+    let mut iter = SsTableIterator::create_and_seek_to_first_raw(sst)?;
+    if let Some(start) = start {
+        iter.seek_to_key(KeySlice::from_slice(start, TS_RANGE_BEGIN))?;
+    }
+    Ok(Box::new(iter))
+}
+
+/// Like `sst_iter`, over a sorted run of SSTs.
+fn concat_iter(ssts: Vec<Arc<SsTable>>, start: Option<&[u8]>) -> Result<SstConcatIterator> {
+    let mut iter = SstConcatIterator::create_and_seek_to_first_raw(ssts)?;
+    if let Some(start) = start {
+        iter.seek_to_key(KeySlice::from_slice(start, TS_RANGE_BEGIN))?;
+    }
+    Ok(iter)
 }
 
 pub(crate) enum CompactionController {
@@ -53,6 +238,21 @@ pub(crate) enum CompactionController {
 }
 
 impl CompactionController {
+    pub(crate) fn new(options: &CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
     pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
@@ -90,6 +290,16 @@ impl CompactionController {
 }
 
 impl CompactionController {
+    /// Estimates the bytes that need to be compacted to bring the column family back into shape.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl.pending_compaction_bytes(snapshot),
+            CompactionController::Simple(ctrl) => ctrl.pending_compaction_bytes(snapshot),
+            CompactionController::Tiered(ctrl) => ctrl.pending_compaction_bytes(snapshot),
+            CompactionController::NoCompaction => 0,
+        }
+    }
+
     pub fn flush_to_l0(&self) -> bool {
         matches!(
             self,
@@ -114,19 +324,42 @@ pub enum CompactionOptions {
 impl LsmStorageInner {
     fn compact_generate_sst_from_iter(
         &self,
+        cf: &ColumnFamily,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
         compact_to_bottom_level: bool,
+        output_level: usize,
+        range_tombstones: CompactionRangeTombstones,
+        end: Option<&[u8]>,
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
         let mut new_sst = Vec::new();
-        let watermark = self.mvcc().watermark();
+        let watermark = self.mvcc().gc_watermark();
+        // Versions covered by a range tombstone below the watermark are invisible to all readers.
+        let covering_tombstones = range_tombstones.below_watermark(watermark);
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
-        'outer: while iter.is_valid() {
+        let ttl_enabled = self.options.ttl.is_some();
+        let now = self.options.ttl.as_ref().map_or(0, TtlOptions::now_millis);
+        // The bytes read but not yet charged to the rate limiter, which is charged about once a block.
+        let mut read_bytes = 0;
+        // Keys before this key and at or below the watermark are removed without being passed to
+        // the filters, as asked by a filter.
+        let mut skip_until = None::<Bytes>;
+        while iter.is_valid() {
+            if matches!(end, Some(end) if iter.key().key_ref() >= end) {
+                break;
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(cf, output_level));
+            }
+            read_bytes += iter.key().raw_len() + iter.value().len();
+            if read_bytes >= cf.options.block_size {
+                self.rate_limit(read_bytes, IoPriority::Low, IoOp::Read);
+                read_bytes = 0;
             }
+            // The value written instead of the current one, as changed by a filter.
+            let mut new_value = None::<Bytes>;
 
             let same_as_last_key = iter.key().key_ref() == last_key;
             if !same_as_last_key {
@@ -153,36 +386,106 @@ impl LsmStorageInner {
 
                 first_key_below_watermark = false;
 
-                if !compaction_filters.is_empty() {
+                if covering_tombstones.covers(iter.key().key_ref(), iter.key().ts()) {
+                    iter.next()?;
+                    continue;
+                }
+
+                if matches!(&skip_until, Some(until) if iter.key().key_ref() >= until.as_ref()) {
+                    skip_until = None;
+                }
+
+                // Tombstones are not passed to the filters, and do not expire. They are kept even
+                // in a skipped range, to hide the older versions in lower levels.
+                if skip_until.is_some() && !iter.value().is_empty() {
+                    // Removed like by `Remove`, without reading the value.
+                    if compact_to_bottom_level {
+                        last_key.clear();
+                        last_key.extend(iter.key().key_ref());
+                        iter.next()?;
+                        continue;
+                    }
+                    new_value = Some(Bytes::new());
+                } else if (!compaction_filters.is_empty() || ttl_enabled)
+                    && !iter.value().is_empty()
+                {
+                    let pointed;
+                    let value = if iter.is_value_pointer() {
+                        pointed = self.value_log.read(&ValuePointer::decode(iter.value())?)?;
+                        &pointed[..]
+                    } else {
+                        iter.value()
+                    };
+                    let (value, expiry) = if ttl_enabled {
+                        ttl::decode_value(value)
+                    } else {
+                        (value, ttl::NO_EXPIRY)
+                    };
+                    let key = iter.key();
+                    // Expired values are removed without being passed to the filters.
+                    let mut remove = expiry <= now;
                     for filter in &compaction_filters {
-                        match filter {
-                            CompactionFilter::Prefix(x) => {
-                                if iter.key().key_ref().starts_with(x) {
-                                    iter.next()?;
-                                    continue 'outer;
+                        if remove {
+                            break;
+                        }
+                        let value = new_value.as_deref().unwrap_or(value);
+                        match filter.filter(output_level, key.key_ref(), key.ts(), value) {
+                            CompactionDecision::Keep => {}
+                            CompactionDecision::ChangeValue(value) => new_value = Some(value),
+                            CompactionDecision::Remove => {
+                                remove = true;
+                                break;
+                            }
+                            CompactionDecision::RemoveAndSkipUntil(until) => {
+                                if until.as_ref() > key.key_ref() {
+                                    skip_until = Some(until);
                                 }
+                                remove = true;
+                                break;
                             }
                         }
                     }
+                    if remove && compact_to_bottom_level {
+                        last_key.clear();
+                        last_key.extend(iter.key().key_ref());
+                        iter.next()?;
+                        continue;
+                    }
+                    if remove {
+                        new_value = Some(Bytes::new());
+                    } else if ttl_enabled {
+                        if let Some(value) = &new_value {
+                            new_value = Some(ttl::encode_value(value, expiry));
+                        }
+                    }
                 }
             }
 
             let builder_inner = builder.as_mut().unwrap();
 
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
+            if builder_inner.estimated_size() >= cf.options.target_sst_size && !same_as_last_key {
                 let sst_id = self.next_sst_id();
                 let old_builder = builder.take().unwrap();
-                let sst = Arc::new(old_builder.build(
-                    sst_id,
-                    Some(self.block_cache.clone()),
-                    self.path_of_sst(sst_id),
-                )?);
+                let sst = self.build_sst(old_builder, sst_id, IoPriority::Low)?;
+                self.statistics
+                    .record_compaction(output_level, sst.table_size());
                 new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(cf, output_level));
             }
 
             let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            if let Some(value) = new_value.take() {
+                builder_inner.add(iter.key(), &value);
+            } else if iter.is_value_pointer() {
+                let pointer = ValuePointer::decode(iter.value())?;
+                if self.value_log.is_relocating(pointer.file_id) {
+                    builder_inner.add(iter.key(), &self.value_log.read(&pointer)?);
+                } else {
+                    builder_inner.add_value_pointer(iter.key(), iter.value());
+                }
+            } else {
+                builder_inner.add(iter.key(), iter.value());
+            }
 
             if !same_as_last_key {
                 last_key.clear();
@@ -191,43 +494,102 @@ impl LsmStorageInner {
 
             iter.next()?;
         }
+        self.rate_limit(read_bytes, IoPriority::Low, IoOp::Read);
+        for (tombstone, isolated) in range_tombstones.input {
+            if compact_to_bottom_level && tombstone.ts <= watermark && isolated {
+                continue;
+            }
+            if builder.is_none() {
+                builder = Some(self.new_sst_builder(cf, output_level));
+            }
+            builder.as_mut().unwrap().add_range_tombstone(tombstone);
+        }
         if let Some(builder) = builder {
             let sst_id = self.next_sst_id(); // lock dropped here
-            let sst = Arc::new(builder.build(
-                sst_id,
-                Some(self.block_cache.clone()),
-                self.path_of_sst(sst_id),
-            )?);
+            let sst = self.build_sst(builder, sst_id, IoPriority::Low)?;
+            self.statistics
+                .record_compaction(output_level, sst.table_size());
             new_sst.push(sst);
         }
         Ok(new_sst)
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+    fn compact(&self, cf: &ColumnFamily, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
         let snapshot = {
-            let state = self.state.read();
+            let state = cf.state.read();
             state.clone()
         };
+        let range_tombstones = CompactionRangeTombstones::new(&snapshot, task);
+        let boundaries = subcompaction_boundaries(&snapshot, task, self.options.max_subcompactions);
+        if boundaries.is_empty() {
+            return self.compact_range(cf, &snapshot, task, range_tombstones, None, None);
+        }
+        log::debug!(
+            "splitting compaction into {} subcompactions",
+            boundaries.len() + 1
+        );
+        // Each subcompaction compacts the keys from its boundary up to the next one, and their
+        // outputs are concatenated in key order.
+        let starts = std::iter::once(None).chain(boundaries.iter().map(|key| Some(key.as_slice())));
+        let ends = boundaries
+            .iter()
+            .map(|key| Some(key.as_slice()))
+            .chain(std::iter::once(None));
+        std::thread::scope(|scope| {
+            let handles = starts
+                .zip(ends)
+                .map(|(start, end)| {
+                    let range_tombstones = range_tombstones.clip(start, end);
+                    let snapshot = &snapshot;
+                    scope.spawn(move || {
+                        self.compact_range(cf, snapshot, task, range_tombstones, start, end)
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut output = Vec::new();
+            for handle in handles {
+                output.extend(handle.join().unwrap()?);
+            }
+            Ok(output)
+        })
+    }
+
+    /// Compacts the keys of the task that are at least `start` and less than `end`.
+    fn compact_range(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        range_tombstones: CompactionRangeTombstones,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let ssts = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>()
+        };
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
                 l1_sstables,
             } => {
                 let mut l0_iters = Vec::with_capacity(l0_sstables.len());
-                for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
-                }
-                let mut l1_iters = Vec::with_capacity(l1_sstables.len());
-                for id in l1_sstables.iter() {
-                    l1_iters.push(snapshot.sstables.get(id).unwrap().clone());
+                for sst in ssts(l0_sstables) {
+                    l0_iters.push(sst_iter(sst, start)?);
                 }
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    concat_iter(ssts(l1_sstables), start)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    cf,
+                    iter,
+                    task.compact_to_bottom_level(),
+                    task.output_level(),
+                    range_tombstones,
+                    end,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -244,64 +606,65 @@ impl LsmStorageInner {
                 ..
             }) => match upper_level {
                 Some(_) => {
-                    let mut upper_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let upper_iter = concat_iter(ssts(upper_level_sst_ids), start)?;
+                    let lower_iter = concat_iter(ssts(lower_level_sst_ids), start)?;
                     self.compact_generate_sst_from_iter(
+                        cf,
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
                         task.compact_to_bottom_level(),
+                        task.output_level(),
+                        range_tombstones,
+                        end,
                     )
                 }
                 None => {
                     let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
+                    for sst in ssts(upper_level_sst_ids) {
+                        upper_iters.push(sst_iter(sst, start)?);
                     }
                     let upper_iter = MergeIterator::create(upper_iters);
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = concat_iter(ssts(lower_level_sst_ids), start)?;
                     self.compact_generate_sst_from_iter(
+                        cf,
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
                         task.compact_to_bottom_level(),
+                        task.output_level(),
+                        range_tombstones,
+                        end,
                     )
                 }
             },
             CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
                 let mut iters = Vec::with_capacity(tiers.len());
                 for (_, tier_sst_ids) in tiers {
-                    let mut ssts = Vec::with_capacity(tier_sst_ids.len());
-                    for id in tier_sst_ids.iter() {
-                        ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+                    iters.push(Box::new(concat_iter(ssts(tier_sst_ids), start)?));
                 }
                 self.compact_generate_sst_from_iter(
+                    cf,
                     MergeIterator::create(iters),
                     task.compact_to_bottom_level(),
+                    task.output_level(),
+                    range_tombstones,
+                    end,
                 )
             }
         }
     }
 
+    /// Compacts the L0 and L1 SSTs of the default column family into new L1 SSTs.
     pub fn force_full_compaction(&self) -> Result<()> {
-        let CompactionOptions::NoCompaction = self.options.compaction_options else {
+        self.force_full_compaction_cf(self.default_cf())
+    }
+
+    /// Compacts the L0 and L1 SSTs of a column family into new L1 SSTs.
+    pub fn force_full_compaction_cf(&self, cf: &ColumnFamily) -> Result<()> {
+        let CompactionOptions::NoCompaction = cf.options.compaction_options else {
             panic!("full compaction can only be called with compaction is not enabled")
         };
+        self.background_error.check_not_read_only()?;
 
         let snapshot = {
-            let state = self.state.read();
+            let state = cf.state.read();
             state.clone()
         };
 
@@ -312,14 +675,24 @@ impl LsmStorageInner {
             l1_sstables: l1_sstables.clone(),
         };
 
-        println!("force full compaction: {:?}", compaction_task);
+        let Some(reservation) = self
+            .running_compactions
+            .try_start(cf, &compaction_task, &snapshot)
+        else {
+            bail!("a compaction of column family {} is running", cf.name());
+        };
+
+        log::info!("force full compaction: {:?}", compaction_task);
+        let mut info = compaction_job_info(cf, &compaction_task, &snapshot);
+        self.notify(|listener| listener.on_compaction_begin(&info));
 
-        let sstables = self.compact(&compaction_task)?;
+        let sstables = self.compact(cf, &compaction_task)?;
         let mut ids = Vec::with_capacity(sstables.len());
+        info.output_bytes = sstables.iter().map(|sst| sst.table_size()).sum();
 
         {
             let state_lock = self.state_lock.lock();
-            let mut state = self.state.read().as_ref().clone();
+            let mut state = cf.state.read().as_ref().clone();
             for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
                 let result = state.sstables.remove(sst);
                 assert!(result.is_some());
@@ -339,47 +712,61 @@ impl LsmStorageInner {
                 .copied()
                 .collect::<Vec<_>>();
             assert!(l0_sstables_map.is_empty());
-            *self.state.write() = Arc::new(state);
+            *cf.state.write() = Arc::new(state);
+            self.write_controller.update(&self.column_families);
             self.sync_dir()?;
-            self.manifest.as_ref().unwrap().add_record(
+            self.add_manifest_record(
                 &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
+                ManifestRecord::compaction(cf.id(), compaction_task, ids.clone()),
             )?;
         }
+        info.output_sst_ids = ids.clone();
+        self.notify(|listener| listener.on_compaction_completed(&info));
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-            std::fs::remove_file(self.path_of_sst(*sst))?;
+            self.delete_sst(cf, *sst)?;
         }
 
-        println!("force full compaction done, new SSTs: {:?}", ids);
+        log::info!("force full compaction done, new SSTs: {:?}", ids);
+        drop(reservation);
+        self.gc_value_log_if_idle()?;
 
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
+    /// Runs the next compaction task of a column family, if any, and returns whether one ran.
+    pub(crate) fn trigger_compaction(&self, cf: &ColumnFamily) -> Result<bool> {
         let snapshot = {
-            let state = self.state.read();
+            let state = cf.state.read();
             state.clone()
         };
-        let task = self
-            .compaction_controller
-            .generate_compaction_task(&snapshot);
+        let Some(pending) = self.running_compactions.pending_state(cf, &snapshot) else {
+            return Ok(false);
+        };
+        let task = cf.compaction_controller.generate_compaction_task(&pending);
         let Some(task) = task else {
-            return Ok(());
+            return Ok(false);
         };
-        self.dump_structure();
-        println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+        // Another thread may have started a conflicting task since the snapshot was taken.
+        let Some(reservation) = self.running_compactions.try_start(cf, &task, &snapshot) else {
+            return Ok(false);
+        };
+        log::info!("running compaction task: {:?}", task);
+        let mut info = compaction_job_info(cf, &task, &snapshot);
+        self.notify(|listener| listener.on_compaction_begin(&info));
+        let sstables = self.compact(cf, &task)?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
+        info.output_sst_ids = output.clone();
+        info.output_bytes = sstables.iter().map(|sst| sst.table_size()).sum();
         let ssts_to_remove = {
             let state_lock = self.state_lock.lock();
-            let mut snapshot = self.state.read().as_ref().clone();
+            let mut snapshot = cf.state.read().as_ref().clone();
             let mut new_sst_ids = Vec::new();
             for file_to_add in sstables {
                 new_sst_ids.push(file_to_add.sst_id());
                 let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
                 assert!(result.is_none());
             }
-            let (mut snapshot, files_to_remove) = self
+            let (mut snapshot, files_to_remove) = cf
                 .compaction_controller
                 .apply_compaction_result(&snapshot, &task, &output);
             let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
@@ -388,45 +775,110 @@ impl LsmStorageInner {
                 assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
                 ssts_to_remove.push(result.unwrap());
             }
-            let mut state = self.state.write();
+            let mut state = cf.state.write();
             *state = Arc::new(snapshot);
             drop(state);
+            self.write_controller.update(&self.column_families);
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+            self.add_manifest_record(
+                &state_lock,
+                ManifestRecord::compaction(cf.id(), task, new_sst_ids),
+            )?;
             ssts_to_remove
         };
-        println!(
+        log::info!(
             "compaction finished: {} files removed, {} files added, output={:?}",
             ssts_to_remove.len(),
             output.len(),
             output
         );
+        self.notify(|listener| listener.on_compaction_completed(&info));
         for sst in ssts_to_remove {
-            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+            self.delete_sst(cf, sst.sst_id())?;
         }
         self.sync_dir()?;
+        drop(reservation);
+        self.gc_value_log_if_idle()?;
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Garbage-collects the value log, unless other compactions are running. The last of them to
+    /// finish does it instead.
+    fn gc_value_log_if_idle(&self) -> Result<()> {
+        self.running_compactions.run_if_idle(|| {
+            self.gc_value_log()?;
+            Ok(())
+        })
+    }
+
+    /// Runs the next compaction task of each column family, until a background error stops
+    /// compactions. A compaction error is cleared once a task of the column family that failed
+    /// runs to completion.
+    pub(crate) fn compact_column_families(&self, column_families: &[Arc<ColumnFamily>]) {
+        for cf in column_families {
+            if !self.background_error.may_run() {
+                break;
+            }
+            match self.trigger_compaction(cf) {
+                Ok(true) => self
+                    .background_error
+                    .clear(BackgroundErrorReason::Compaction, Some(cf.name())),
+                Ok(false) => {}
+                Err(e) => self.report_background_error(
+                    BackgroundErrorReason::Compaction,
+                    Some(cf.name()),
+                    &e.context(format!("compaction of {} failed", cf.name())),
+                ),
+            }
+        }
     }
 
     pub(crate) fn spawn_compaction_thread(
         self: &Arc<Self>,
         rx: crossbeam_channel::Receiver<()>,
     ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        if let CompactionOptions::Leveled(_)
-        | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
-        {
+        // Column families without compaction are left alone.
+        let column_families = self
+            .column_families
+            .iter()
+            .filter(|cf| {
+                matches!(
+                    cf.options.compaction_options,
+                    CompactionOptions::Leveled(_)
+                        | CompactionOptions::Simple(_)
+                        | CompactionOptions::Tiered(_)
+                )
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if !column_families.is_empty() {
             let this = self.clone();
             let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+                // Each worker runs one task at a time. Workers pick tasks that do not conflict
+                // with the running ones, and stop once `stop_tx` is dropped.
+                let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+                let workers = (0..this.options.max_background_compactions.max(1))
+                    .map(|_| {
+                        let this = this.clone();
+                        let column_families = column_families.clone();
+                        let stop_rx = stop_rx.clone();
+                        std::thread::spawn(move || {
+                            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                            loop {
+                                crossbeam_channel::select! {
+                                    recv(ticker) -> _ => this.compact_column_families(&column_families),
+                                    recv(stop_rx) -> _ => return
+                                }
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                rx.recv().ok();
+                drop(stop_tx);
+                for worker in workers {
+                    if let Err(e) = worker.join() {
+                        std::panic::resume_unwind(e);
                     }
                 }
             });
@@ -435,13 +887,15 @@ impl LsmStorageInner {
         Ok(None)
     }
 
-    fn trigger_flush(&self) -> Result<()> {
-        let res = {
-            let state = self.state.read();
-            state.imm_memtables.len() >= self.options.num_memtable_limit
-        };
-        if res {
-            self.force_flush_next_imm_memtable()?;
+    pub(crate) fn trigger_flush(&self) -> Result<()> {
+        for cf in &self.column_families {
+            let res = {
+                let state = cf.state.read();
+                state.imm_memtables.len() >= cf.options.num_memtable_limit
+            };
+            if res {
+                self.flush_next_imm_memtable(cf)?;
+            }
         }
 
         Ok(())
@@ -456,8 +910,11 @@ impl LsmStorageInner {
             let ticker = crossbeam_channel::tick(Duration::from_millis(50));
             loop {
                 crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("flush failed: {}", e);
+                    recv(ticker) -> _ => if this.background_error.may_run() {
+                        match this.trigger_flush() {
+                            Ok(()) => this.background_error.clear(BackgroundErrorReason::Flush, None),
+                            Err(e) => this.report_background_error(BackgroundErrorReason::Flush, None, &e),
+                        }
                     },
                     recv(rx) -> _ => return
                 }
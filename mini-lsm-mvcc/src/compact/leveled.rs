@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -61,10 +61,9 @@ impl LeveledCompactionController {
         overlap_ssts
     }
 
-    pub fn generate_compaction_task(
-        &self,
-        snapshot: &LsmStorageState,
-    ) -> Option<LeveledCompactionTask> {
+    /// Computes the real and the target size of each level, and the base level L0 is compacted
+    /// into.
+    fn level_sizes(&self, snapshot: &LsmStorageState) -> (Vec<usize>, Vec<usize>, usize) {
         // step 1: compute target level size
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
         let mut real_level_size = Vec::with_capacity(self.options.max_levels);
@@ -93,10 +92,18 @@ impl LeveledCompactionController {
                 base_level = i + 1;
             }
         }
+        (real_level_size, target_level_size, base_level)
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        let (real_level_size, target_level_size, base_level) = self.level_sizes(snapshot);
 
         // Flush L0 SST is the top priority
         if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
-            println!("flush L0 SST to base level {}", base_level);
+            log::info!("flush L0 SST to base level {}", base_level);
             return Some(LeveledCompactionTask {
                 upper_level: None,
                 upper_level_sst_ids: snapshot.l0_sstables.clone(),
@@ -121,7 +128,7 @@ impl LeveledCompactionController {
 
         let priority = priorities.first();
         if let Some((_, level)) = priority {
-            println!(
+            log::info!(
                 "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
                 target_level_size
                     .iter()
@@ -136,7 +143,7 @@ impl LeveledCompactionController {
 
             let level = *level;
             let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
-            println!(
+            log::info!(
                 "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
                 priorities
             );
@@ -155,6 +162,24 @@ impl LeveledCompactionController {
         None
     }
 
+    /// Estimates the bytes that need to be compacted to bring L0 under the trigger and each level
+    /// above the bottom back to its target size.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let (real_level_size, target_level_size, _) = self.level_sizes(snapshot);
+        let mut pending = 0;
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            pending += snapshot
+                .l0_sstables
+                .iter()
+                .map(|id| snapshot.sstables[id].table_size())
+                .sum::<u64>();
+        }
+        for level in 0..self.options.max_levels - 1 {
+            pending += real_level_size[level].saturating_sub(target_level_size[level]) as u64;
+        }
+        pending
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -216,14 +241,21 @@ impl LeveledCompactionController {
             .collect::<Vec<_>>();
         assert!(lower_level_sst_ids_set.is_empty());
         new_lower_level_ssts.extend(output);
-        new_lower_level_ssts.sort_by(|x, y| {
-            snapshot
-                .sstables
-                .get(x)
-                .unwrap()
-                .first_key()
-                .cmp(snapshot.sstables.get(y).unwrap().first_key())
-        });
+        // The SSTs are not loaded yet while recovering from the manifest. The levels are sorted
+        // once they are.
+        if new_lower_level_ssts
+            .iter()
+            .all(|x| snapshot.sstables.contains_key(x))
+        {
+            new_lower_level_ssts.sort_by(|x, y| {
+                snapshot
+                    .sstables
+                    .get(x)
+                    .unwrap()
+                    .first_key()
+                    .cmp(snapshot.sstables.get(y).unwrap().first_key())
+            });
+        }
         snapshot.levels[task.lower_level - 1].1 = new_lower_level_ssts;
         (snapshot, files_to_remove)
     }
@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::column_family::ColumnFamily;
+use crate::lsm_storage::LsmStorageState;
+
+use super::CompactionTask;
+
+/// A compaction that is running.
+struct RunningCompaction {
+    cf: u32,
+    /// Whether the column family uses leveled compaction. Other strategies rewrite whole levels or
+    /// tiers, so only one task of such a column family runs at a time.
+    leveled: bool,
+    inputs: HashSet<usize>,
+    reads_l0: bool,
+    /// The level above the output level that a leveled task reads, `None` for L0.
+    upper_level: Option<usize>,
+    output_level: usize,
+    /// The smallest and the largest user key of the inputs.
+    first_key: Vec<u8>,
+    last_key: Vec<u8>,
+}
+
+impl RunningCompaction {
+    fn conflicts_with(&self, other: &RunningCompaction) -> bool {
+        if self.cf != other.cf {
+            return false;
+        }
+        if !self.leveled || !other.leveled {
+            return true;
+        }
+        // L0 SSTs overlap each other and must be compacted from the oldest, so only one task may
+        // read L0. Two tasks writing overlapping keys to the same level would make its SSTs overlap.
+        // So would a task writing keys to a level while another task still holds the SSTs of that
+        // level that overlap them, as new tasks are planned without the SSTs being compacted.
+        let overlaps = self.first_key <= other.last_key && other.first_key <= self.last_key;
+        !self.inputs.is_disjoint(&other.inputs)
+            || (self.reads_l0 && other.reads_l0)
+            || (overlaps
+                && (self.output_level == other.output_level
+                    || Some(self.output_level) == other.upper_level
+                    || Some(other.output_level) == self.upper_level))
+    }
+}
+
+/// Keeps track of the compactions that are running, so that only tasks that do not conflict with
+/// any of them are started.
+#[derive(Default)]
+pub(crate) struct RunningCompactions {
+    running: Mutex<Vec<Arc<RunningCompaction>>>,
+}
+
+/// Marks a task as running until dropped.
+pub(crate) struct CompactionReservation<'a> {
+    running: &'a RunningCompactions,
+    compaction: Arc<RunningCompaction>,
+}
+
+impl Drop for CompactionReservation<'_> {
+    fn drop(&mut self) {
+        self.running
+            .running
+            .lock()
+            .retain(|compaction| !Arc::ptr_eq(compaction, &self.compaction));
+    }
+}
+
+impl RunningCompactions {
+    /// The state to generate a new task of a column family from. The SSTs that are being compacted
+    /// are left out, so that the controller picks other SSTs. Returns `None` if no other task of the
+    /// column family may run now.
+    pub(crate) fn pending_state(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &LsmStorageState,
+    ) -> Option<LsmStorageState> {
+        let running = self.running.lock();
+        let mut compacting = HashSet::new();
+        for compaction in running.iter().filter(|compaction| compaction.cf == cf.id()) {
+            if !compaction.leveled {
+                return None;
+            }
+            compacting.extend(compaction.inputs.iter().copied());
+        }
+        let mut snapshot = snapshot.clone();
+        if !compacting.is_empty() {
+            snapshot.l0_sstables.retain(|id| !compacting.contains(id));
+            for (_, level) in &mut snapshot.levels {
+                level.retain(|id| !compacting.contains(id));
+            }
+        }
+        Some(snapshot)
+    }
+
+    /// Marks a task as running, unless it conflicts with a running task. All its input SSTs must be
+    /// in `snapshot`.
+    pub(crate) fn try_start(
+        &self,
+        cf: &ColumnFamily,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+    ) -> Option<CompactionReservation<'_>> {
+        let inputs = task.input_sst_ids();
+        let first_key = inputs
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key().key_ref())
+            .min()
+            .unwrap_or_default()
+            .to_vec();
+        let last_key = inputs
+            .iter()
+            .map(|id| snapshot.sstables[id].last_key().key_ref())
+            .max()
+            .unwrap_or_default()
+            .to_vec();
+        let compaction = Arc::new(RunningCompaction {
+            cf: cf.id(),
+            leveled: matches!(task, CompactionTask::Leveled(_)),
+            inputs: inputs.into_iter().collect(),
+            reads_l0: task.reads_l0(),
+            upper_level: match task {
+                CompactionTask::Leveled(task) => task.upper_level,
+                _ => None,
+            },
+            output_level: task.output_level(),
+            first_key,
+            last_key,
+        });
+        let mut running = self.running.lock();
+        if running.iter().any(|other| compaction.conflicts_with(other)) {
+            return None;
+        }
+        running.push(compaction.clone());
+        Some(CompactionReservation {
+            running: self,
+            compaction,
+        })
+    }
+
+    /// Runs `f` if no compaction is running, and keeps new compactions from starting until it
+    /// returns.
+    pub(crate) fn run_if_idle(&self, f: impl FnOnce() -> Result<()>) -> Result<()> {
+        let running = self.running.lock();
+        if running.is_empty() {
+            f()?;
+        }
+        Ok(())
+    }
+}
@@ -11,7 +11,7 @@ pub struct SimpleLeveledCompactionOptions {
     pub max_levels: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -53,9 +53,11 @@ impl SimpleLeveledCompactionController {
             let lower_level = i + 1;
             let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
             if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
-                println!(
+                log::info!(
                     "compaction triggered at level {} and {} with size ratio {}",
-                    i, lower_level, size_ratio
+                    i,
+                    lower_level,
+                    size_ratio
                 );
                 return Some(SimpleLeveledCompactionTask {
                     upper_level: if i == 0 { None } else { Some(i) },
@@ -73,6 +75,30 @@ impl SimpleLeveledCompactionController {
         None
     }
 
+    /// Estimates the bytes that need to be compacted: the size of every level that is due to be
+    /// compacted into the next one.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let mut pending = 0;
+        for i in 0..self.options.max_levels {
+            let upper_level = if i == 0 {
+                &snapshot.l0_sstables
+            } else {
+                &snapshot.levels[i - 1].1
+            };
+            if i == 0 && upper_level.len() < self.options.level0_file_num_compaction_trigger {
+                continue;
+            }
+            let size_ratio = snapshot.levels[i].1.len() as f64 / upper_level.len() as f64;
+            if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
+                pending += upper_level
+                    .iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum::<u64>();
+            }
+        }
+        pending
+    }
+
     /// Apply the compaction result.
     ///
     /// The compactor will call this function with the compaction task and the list of SST ids generated. This function applies the
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
@@ -46,7 +46,7 @@ impl TieredCompactionController {
         let space_amp_ratio =
             (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
-            println!(
+            log::info!(
                 "compaction triggered by space amplification ratio: {}",
                 space_amp_ratio
             );
@@ -63,7 +63,7 @@ impl TieredCompactionController {
             let next_level_size = snapshot.levels[id + 1].1.len();
             let current_size_ratio = size as f64 / next_level_size as f64;
             if current_size_ratio >= size_ratio_trigger && id + 2 >= self.options.min_merge_width {
-                println!(
+                log::info!(
                     "compaction triggered by size ratio: {}",
                     current_size_ratio * 100.0
                 );
@@ -80,7 +80,7 @@ impl TieredCompactionController {
         }
         // trying to reduce sorted runs without respecting size ratio
         let num_tiers_to_take = snapshot.levels.len() - self.options.num_tiers + 2;
-        println!("compaction triggered by reducing sorted runs");
+        log::info!("compaction triggered by reducing sorted runs");
         return Some(TieredCompactionTask {
             tiers: snapshot
                 .levels
@@ -92,6 +92,19 @@ impl TieredCompactionController {
         });
     }
 
+    /// Estimates the bytes that need to be compacted: the size of all tiers above the bottom one,
+    /// once there are enough tiers to consider a compaction.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        if snapshot.levels.len() < self.options.num_tiers {
+            return 0;
+        }
+        snapshot.levels[..snapshot.levels.len() - 1]
+            .iter()
+            .flat_map(|(_, ssts)| ssts)
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum()
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -0,0 +1,54 @@
+//! Filters that drop or rewrite entries while they are compacted, e.g. to expire data, migrate
+//! values to a new schema, or purge the keys of a tenant without scanning the whole DB.
+
+use std::fmt::Debug;
+
+use bytes::Bytes;
+
+/// What a compaction does with an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    /// Drops the key. Unless the compaction writes to the bottom level, a delete tombstone is
+    /// written instead, so that older versions in lower levels stay hidden.
+    Remove,
+    /// Replaces the value of the entry.
+    ChangeValue(Bytes),
+    /// Drops the key and all later keys before the given key like `Remove`, without passing them
+    /// to the filter. A key that is not after the current key is the same as `Remove`.
+    RemoveAndSkipUntil(Bytes),
+}
+
+/// Decides what happens to each entry of a compaction. The filter only sees the latest version of
+/// each key at or below the MVCC watermark, as the older versions are dropped and the newer ones
+/// may still be read by a snapshot. It never sees delete tombstones or expired values, and the
+/// values it sees do not include their expiry time, which is kept when a value is changed.
+pub trait CompactionFilter: Debug + Send + Sync {
+    /// `level` is the level the compaction writes to, which is `usize::MAX` for tiered compactions
+    /// that include the bottom tier.
+    fn filter(&self, level: usize, key: &[u8], ts: u64, value: &[u8]) -> CompactionDecision;
+}
+
+/// Removes all keys that start with a prefix.
+#[derive(Debug, Clone)]
+pub struct PrefixCompactionFilter {
+    prefix: Bytes,
+}
+
+impl PrefixCompactionFilter {
+    pub fn new(prefix: impl Into<Bytes>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl CompactionFilter for PrefixCompactionFilter {
+    fn filter(&self, _level: usize, key: &[u8], _ts: u64, _value: &[u8]) -> CompactionDecision {
+        if key.starts_with(&self.prefix) {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Callbacks on the background work of the engine. Listeners are registered through
+//! `LsmStorageOptions::event_listeners`, and are called from the thread that did the work, after
+//! the change is visible to readers.
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use crate::compact::CompactionTask;
+use crate::write_stall::WriteStall;
+
+#[derive(Debug, Clone)]
+pub struct FlushJobInfo {
+    pub column_family: String,
+    /// The id of the flushed memtable, which is also the id of the new L0 SST.
+    pub sst_id: usize,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompactionJobInfo {
+    pub column_family: String,
+    pub task: CompactionTask,
+    pub input_sst_ids: Vec<usize>,
+    /// The total size of the input SSTs.
+    pub input_bytes: u64,
+    /// The SSTs written by the compaction, which are empty when it begins.
+    pub output_sst_ids: Vec<usize>,
+    pub output_bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableFileDeletionInfo {
+    pub column_family: String,
+    pub sst_id: usize,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteStallInfo {
+    pub previous: Option<WriteStall>,
+    /// `None` once writes are no longer stalled.
+    pub current: Option<WriteStall>,
+}
+
+/// The work that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundErrorReason {
+    Flush,
+    Compaction,
+    /// A manifest write of a flush, a compaction or a memtable switch.
+    ManifestWrite,
+}
+
+/// Receives the events of a DB. All callbacks do nothing by default. They may be called from
+/// several threads at once, and must not block for long, as they hold up the background work.
+pub trait EventListener: Debug + Send + Sync {
+    /// Called once a memtable was flushed to a new L0 SST.
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// Called before a compaction reads its inputs.
+    fn on_compaction_begin(&self, _info: &CompactionJobInfo) {}
+
+    /// Called once the outputs of a compaction replaced its inputs, before the input files are
+    /// deleted.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    /// Called once an SST that is no longer used was deleted.
+    fn on_table_file_deleted(&self, _info: &TableFileDeletionInfo) {}
+
+    /// Called whenever writes start or stop being delayed or blocked, or the reason changes.
+    fn on_stall_conditions_changed(&self, _info: &WriteStallInfo) {}
+
+    /// Called when a flush or a compaction in a background thread, or a manifest write, fails.
+    /// The error is also returned by `MiniLsm::background_error` until it is cleared.
+    fn on_background_error(&self, _reason: BackgroundErrorReason, _error: &anyhow::Error) {}
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+use crate::lsm_storage::WriteBatchRecord;
+
+/// A write batch waiting in the write queue.
+pub(crate) struct PendingWrite {
+    /// The records and the id of the column family each of them is written to.
+    pub(crate) batch: Vec<(u32, WriteBatchRecord<Bytes>)>,
+    /// Whether the WAL must be synced before the write is acknowledged.
+    pub(crate) sync: bool,
+    /// The commit timestamp of the batch, set once it was committed by a leader.
+    result: Mutex<Option<Result<u64>>>,
+    cv: Condvar,
+}
+
+impl PendingWrite {
+    pub(crate) fn new(batch: Vec<(u32, WriteBatchRecord<Bytes>)>, sync: bool) -> Arc<Self> {
+        Arc::new(Self {
+            batch,
+            sync,
+            result: Mutex::new(None),
+            cv: Condvar::new(),
+        })
+    }
+}
+
+/// The queue of concurrent write batches, which are committed in groups.
+///
+/// The writer at the front of the queue becomes the leader. It takes all batches queued behind
+/// it, commits them with a single WAL append (and a single sync, if any of them asks for one),
+/// and then wakes up the writers of the group with their results. Writers that arrive while a
+/// group is being committed queue up to form the next group.
+#[derive(Default)]
+pub(crate) struct WriteQueue {
+    queue: Mutex<VecDeque<Arc<PendingWrite>>>,
+}
+
+impl WriteQueue {
+    /// Enqueues a write and waits until it is committed. `commit` is called by the leader with
+    /// the writes of a group in queue order, and returns the commit timestamp of each of them.
+    pub(crate) fn write(
+        &self,
+        write: Arc<PendingWrite>,
+        commit: impl FnOnce(&[Arc<PendingWrite>]) -> Result<Vec<u64>>,
+    ) -> Result<u64> {
+        let mut queue = self.queue.lock();
+        queue.push_back(write.clone());
+        loop {
+            if let Some(result) = write.result.lock().take() {
+                return result;
+            }
+            if Arc::ptr_eq(&queue[0], &write) {
+                break;
+            }
+            write.cv.wait(&mut queue);
+        }
+
+        // This writer is the leader. The queue is unlocked while committing, so that more
+        // writers can queue up behind the group.
+        let group = CommittingGroup {
+            queue: &self.queue,
+            writes: queue.iter().cloned().collect(),
+        };
+        drop(queue);
+        match commit(&group.writes) {
+            Ok(timestamps) => {
+                for (follower, ts) in group.writes.iter().zip(&timestamps).skip(1) {
+                    *follower.result.lock() = Some(Ok(*ts));
+                }
+                Ok(timestamps[0])
+            }
+            Err(e) => {
+                for follower in group.writes.iter().skip(1) {
+                    *follower.result.lock() = Some(Err(anyhow!("{:#}", e)));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The group a leader is committing. Dropping it removes the group from the queue and wakes up
+/// its followers and the next leader, also if the leader panicked while committing, in which case
+/// the followers fail instead of waiting forever.
+struct CommittingGroup<'a> {
+    queue: &'a Mutex<VecDeque<Arc<PendingWrite>>>,
+    writes: Vec<Arc<PendingWrite>>,
+}
+
+impl Drop for CommittingGroup<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock();
+        queue.drain(..self.writes.len());
+        for follower in self.writes.iter().skip(1) {
+            follower
+                .result
+                .lock()
+                .get_or_insert_with(|| Err(anyhow!("the leader of the write group panicked")));
+            follower.cv.notify_one();
+        }
+        if let Some(next) = queue.front() {
+            next.cv.notify_one();
+        }
+    }
+}
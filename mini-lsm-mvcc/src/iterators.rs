@@ -19,8 +19,46 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Whether the current value is an encoded pointer into the value log rather than the value
+    /// itself. Only iterators that read raw SST entries return pointers.
+    fn is_value_pointer(&self) -> bool {
+        false
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
     }
 }
+
+/// A storage iterator that can also move backward. Iterators can change the direction at any
+/// valid position.
+pub trait DoubleEndedStorageIterator: StorageIterator {
+    /// Move to the first position.
+    fn seek_to_first(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the last position.
+    fn seek_to_last(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the previous position. The iterator becomes invalid when moving before the first
+    /// position.
+    fn prev(&mut self) -> anyhow::Result<()>;
+}
+
+/// A storage iterator that can be moved to a key in place, without creating the iterator again.
+pub trait SeekableStorageIterator: StorageIterator {
+    /// Move to the first position whose user key is >= `key`. For iterators over versioned keys,
+    /// this is the latest version of the key. The iterator moves forward afterwards.
+    fn seek(&mut self, key: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Move `iter` to the previous position. If there is none, park it at its first position so that
+/// the direction can later be changed with `next` alone, and return false.
+pub(crate) fn prev_or_park<I: DoubleEndedStorageIterator>(iter: &mut I) -> anyhow::Result<bool> {
+    iter.prev()?;
+    if iter.is_valid() {
+        return Ok(true);
+    }
+    iter.seek_to_first()?;
+    Ok(false)
+}
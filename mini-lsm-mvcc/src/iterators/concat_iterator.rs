@@ -3,11 +3,11 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use crate::{
-    key::KeySlice,
+    key::{KeySlice, TS_RANGE_BEGIN},
     table::{SsTable, SsTableIterator},
 };
 
-use super::StorageIterator;
+use super::{DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator};
 
 /// Concat multiple iterators ordered in key order and their key ranges do not overlap. We do not want to create the
 /// iterators when initializing this iterator to reduce the overhead of seeking.
@@ -15,6 +15,8 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    /// Whether the SST iterators resolve value pointers, see `SsTableIterator`.
+    resolve_values: bool,
 }
 
 impl SstConcatIterator {
@@ -29,50 +31,94 @@ impl SstConcatIterator {
         }
     }
 
-    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
-        Self::check_sst_valid(&sstables);
-        if sstables.is_empty() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: 0,
-                sstables,
-            });
+    fn create_table_iter(&self, idx: usize) -> Result<SsTableIterator> {
+        let table = self.sstables[idx].clone();
+        if self.resolve_values {
+            SsTableIterator::create_and_seek_to_first(table)
+        } else {
+            SsTableIterator::create_and_seek_to_first_raw(table)
+        }
+    }
+
+    fn create_table_iter_at_last(&self, idx: usize) -> Result<SsTableIterator> {
+        let table = self.sstables[idx].clone();
+        if self.resolve_values {
+            SsTableIterator::create_and_seek_to_last(table)
+        } else {
+            let mut iter = SsTableIterator::create_and_seek_to_first_raw(table)?;
+            iter.seek_to_last()?;
+            Ok(iter)
         }
+    }
+
+    fn create_table_iter_at_key(&self, idx: usize, key: KeySlice) -> Result<SsTableIterator> {
+        let table = self.sstables[idx].clone();
+        if self.resolve_values {
+            SsTableIterator::create_and_seek_to_key(table, key)
+        } else {
+            let mut iter = SsTableIterator::create_and_seek_to_first_raw(table)?;
+            iter.seek_to_key(key)?;
+            Ok(iter)
+        }
+    }
+
+    fn create_and_seek_to_first_inner(
+        sstables: Vec<Arc<SsTable>>,
+        resolve_values: bool,
+    ) -> Result<Self> {
+        Self::check_sst_valid(&sstables);
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_first(
-                sstables[0].clone(),
-            )?),
-            next_sst_idx: 1,
+            current: None,
+            next_sst_idx: 0,
             sstables,
+            resolve_values,
         };
+        if iter.sstables.is_empty() {
+            return Ok(iter);
+        }
+        iter.current = Some(iter.create_table_iter(0)?);
+        iter.next_sst_idx = 1;
         iter.move_until_valid()?;
         Ok(iter)
     }
 
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_and_seek_to_first_inner(sstables, true)
+    }
+
+    /// Like `create_and_seek_to_first`, but value pointers are returned as they are stored.
+    pub(crate) fn create_and_seek_to_first_raw(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_and_seek_to_first_inner(sstables, false)
+    }
+
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
         Self::check_sst_valid(&sstables);
-        let idx: usize = sstables
-            .partition_point(|table| table.first_key().as_key_slice() <= key)
-            .saturating_sub(1);
-        if idx >= sstables.len() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: sstables.len(),
-                sstables,
-            });
-        }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
-                key,
-            )?),
-            next_sst_idx: idx + 1,
+            current: None,
+            next_sst_idx: 0,
             sstables,
+            resolve_values: true,
         };
-        iter.move_until_valid()?;
+        iter.seek_to_key(key)?;
         Ok(iter)
     }
 
+    /// Seek to the first key-value pair which >= `key`.
+    pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        let idx: usize = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        if idx >= self.sstables.len() {
+            self.current = None;
+            self.next_sst_idx = self.sstables.len();
+            return Ok(());
+        }
+        self.current = Some(self.create_table_iter_at_key(idx, key)?);
+        self.next_sst_idx = idx + 1;
+        self.move_until_valid()
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
@@ -81,14 +127,29 @@ impl SstConcatIterator {
             if self.next_sst_idx >= self.sstables.len() {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
-                    self.sstables[self.next_sst_idx].clone(),
-                )?);
+                self.current = Some(self.create_table_iter(self.next_sst_idx)?);
                 self.next_sst_idx += 1;
             }
         }
         Ok(())
     }
+
+    /// Like `move_until_valid`, but moves to the previous SSTs.
+    fn move_back_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_mut() {
+            if iter.is_valid() {
+                break;
+            }
+            // `next_sst_idx - 1` is the index of the current SST.
+            if self.next_sst_idx <= 1 {
+                self.current = None;
+            } else {
+                self.next_sst_idx -= 1;
+                self.current = Some(self.create_table_iter_at_last(self.next_sst_idx - 1)?);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl StorageIterator for SstConcatIterator {
@@ -102,6 +163,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.current.as_ref().unwrap().is_value_pointer()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
@@ -121,3 +186,36 @@ impl StorageIterator for SstConcatIterator {
         1
     }
 }
+
+impl DoubleEndedStorageIterator for SstConcatIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.current = None;
+        if self.sstables.is_empty() {
+            return Ok(());
+        }
+        self.current = Some(self.create_table_iter(0)?);
+        self.next_sst_idx = 1;
+        self.move_until_valid()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.current = None;
+        if self.sstables.is_empty() {
+            return Ok(());
+        }
+        self.current = Some(self.create_table_iter_at_last(self.sstables.len() - 1)?);
+        self.next_sst_idx = self.sstables.len();
+        self.move_back_until_valid()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.current.as_mut().unwrap().prev()?;
+        self.move_back_until_valid()
+    }
+}
+
+impl SeekableStorageIterator for SstConcatIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.seek_to_key(KeySlice::from_slice(key, TS_RANGE_BEGIN))
+    }
+}
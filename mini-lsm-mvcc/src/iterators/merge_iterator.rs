@@ -6,9 +6,10 @@ use anyhow::Result;
 
 use crate::key::KeySlice;
 
-use super::StorageIterator;
+use super::{prev_or_park, DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator};
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator with its index, and whether the merge iterator is moving backward.
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub bool);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -21,12 +22,18 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     #[allow(clippy::non_canonical_partial_ord_impl)]
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(&other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
+        let ordering = match self.1.key().cmp(&other.1.key()) {
+            cmp::Ordering::Greater => cmp::Ordering::Greater,
+            cmp::Ordering::Less => cmp::Ordering::Less,
+            // Prefer the iterator with the smaller index in both directions.
+            cmp::Ordering::Equal => return self.0.partial_cmp(&other.0).map(|x| x.reverse()),
+        };
+        // The heap top is the smallest key when moving forward, and the largest one otherwise.
+        if self.2 {
+            Some(ordering)
+        } else {
+            Some(ordering.reverse())
         }
-        .map(|x| x.reverse())
     }
 }
 
@@ -41,39 +48,109 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// Iterators without entries left in the current direction, kept for changing the direction.
+    /// When moving backward, they are parked at their first entry.
+    exhausted: Vec<HeapWrapper<I>>,
+    /// Whether the iterator is moving backward.
+    backward: bool,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: Vec::new(),
+            backward: false,
+        };
+        let (active, exhausted) = iters
+            .into_iter()
+            .enumerate()
+            .map(|(idx, iter)| HeapWrapper(idx, iter, false))
+            .partition(|x| x.1.is_valid());
+        iter.rebuild(active, exhausted, false);
+        iter
+    }
 
-        let mut heap = BinaryHeap::new();
+    /// Take all iterators out of the merge iterator.
+    fn take_iters(&mut self) -> Vec<HeapWrapper<I>> {
+        let mut iters = std::mem::take(&mut self.iters).into_vec();
+        iters.append(&mut self.exhausted);
+        iters.extend(self.current.take());
+        iters
+    }
 
-        if iters.iter().all(|x| !x.is_valid()) {
+    /// Put the iterators back, with `active` ones positioned at their next entry in the new
+    /// direction.
+    fn rebuild(
+        &mut self,
+        active: Vec<HeapWrapper<I>>,
+        exhausted: Vec<HeapWrapper<I>>,
+        backward: bool,
+    ) {
+        self.backward = backward;
+        self.iters = active
+            .into_iter()
+            .map(|HeapWrapper(idx, iter, _)| HeapWrapper(idx, iter, backward))
+            .collect();
+        self.current = self.iters.pop();
+        self.exhausted = exhausted;
+        if self.current.is_none() && !backward {
             // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
+            self.current = self.exhausted.pop();
         }
+    }
+}
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Change the direction from backward to forward. All other iterators are before the current
+    /// key or parked at their first entry, so they only need to move forward past it.
+    fn switch_to_forward(&mut self) -> Result<()> {
+        let mut current = self.current.take().unwrap();
+        let key = current.1.key().to_key_vec();
+        let mut iters = self.take_iters();
+        for HeapWrapper(_, iter, _) in iters.iter_mut() {
+            while iter.is_valid() && iter.key() <= key.as_key_slice() {
+                iter.next()?;
             }
         }
+        current.1.next()?;
+        iters.push(current);
+        let (active, exhausted) = iters.into_iter().partition(|x| x.1.is_valid());
+        self.rebuild(active, exhausted, false);
+        Ok(())
+    }
+}
 
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
+impl<I: 'static + for<'a> DoubleEndedStorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Change the direction from forward to backward. All other iterators are after the current
+    /// key or exhausted, so they are moved back before it.
+    fn switch_to_backward(&mut self) -> Result<()> {
+        let mut current = self.current.take().unwrap();
+        let key = current.1.key().to_key_vec();
+        let mut active = Vec::new();
+        let mut exhausted = Vec::new();
+        for mut iter in self.take_iters() {
+            if !iter.1.is_valid() {
+                iter.1.seek_to_last()?;
+            }
+            let mut is_active = iter.1.is_valid();
+            while is_active && iter.1.key() >= key.as_key_slice() {
+                is_active = prev_or_park(&mut *iter.1)?;
+            }
+            if is_active {
+                active.push(iter);
+            } else {
+                exhausted.push(iter);
+            }
+        }
+        if prev_or_park(&mut *current.1)? {
+            active.push(current);
+        } else {
+            exhausted.push(current);
         }
+        self.rebuild(active, exhausted, true);
+        Ok(())
     }
 }
 
@@ -90,6 +167,10 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         self.current.as_ref().unwrap().1.value()
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.current.as_ref().unwrap().1.is_value_pointer()
+    }
+
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
@@ -98,6 +179,9 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.backward {
+            return self.switch_to_forward();
+        }
         let current = self.current.as_mut().unwrap();
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
@@ -114,7 +198,7 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
@@ -126,7 +210,7 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -153,3 +237,76 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
                 .unwrap_or(0)
     }
 }
+
+impl<I: 'static + for<'a> DoubleEndedStorageIterator<KeyType<'a> = KeySlice<'a>>>
+    DoubleEndedStorageIterator for MergeIterator<I>
+{
+    fn seek_to_first(&mut self) -> Result<()> {
+        let mut iters = self.take_iters();
+        for HeapWrapper(_, iter, _) in iters.iter_mut() {
+            iter.seek_to_first()?;
+        }
+        let (active, exhausted) = iters.into_iter().partition(|x| x.1.is_valid());
+        self.rebuild(active, exhausted, false);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let mut iters = self.take_iters();
+        for HeapWrapper(_, iter, _) in iters.iter_mut() {
+            iter.seek_to_last()?;
+        }
+        let (active, exhausted) = iters.into_iter().partition(|x| x.1.is_valid());
+        self.rebuild(active, exhausted, true);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.backward {
+            return self.switch_to_backward();
+        }
+        let current = self.current.as_mut().unwrap();
+        // Move back the iterators with the same key, like `next` does.
+        while let Some(mut inner_iter) = self.iters.peek_mut() {
+            if inner_iter.1.key() != current.1.key() {
+                break;
+            }
+            match prev_or_park(&mut *inner_iter.1) {
+                Ok(true) => {}
+                Ok(false) => self.exhausted.push(PeekMut::pop(inner_iter)),
+                Err(e) => {
+                    PeekMut::pop(inner_iter);
+                    return Err(e);
+                }
+            }
+        }
+
+        if !prev_or_park(&mut *current.1)? {
+            self.exhausted.extend(self.current.take());
+            self.current = self.iters.pop();
+            return Ok(());
+        }
+
+        if let Some(mut inner_iter) = self.iters.peek_mut() {
+            if *current < *inner_iter {
+                std::mem::swap(&mut *inner_iter, current);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: 'static + for<'a> SeekableStorageIterator<KeyType<'a> = KeySlice<'a>>>
+    SeekableStorageIterator for MergeIterator<I>
+{
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let mut iters = self.take_iters();
+        for HeapWrapper(_, iter, _) in iters.iter_mut() {
+            iter.seek(key)?;
+        }
+        let (active, exhausted) = iters.into_iter().partition(|x| x.1.is_valid());
+        self.rebuild(active, exhausted, false);
+        Ok(())
+    }
+}
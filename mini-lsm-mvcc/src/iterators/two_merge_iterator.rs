@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{prev_or_park, DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator};
 
 /// Merges two iterators of different types into one. If the two iterators have the same key, only
 /// produce the key once and prefer the entry from A.
@@ -8,6 +8,12 @@ pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    /// Whether the iterator is moving backward.
+    backward: bool,
+    /// Whether A has no entries left when moving backward. It is then parked at its first entry.
+    a_parked: bool,
+    /// Same as `a_parked`, for B.
+    b_parked: bool,
 }
 
 impl<
@@ -15,14 +21,18 @@ impl<
         B: 'static + for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
     > TwoMergeIterator<A, B>
 {
-    fn choose_a(a: &A, b: &B) -> bool {
-        if !a.is_valid() {
+    fn choose_a(&self) -> bool {
+        if !self.a.is_valid() || self.a_parked {
             return false;
         }
-        if !b.is_valid() {
+        if !self.b.is_valid() || self.b_parked {
             return true;
         }
-        a.key() < b.key()
+        if self.backward {
+            self.a.key() > self.b.key()
+        } else {
+            self.a.key() < self.b.key()
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
@@ -35,13 +45,38 @@ impl<
     pub fn create(a: A, b: B) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
+            backward: false,
+            a_parked: false,
+            b_parked: false,
             a,
             b,
         };
         iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.choose_a = iter.choose_a();
         Ok(iter)
     }
+
+    /// Change the direction from backward to forward. The other iterator is before the current key
+    /// or parked at its first entry, so it only needs to move forward past it.
+    fn switch_to_forward(&mut self) -> Result<()> {
+        if self.choose_a {
+            while self.b.is_valid() && self.b.key() <= self.a.key() {
+                self.b.next()?;
+            }
+            self.a.next()?;
+        } else {
+            while self.a.is_valid() && self.a.key() <= self.b.key() {
+                self.a.next()?;
+            }
+            self.b.next()?;
+        }
+        self.backward = false;
+        self.a_parked = false;
+        self.b_parked = false;
+        self.skip_b()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
 }
 
 impl<
@@ -69,22 +104,33 @@ impl<
         }
     }
 
+    fn is_value_pointer(&self) -> bool {
+        if self.choose_a {
+            self.a.is_value_pointer()
+        } else {
+            self.b.is_value_pointer()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
         } else {
-            self.b.is_valid()
+            self.b.is_valid() && !self.b_parked
         }
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.backward {
+            return self.switch_to_forward();
+        }
         if self.choose_a {
             self.a.next()?;
         } else {
             self.b.next()?;
         }
         self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
+        self.choose_a = self.choose_a();
         Ok(())
     }
 
@@ -92,3 +138,106 @@ impl<
         self.a.num_active_iterators() + self.b.num_active_iterators()
     }
 }
+
+impl<
+        A: 'static + DoubleEndedStorageIterator,
+        B: 'static + for<'a> DoubleEndedStorageIterator<KeyType<'a> = A::KeyType<'a>>,
+    > TwoMergeIterator<A, B>
+{
+    fn skip_b_backward(&mut self) -> Result<()> {
+        if self.a.is_valid()
+            && !self.a_parked
+            && self.b.is_valid()
+            && !self.b_parked
+            && self.b.key() == self.a.key()
+        {
+            self.b_parked = !prev_or_park(&mut self.b)?;
+        }
+        Ok(())
+    }
+
+    /// Change the direction from forward to backward. The other iterator is after the current key
+    /// or exhausted, so it is moved back before it.
+    fn switch_to_backward(&mut self) -> Result<()> {
+        if self.choose_a {
+            if !self.b.is_valid() {
+                self.b.seek_to_last()?;
+            }
+            while self.b.is_valid() && !self.b_parked && self.b.key() >= self.a.key() {
+                self.b_parked = !prev_or_park(&mut self.b)?;
+            }
+            self.a_parked = !prev_or_park(&mut self.a)?;
+        } else {
+            if !self.a.is_valid() {
+                self.a.seek_to_last()?;
+            }
+            while self.a.is_valid() && !self.a_parked && self.a.key() >= self.b.key() {
+                self.a_parked = !prev_or_park(&mut self.a)?;
+            }
+            self.b_parked = !prev_or_park(&mut self.b)?;
+        }
+        self.backward = true;
+        self.skip_b_backward()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
+}
+
+impl<
+        A: 'static + DoubleEndedStorageIterator,
+        B: 'static + for<'a> DoubleEndedStorageIterator<KeyType<'a> = A::KeyType<'a>>,
+    > DoubleEndedStorageIterator for TwoMergeIterator<A, B>
+{
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.a.seek_to_first()?;
+        self.b.seek_to_first()?;
+        self.backward = false;
+        self.a_parked = false;
+        self.b_parked = false;
+        self.skip_b()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.a.seek_to_last()?;
+        self.b.seek_to_last()?;
+        self.backward = true;
+        self.a_parked = false;
+        self.b_parked = false;
+        self.skip_b_backward()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.backward {
+            return self.switch_to_backward();
+        }
+        if self.choose_a {
+            self.a_parked = !prev_or_park(&mut self.a)?;
+        } else {
+            self.b_parked = !prev_or_park(&mut self.b)?;
+        }
+        self.skip_b_backward()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
+}
+
+impl<
+        A: 'static + SeekableStorageIterator,
+        B: 'static + for<'a> SeekableStorageIterator<KeyType<'a> = A::KeyType<'a>>,
+    > SeekableStorageIterator for TwoMergeIterator<A, B>
+{
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.a.seek(key)?;
+        self.b.seek(key)?;
+        self.backward = false;
+        self.a_parked = false;
+        self.b_parked = false;
+        self.skip_b()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
+}
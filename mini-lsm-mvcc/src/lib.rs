@@ -1,6 +1,13 @@
+pub mod backend;
+pub mod background_error;
 pub mod block;
+pub mod checkpoint;
+pub mod column_family;
 pub mod compact;
+pub mod compaction_filter;
 pub mod debug;
+pub mod event_listener;
+mod group_commit;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
@@ -8,8 +15,16 @@ pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
 pub mod mvcc;
+pub mod prefix_extractor;
+pub mod range_tombstone;
+pub mod rate_limiter;
+pub mod recovery;
+pub mod statistics;
 pub mod table;
+pub mod ttl;
+pub mod value_log;
 pub mod wal;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
@@ -6,9 +6,11 @@ use bytes::Bytes;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator};
 use crate::mem_table::MemTableIterator;
+use crate::range_tombstone::RangeTombstones;
 use crate::table::SsTableIterator;
+use crate::ttl;
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
 type LsmIteratorInner = TwoMergeIterator<
@@ -18,40 +20,96 @@ type LsmIteratorInner = TwoMergeIterator<
 
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
     end_bound: Bound<Bytes>,
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    /// Range tombstones visible at `read_ts`.
+    range_tombstones: RangeTombstones,
+    /// Whether the iterator is moving backward. The inner iterator is then already positioned
+    /// before the current key, whose value is kept in `prev_value`.
+    backward: bool,
+    prev_value: Vec<u8>,
+    /// The current time in milliseconds if values carry an expiry time. Values that expired by
+    /// then are skipped like tombstones.
+    now: Option<u64>,
 }
 
 impl LsmIterator {
     pub(crate) fn new(
         iter: LsmIteratorInner,
+        start_bound: Bound<Bytes>,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        range_tombstones: RangeTombstones,
+        now: Option<u64>,
     ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
             inner: iter,
+            start_bound,
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            range_tombstones,
+            backward: false,
+            prev_value: Vec::new(),
+            now,
         };
         iter.move_to_key()?;
         Ok(iter)
     }
 
+    /// Whether a stored value is neither a tombstone nor expired.
+    fn is_live(&self, value: &[u8]) -> bool {
+        match self.now {
+            _ if value.is_empty() => false,
+            Some(now) => ttl::decode_value(value).1 > now,
+            None => true,
+        }
+    }
+
+    fn is_before_end(&self, key: &[u8]) -> bool {
+        match self.end_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(end) => key <= end.as_ref(),
+            Bound::Excluded(end) => key < end.as_ref(),
+        }
+    }
+
+    fn is_after_start(&self, key: &[u8]) -> bool {
+        match self.start_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(start) => key >= start.as_ref(),
+            Bound::Excluded(start) => key > start.as_ref(),
+        }
+    }
+
+    /// Move the inner iterator to the first version of the first key >= `key` within the start
+    /// bound.
+    fn seek_inner(&mut self, key: &[u8]) -> Result<()> {
+        let key = match self.start_bound.as_ref() {
+            Bound::Included(start) | Bound::Excluded(start) if start.as_ref() > key => {
+                start.clone()
+            }
+            _ => Bytes::copy_from_slice(key),
+        };
+        self.inner.seek(&key)?;
+        // Skip the versions of an excluded start key.
+        while self.inner.is_valid() && !self.is_after_start(self.inner.key().key_ref()) {
+            self.inner.next()?;
+        }
+        Ok(())
+    }
+
     fn next_inner(&mut self) -> Result<()> {
         self.inner.next()?;
         if !self.inner.is_valid() {
             self.is_valid = false;
             return Ok(());
         }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
-        }
+        self.is_valid = self.is_before_end(self.inner.key().key_ref());
         Ok(())
     }
 
@@ -77,12 +135,49 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
+            if self.is_live(self.inner.value())
+                && !self
+                    .range_tombstones
+                    .covers(&self.prev_key, self.inner.key().ts())
+            {
                 break;
             }
         }
         Ok(())
     }
+
+    /// Move backward to the previous key that is visible at `read_ts`. The inner iterator must be
+    /// at the last version of the key to check first, and is left before the visible key.
+    fn move_to_prev_key(&mut self) -> Result<()> {
+        loop {
+            if !self.inner.is_valid() || !self.is_after_start(self.inner.key().key_ref()) {
+                self.is_valid = false;
+                return Ok(());
+            }
+            self.prev_key.clear();
+            self.prev_key.extend(self.inner.key().key_ref());
+            // Versions are ordered by descending timestamp, so the last one at or below `read_ts`
+            // is the visible one.
+            let mut visible_ts = None;
+            while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+                let ts = self.inner.key().ts();
+                if ts <= self.read_ts {
+                    visible_ts = Some(ts);
+                    self.prev_value.clear();
+                    self.prev_value.extend(self.inner.value());
+                }
+                self.inner.prev()?;
+            }
+            if let Some(ts) = visible_ts {
+                if self.is_live(&self.prev_value)
+                    && !self.range_tombstones.covers(&self.prev_key, ts)
+                {
+                    self.is_valid = true;
+                    return Ok(());
+                }
+            }
+        }
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -93,15 +188,40 @@ impl StorageIterator for LsmIterator {
     }
 
     fn key(&self) -> &[u8] {
-        self.inner.key().key_ref()
+        if self.backward {
+            &self.prev_key
+        } else {
+            self.inner.key().key_ref()
+        }
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        let value = if self.backward {
+            &self.prev_value
+        } else {
+            self.inner.value()
+        };
+        if self.now.is_some() {
+            ttl::decode_value(value).0
+        } else {
+            value
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
+        if self.backward {
+            // Move the inner iterator past all versions of the current key.
+            self.backward = false;
+            if !self.inner.is_valid() {
+                self.inner.seek_to_first()?;
+            }
+            while self.inner.is_valid() && self.inner.key().key_ref() <= self.prev_key.as_slice() {
+                self.inner.next()?;
+            }
+            self.is_valid = self.inner.is_valid() && self.is_before_end(self.inner.key().key_ref());
+        } else {
+            self.next_inner()?;
+        }
         self.move_to_key()?;
         Ok(())
     }
@@ -111,6 +231,54 @@ impl StorageIterator for LsmIterator {
     }
 }
 
+impl DoubleEndedStorageIterator for LsmIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        // All keys are >= the empty key.
+        self.seek(&[])
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.backward = true;
+        match self.end_bound.clone() {
+            Bound::Unbounded => self.inner.seek_to_last()?,
+            Bound::Included(end) | Bound::Excluded(end) => {
+                // Move to the first version after the end bound, and then back before it.
+                self.inner.seek(&end)?;
+                while self.inner.is_valid() && self.is_before_end(self.inner.key().key_ref()) {
+                    self.inner.next()?;
+                }
+                if self.inner.is_valid() {
+                    self.inner.prev()?;
+                } else {
+                    self.inner.seek_to_last()?;
+                }
+            }
+        }
+        self.move_to_prev_key()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.backward {
+            // Move the inner iterator before all versions of the current key.
+            self.backward = true;
+            while self.inner.is_valid() && self.inner.key().key_ref() >= self.prev_key.as_slice() {
+                self.inner.prev()?;
+            }
+        }
+        self.move_to_prev_key()
+    }
+}
+
+impl SeekableStorageIterator for LsmIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.backward = false;
+        self.seek_inner(key)?;
+        self.is_valid = self.inner.is_valid() && self.is_before_end(self.inner.key().key_ref());
+        self.prev_key.clear();
+        self.move_to_key()
+    }
+}
+
 /// A wrapper around existing iterator, will prevent users from calling `next` when the iterator is
 /// invalid. If an iterator is already invalid, `next` does not do anything. If `next` returns an error,
 /// `is_valid` should return false, and `next` should always return an error.
@@ -167,3 +335,54 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.num_active_iterators()
     }
 }
+
+impl<I: DoubleEndedStorageIterator> DoubleEndedStorageIterator for FusedIterator<I> {
+    fn seek_to_first(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_to_first() {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_to_last() {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        // only move when the iterator is valid and not errored
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if self.iter.is_valid() {
+            if let Err(e) = self.iter.prev() {
+                self.has_errored = true;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: SeekableStorageIterator> SeekableStorageIterator for FusedIterator<I> {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
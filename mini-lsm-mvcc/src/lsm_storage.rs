@@ -1,19 +1,28 @@
-use std::collections::{BTreeSet, HashMap};
-use std::fs::File;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
+use crate::backend::{LocalBackend, StorageBackend};
+use crate::background_error::{BackgroundError, BackgroundErrorHandler, ManifestWriteError};
 use crate::block::Block;
+use crate::column_family::{
+    ColumnFamily, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY, DEFAULT_COLUMN_FAMILY_ID,
+};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionOptions, LeveledCompactionOptions, RunningCompactions, SimpleLeveledCompactionOptions,
+};
+use crate::compaction_filter::CompactionFilter;
+use crate::event_listener::{
+    BackgroundErrorReason, EventListener, FlushJobInfo, TableFileDeletionInfo,
 };
+use crate::group_commit::{PendingWrite, WriteQueue};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
@@ -22,9 +31,19 @@ use crate::key::{self, KeySlice};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
 use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
+use crate::mvcc::snapshot::Snapshot;
 use crate::mvcc::txn::{Transaction, TxnIterator};
 use crate::mvcc::LsmMvccInner;
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstones;
+use crate::rate_limiter::{IoOp, IoPriority, RateLimiter};
+use crate::recovery::{RecoveryMode, RecoveryReport};
+use crate::statistics::{HistogramType, Statistics, Ticker};
+use crate::table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::ttl::{self, TtlOptions};
+use crate::value_log::{ValueLog, ValueLogOptions};
+use crate::wal::{Wal, WalBatch};
+use crate::write_stall::{WriteController, WriteStall, WriteStallOptions};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
@@ -47,11 +66,24 @@ pub struct LsmStorageState {
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
+    /// Deletes all keys in `start..end`.
+    DelRange(T, T),
+}
+
+/// Options of a single write.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Sync the WAL before the write is acknowledged, so that it survives a crash of the machine.
+    /// Otherwise, the write is only handed to the OS and survives a crash of the process.
+    pub sync: bool,
+    /// The TTL of the values put by the write, which overrides `TtlOptions::default_ttl`. Writes
+    /// with a TTL fail unless the DB is opened with `LsmStorageOptions::ttl`.
+    pub ttl: Option<Duration>,
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
-        let levels = match &options.compaction_options {
+    pub(crate) fn create(compaction_options: &CompactionOptions) -> Self {
+        let levels = match compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
                 ..=*max_levels)
@@ -68,6 +100,28 @@ impl LsmStorageState {
             sstables: Default::default(),
         }
     }
+
+    /// Collects the range tombstones visible at `read_ts` that overlap the given key range.
+    pub(crate) fn range_tombstones(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> RangeTombstones {
+        let memtable_tombstones = std::iter::once(&self.memtable)
+            .chain(self.imm_memtables.iter())
+            .flat_map(|memtable| memtable.range_tombstones());
+        let sst_tombstones = self
+            .sstables
+            .values()
+            .flat_map(|sst| sst.range_tombstones().iter().cloned());
+        RangeTombstones::new(
+            memtable_tombstones
+                .chain(sst_tombstones)
+                .filter(|tombstone| tombstone.ts <= read_ts && tombstone.overlaps(lower, upper))
+                .collect(),
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -79,8 +133,41 @@ pub struct LsmStorageOptions {
     // Maximum number of memtables in memory, flush to L0 when exceeding this limit
     pub num_memtable_limit: usize,
     pub compaction_options: CompactionOptions,
+    // Number of threads running compactions. Tasks that do not conflict with each other run
+    // concurrently
+    pub max_background_compactions: usize,
+    // Split each compaction into up to this many key ranges, which are compacted in parallel
+    pub max_subcompactions: usize,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Store large values in a value log instead of the SSTs, disabled if `None`
+    pub value_log_options: Option<ValueLogOptions>,
+    // Build per-SST bloom filters over key prefixes for `scan_prefix`, disabled if `None`
+    pub prefix_extractor: Option<PrefixExtractor>,
+    // Block compression of the SSTs in each level, starting with L0. Levels past the end use the
+    // last entry, and blocks are not compressed if it is empty
+    pub compression_per_level: Vec<CompressionType>,
+    // Column families besides the default one, which uses the options above. Column families
+    // cannot be dropped, so all column families of an existing DB must be listed
+    pub column_families: Vec<(String, ColumnFamilyOptions)>,
+    // Rewrite the manifest as a snapshot of the current state once it grows past this size in
+    // bytes. The manifest is also rewritten every time the DB is opened
+    pub max_manifest_size: usize,
+    // How to handle damaged WAL and manifest records when opening the DB
+    pub recovery_mode: RecoveryMode,
+    // Where the files of the DB are stored
+    pub backend: Arc<dyn StorageBackend>,
+    // Delay and then block writes when flushes or compactions fall behind, disabled if `None`
+    pub write_stall_options: Option<WriteStallOptions>,
+    // Limit the I/O rate of flushes and compactions, disabled if `None`. The limiter can be shared
+    // with other DBs, and its rate changed while the DB is running
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Notified of flushes, compactions, deleted SSTs, write stalls and background errors
+    pub event_listeners: Vec<Arc<dyn EventListener>>,
+    // Store an expiry time with every value, so that keys can be written with a TTL, disabled if
+    // `None`. A DB must always be opened with the same setting, as it changes how values are
+    // stored, and fails to open otherwise
+    pub ttl: Option<TtlOptions>,
 }
 
 impl LsmStorageOptions {
@@ -89,9 +176,22 @@ impl LsmStorageOptions {
             block_size: 4096,
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
+            max_background_compactions: 1,
+            max_subcompactions: 1,
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            value_log_options: None,
+            prefix_extractor: None,
+            compression_per_level: Vec::new(),
+            column_families: Vec::new(),
+            max_manifest_size: 1 << 20,
+            recovery_mode: RecoveryMode::TolerateCorruptedTailRecords,
+            backend: Arc::new(LocalBackend),
+            write_stall_options: None,
+            rate_limiter: None,
+            event_listeners: Vec::new(),
+            ttl: None,
         }
     }
 
@@ -100,9 +200,22 @@ impl LsmStorageOptions {
             block_size: 4096,
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
+            max_background_compactions: 1,
+            max_subcompactions: 1,
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            value_log_options: None,
+            prefix_extractor: None,
+            compression_per_level: Vec::new(),
+            column_families: Vec::new(),
+            max_manifest_size: 1 << 20,
+            recovery_mode: RecoveryMode::TolerateCorruptedTailRecords,
+            backend: Arc::new(LocalBackend),
+            write_stall_options: None,
+            rate_limiter: None,
+            event_listeners: Vec::new(),
+            ttl: None,
         }
     }
 
@@ -111,9 +224,34 @@ impl LsmStorageOptions {
             block_size: 4096,
             target_sst_size: 1 << 20, // 1MB
             compaction_options,
+            max_background_compactions: 1,
+            max_subcompactions: 1,
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            value_log_options: None,
+            prefix_extractor: None,
+            compression_per_level: Vec::new(),
+            column_families: Vec::new(),
+            max_manifest_size: 1 << 20,
+            recovery_mode: RecoveryMode::TolerateCorruptedTailRecords,
+            backend: Arc::new(LocalBackend),
+            write_stall_options: None,
+            rate_limiter: None,
+            event_listeners: Vec::new(),
+            ttl: None,
+        }
+    }
+
+    /// The options of the default column family.
+    pub fn column_family_options(&self) -> ColumnFamilyOptions {
+        ColumnFamilyOptions {
+            block_size: self.block_size,
+            target_sst_size: self.target_sst_size,
+            num_memtable_limit: self.num_memtable_limit,
+            compaction_options: self.compaction_options.clone(),
+            prefix_extractor: self.prefix_extractor,
+            compression_per_level: self.compression_per_level.clone(),
         }
     }
 }
@@ -149,23 +287,29 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
-#[derive(Clone, Debug)]
-pub enum CompactionFilter {
-    Prefix(Bytes),
-}
-
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
+    /// The state of the default column family.
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
     path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
     next_sst_id: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
-    pub(crate) compaction_controller: CompactionController,
+    /// All column families sorted by id, starting with the default column family.
+    pub(crate) column_families: Vec<Arc<ColumnFamily>>,
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
-    pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) compaction_filters: Arc<Mutex<Vec<Arc<dyn CompactionFilter>>>>,
+    pub(crate) value_log: Arc<ValueLog>,
+    /// What was dropped while opening the storage.
+    pub(crate) recovery_report: RecoveryReport,
+    /// The writes waiting to be committed.
+    pub(crate) write_queue: WriteQueue,
+    pub(crate) running_compactions: RunningCompactions,
+    pub(crate) write_controller: WriteController,
+    pub(crate) statistics: Arc<Statistics>,
+    pub(crate) background_error: BackgroundErrorHandler,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -213,19 +357,18 @@ impl MiniLsm {
             return Ok(());
         }
 
-        // create memtable and skip updating manifest
-        if !self.inner.state.read().memtable.is_empty() {
+        if self.inner.has_unfrozen_data() {
             self.inner
-                .freeze_memtable_with_memtable(Arc::new(MemTable::create(
-                    self.inner.next_sst_id(),
-                )))?;
+                .freeze_memtables(None, &self.inner.state_lock.lock())?;
         }
 
-        while {
-            let snapshot = self.inner.state.read();
-            !snapshot.imm_memtables.is_empty()
-        } {
-            self.inner.force_flush_next_imm_memtable()?;
+        for cf in &self.inner.column_families {
+            while {
+                let snapshot = cf.state.read();
+                !snapshot.imm_memtables.is_empty()
+            } {
+                self.inner.flush_next_imm_memtable(cf)?;
+            }
         }
         self.inner.sync_dir()?;
 
@@ -249,53 +392,204 @@ impl MiniLsm {
         }))
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+    pub fn add_compaction_filter(&self, compaction_filter: Arc<dyn CompactionFilter>) {
         self.inner.add_compaction_filter(compaction_filter)
     }
 
+    /// Returns the column family with the given name, which is listed in the options or is the
+    /// default column family.
+    pub fn column_family(&self, name: &str) -> Option<Arc<ColumnFamily>> {
+        self.inner.column_family(name)
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
         self.inner.get(key)
     }
 
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_cf(cf, key)
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
 
+    pub fn write_batch_opt<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_opt(batch, options)
+    }
+
+    /// Atomically applies a batch of writes to several column families.
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        self.inner.write_batch_cf(batch)
+    }
+
+    pub fn write_batch_cf_opt<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_cf_opt(batch, options)
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
 
+    pub fn put_opt(&self, key: &[u8], value: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.put_opt(key, value, options)
+    }
+
+    /// Put a key-value pair that expires after `ttl`. The DB must be opened with
+    /// `LsmStorageOptions::ttl`.
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.inner.put_with_ttl(key, value, ttl)
+    }
+
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put_cf(cf, key, value)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
 
+    pub fn delete_opt(&self, key: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.delete_opt(key, options)
+    }
+
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.inner.delete_cf(cf, key)
+    }
+
+    /// Deletes all keys in `start..end`.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_range(start, end)
+    }
+
+    /// Deletes all keys in `start..end` of a column family.
+    pub fn delete_range_cf(&self, cf: &ColumnFamily, start: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_range_cf(cf, start, end)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
 
+    /// Returns the damaged WAL and manifest records that were dropped when opening the storage.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.inner.recovery_report
+    }
+
+    /// Returns why writes are currently delayed or blocked, if they are.
+    pub fn write_stall(&self) -> Option<WriteStall> {
+        self.inner.write_controller.stall()
+    }
+
+    /// Returns the error that stopped writes, if any. Writes resume once the failed work succeeds
+    /// on a retry or `resume` is called, unless the error is fatal.
+    pub fn background_error(&self) -> Option<BackgroundError> {
+        self.inner.background_error.error()
+    }
+
+    /// Retries the work that failed with a transient error right away, and lets writes resume if it
+    /// succeeds. The DB has to be reopened after a fatal error.
+    pub fn resume(&self) -> Result<()> {
+        self.inner.resume()
+    }
+
+    /// Returns the counters and latency histograms of the DB.
+    pub fn statistics(&self) -> &Statistics {
+        &self.inner.statistics
+    }
+
     pub fn new_txn(&self) -> Result<Arc<Transaction>> {
         self.inner.new_txn()
     }
 
+    /// Create a read-only snapshot at the latest commit. The versions it reads are kept until it
+    /// is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.snapshot()
+    }
+
+    /// Create a read-only snapshot at a commit timestamp that has not been garbage collected yet.
+    pub fn snapshot_at(&self, ts: u64) -> Result<Snapshot> {
+        self.inner.snapshot_at(ts)
+    }
+
+    /// Reads a key as of the commit timestamp `ts`.
+    pub fn get_at(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, ts)
+    }
+
+    /// Create an iterator over a range of keys as of the commit timestamp `ts`.
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_at(lower, upper, ts)
+    }
+
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_cf(cf, lower, upper)
+    }
+
+    /// Create an iterator over the keys that start with `prefix`.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        self.inner.scan_prefix(prefix)
+    }
+
+    /// Create an iterator over the keys of a column family that start with `prefix`.
+    pub fn scan_prefix_cf(&self, cf: &ColumnFamily, prefix: &[u8]) -> Result<TxnIterator> {
+        self.inner.scan_prefix_cf(cf, prefix)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
-        if !self.inner.state.read().memtable.is_empty() {
+        self.inner.background_error.check_not_read_only()?;
+        if self.inner.has_unfrozen_data() {
             self.inner
-                .force_freeze_memtable(&self.inner.state_lock.lock())?;
+                .freeze_memtables(None, &self.inner.state_lock.lock())?;
         }
-        if !self.inner.state.read().imm_memtables.is_empty() {
-            self.inner.force_flush_next_imm_memtable()?;
+        for cf in &self.inner.column_families {
+            if !cf.state.read().imm_memtables.is_empty() {
+                self.inner.flush_next_imm_memtable(cf)?;
+            }
         }
         Ok(())
     }
 
+    /// Creates a consistent copy of the database in `dir`, which must not exist yet. The memtables
+    /// are flushed first, and the SSTs are hard-linked into `dir` where possible.
+    pub fn checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        self.inner.checkpoint(dir)
+    }
+
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    pub fn force_full_compaction_cf(&self, cf: &ColumnFamily) -> Result<()> {
+        self.inner.force_full_compaction_cf(cf)
+    }
 }
 
 impl LsmStorageInner {
@@ -304,59 +598,198 @@ impl LsmStorageInner {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
+    /// Waits until the rate limiter, if any, allows `bytes` bytes of background I/O.
+    pub(crate) fn rate_limit(&self, bytes: usize, priority: IoPriority, op: IoOp) {
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            rate_limiter.request(bytes, priority, op);
+        }
+    }
+
+    /// Calls each event listener registered in the options.
+    pub(crate) fn notify(&self, f: impl Fn(&dyn EventListener)) {
+        for listener in &self.options.event_listeners {
+            f(listener.as_ref());
+        }
+    }
+
+    /// Records the error of a flush, a compaction or a manifest write, which stops writes until it
+    /// is cleared. `column_family` is the column family of a failed compaction.
+    pub(crate) fn report_background_error(
+        &self,
+        reason: BackgroundErrorReason,
+        column_family: Option<&str>,
+        error: &anyhow::Error,
+    ) {
+        // A flush or a compaction that failed to write the manifest was already reported by
+        // `add_manifest_record`.
+        if reason != BackgroundErrorReason::ManifestWrite
+            && error.downcast_ref::<ManifestWriteError>().is_some()
+        {
+            return;
+        }
+        self.background_error.set(reason, column_family, error);
+        // Blocked writes fail instead of waiting for background work that no longer runs.
+        self.write_controller.wake_up();
+        log::error!("{:?} failed: {:#}", reason, error);
+        self.notify(|listener| listener.on_background_error(reason, error));
+    }
+
+    /// Retries the work that failed with a transient error, and clears the error if it succeeds.
+    pub(crate) fn resume(&self) -> Result<()> {
+        let Some(error) = self.background_error.error() else {
+            return Ok(());
+        };
+        self.background_error.check_not_read_only()?;
+        let column_family = error.column_family.as_deref();
+        let result = match error.reason {
+            BackgroundErrorReason::Flush => self.trigger_flush(),
+            BackgroundErrorReason::Compaction => self
+                .column_families
+                .iter()
+                .try_for_each(|cf| self.trigger_compaction(cf).map(|_| ())),
+            BackgroundErrorReason::ManifestWrite => unreachable!("manifest write errors are fatal"),
+        };
+        if let Err(e) = result {
+            self.report_background_error(error.reason, column_family, &e);
+            return Err(e);
+        }
+        self.background_error.clear(error.reason, column_family);
+        Ok(())
+    }
+
+    /// Deletes an SST that was removed from the state of a column family.
+    pub(crate) fn delete_sst(&self, cf: &ColumnFamily, sst_id: usize) -> Result<()> {
+        let path = self.path_of_sst(sst_id);
+        self.backend().delete(&path)?;
+        let info = TableFileDeletionInfo {
+            column_family: cf.name().to_string(),
+            sst_id,
+            path,
+        };
+        self.notify(|listener| listener.on_table_file_deleted(&info));
+        Ok(())
+    }
+
     pub(crate) fn mvcc(&self) -> &LsmMvccInner {
         self.mvcc.as_ref().unwrap()
     }
 
+    pub(crate) fn backend(&self) -> &dyn StorageBackend {
+        self.options.backend.as_ref()
+    }
+
     pub(crate) fn manifest(&self) -> &Manifest {
         self.manifest.as_ref().unwrap()
     }
 
+    pub(crate) fn default_cf(&self) -> &Arc<ColumnFamily> {
+        &self.column_families[0]
+    }
+
+    pub(crate) fn column_family(&self, name: &str) -> Option<Arc<ColumnFamily>> {
+        self.column_families
+            .iter()
+            .find(|cf| cf.name() == name)
+            .cloned()
+    }
+
+    pub(crate) fn column_family_by_id(&self, id: u32) -> Option<&Arc<ColumnFamily>> {
+        self.column_families.iter().find(|cf| cf.id() == id)
+    }
+
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
         let path = path.as_ref();
         let mut next_sst_id = 1;
         let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
-        let manifest;
-
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+        let statistics = Arc::new(Statistics::default());
+        let backend = options.backend.clone();
+        let value_log = Arc::new(ValueLog::new(
+            backend.clone(),
+            path,
+            options.value_log_options.clone(),
+        ));
+
+        // The options of all column families, starting with the default one.
+        let mut cf_options = vec![(
+            DEFAULT_COLUMN_FAMILY.to_string(),
+            options.column_family_options(),
+        )];
+        cf_options.extend(options.column_families.iter().cloned());
+        let mut names = HashSet::new();
+        for (name, cf_options) in &cf_options {
+            if !names.insert(name) {
+                bail!("duplicate column family {}", name);
             }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            if let Some(write_stall_options) = &options.write_stall_options {
+                write_stall_options.check_column_family(name, cf_options)?;
             }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
-            ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+        // Column families by id, with the state recovered so far.
+        let new_cf = |id: u32, name: &str| {
+            let Some((_, cf_options)) = cf_options.iter().find(|(x, _)| x == name) else {
+                bail!("column family {} is missing from the options", name);
+            };
+            let state = LsmStorageState::create(&cf_options.compaction_options);
+            Ok((
+                ColumnFamily::new(id, name.to_string(), cf_options.clone()),
+                state,
+            ))
         };
-
-        if !path.exists() {
-            std::fs::create_dir_all(path).context("failed to create DB dir")?;
+        let mut column_families = BTreeMap::new();
+        column_families.insert(
+            DEFAULT_COLUMN_FAMILY_ID,
+            new_cf(DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY)?,
+        );
+
+        if !backend.exists(path) {
+            backend
+                .create_dir_all(path)
+                .context("failed to create DB dir")?;
         }
-        let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
-        if !manifest_path.exists() {
-            if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
-            }
-            manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
-            manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+        let mut recovery_report = RecoveryReport::default();
+        let recovered = Manifest::exists(backend.as_ref(), path);
+        let (manifest, generation) = if !recovered {
+            let manifest = Manifest::create(
+                backend.clone(),
+                path,
+                [ManifestRecord::Ttl(options.ttl.is_some())],
+            )
+            .context("failed to create manifest")?;
+            (manifest, 0)
         } else {
-            let (m, records) = Manifest::recover(&manifest_path)?;
-            let mut memtables = BTreeSet::new();
+            let (m, records, dropped_tail) =
+                Manifest::recover(backend.clone(), path, options.recovery_mode)?;
+            recovery_report.dropped_tails.extend(dropped_tail);
+            let mut wal_ids = BTreeSet::new();
+            // The `(column family, WAL)` pairs whose memtable has been flushed.
+            let mut flushed = HashSet::new();
+            // Whether the values of the DB have expiry times, which is unknown for DBs created
+            // before it was recorded in the manifest.
+            let mut ttl_enabled = None;
             for record in records {
-                match record {
+                let record = match record {
                     ManifestRecord::Flush(sst_id) => {
-                        let res = memtables.remove(&sst_id);
-                        assert!(res, "memtable not exist?");
-                        if compaction_controller.flush_to_l0() {
+                        ManifestRecord::ColumnFamilyFlush(DEFAULT_COLUMN_FAMILY_ID, sst_id, sst_id)
+                    }
+                    ManifestRecord::Compaction(task, output) => {
+                        ManifestRecord::ColumnFamilyCompaction(
+                            DEFAULT_COLUMN_FAMILY_ID,
+                            task,
+                            output,
+                        )
+                    }
+                    record => record,
+                };
+                match record {
+                    ManifestRecord::ColumnFamilyFlush(cf_id, wal_id, sst_id) => {
+                        let Some((cf, state)) = column_families.get_mut(&cf_id) else {
+                            bail!("flush of unknown column family {}", cf_id);
+                        };
+                        flushed.insert((cf_id, wal_id));
+                        if cf.compaction_controller.flush_to_l0() {
                             state.l0_sstables.insert(0, sst_id);
                         } else {
                             state.levels.insert(0, (sst_id, vec![sst_id]));
@@ -365,107 +798,331 @@ impl LsmStorageInner {
                     }
                     ManifestRecord::NewMemtable(x) => {
                         next_sst_id = next_sst_id.max(x);
-                        memtables.insert(x);
+                        wal_ids.insert(x);
                     }
-                    ManifestRecord::Compaction(task, output) => {
-                        let (new_state, _) =
-                            compaction_controller.apply_compaction_result(&state, &task, &output);
-                        // TODO: apply remove again
-                        state = new_state;
+                    ManifestRecord::ColumnFamilyCompaction(cf_id, task, output) => {
+                        let Some((cf, state)) = column_families.get_mut(&cf_id) else {
+                            bail!("compaction of unknown column family {}", cf_id);
+                        };
+                        // The SSTs removed by the compaction are deleted below if the
+                        // compaction did not get to delete them.
+                        let (new_state, _) = cf
+                            .compaction_controller
+                            .apply_compaction_result(state, &task, &output);
+                        *state = new_state;
                         next_sst_id =
                             next_sst_id.max(output.iter().max().copied().unwrap_or_default());
                     }
+                    ManifestRecord::ColumnFamilySnapshot(cf_id, l0_sstables, levels) => {
+                        let Some((_, state)) = column_families.get_mut(&cf_id) else {
+                            bail!("snapshot of unknown column family {}", cf_id);
+                        };
+                        for sst_id in l0_sstables
+                            .iter()
+                            .chain(levels.iter().flat_map(|(_, files)| files))
+                        {
+                            next_sst_id = next_sst_id.max(*sst_id);
+                        }
+                        state.l0_sstables = l0_sstables;
+                        state.levels = levels;
+                    }
+                    ManifestRecord::ColumnFamilyWalFlushed(cf_id, wal_id) => {
+                        flushed.insert((cf_id, wal_id));
+                    }
+                    ManifestRecord::CreateColumnFamily(id, name) => {
+                        column_families.insert(id, new_cf(id, &name)?);
+                    }
+                    ManifestRecord::Ttl(enabled) => ttl_enabled = Some(enabled),
+                    ManifestRecord::Flush(_) | ManifestRecord::Compaction(..) => unreachable!(),
+                }
+            }
+            // Values would be read with the wrong layout if the setting changed.
+            match ttl_enabled {
+                Some(true) if options.ttl.is_none() => {
+                    bail!("the DB stores values with expiry times, and must be opened with TTL enabled")
+                }
+                Some(false) if options.ttl.is_some() => {
+                    bail!("the DB stores values without expiry times, and cannot be opened with TTL enabled")
                 }
+                Some(_) => {}
+                None => m.add_record_when_init(ManifestRecord::Ttl(options.ttl.is_some()))?,
             }
 
             let mut sst_cnt = 0;
             // recover SSTs
-            for table_id in state
-                .l0_sstables
-                .iter()
-                .chain(state.levels.iter().flat_map(|(_, files)| files))
-            {
-                let table_id = *table_id;
-                let sst = SsTable::open(
-                    table_id,
-                    Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
+            for (_, state) in column_families.values_mut() {
+                for table_id in state
+                    .l0_sstables
+                    .iter()
+                    .chain(state.levels.iter().flat_map(|(_, files)| files))
+                {
+                    let table_id = *table_id;
+                    let mut sst = SsTable::open(
+                        table_id,
+                        Some(block_cache.clone()),
+                        FileObject::open_with_backend(
+                            backend.as_ref(),
+                            &Self::path_of_sst_static(path, table_id),
+                        )
                         .context("failed to open SST")?,
-                )?;
-                last_commit_ts = last_commit_ts.max(sst.max_ts());
-                state.sstables.insert(table_id, Arc::new(sst));
-                sst_cnt += 1;
+                    )?;
+                    sst.attach_value_log(&value_log)?;
+                    sst.attach_statistics(&statistics);
+                    last_commit_ts = last_commit_ts.max(sst.max_ts());
+                    state.sstables.insert(table_id, Arc::new(sst));
+                    sst_cnt += 1;
+                }
+                // Compactions replayed from the manifest leave the levels unsorted.
+                let sstables = &state.sstables;
+                for (_, files) in &mut state.levels {
+                    files.sort_by(|x, y| sstables[x].first_key().cmp(sstables[y].first_key()));
+                }
+            }
+            log::info!("{} SSTs opened", sst_cnt);
+
+            // Remove the SSTs left behind by compactions and flushes that did not finish.
+            for sst_path in backend.list(path)? {
+                if sst_path.extension().is_none_or(|ext| ext != "sst") {
+                    continue;
+                }
+                let table_id = sst_path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok());
+                if let Some(table_id) = table_id {
+                    if !column_families
+                        .values()
+                        .any(|(_, state)| state.sstables.contains_key(&table_id))
+                    {
+                        backend.delete(&sst_path)?;
+                    }
+                }
+            }
+
+            // Value log files share the id space with SSTs but are not recorded in the manifest.
+            for id in value_log.list_files()? {
+                next_sst_id = next_sst_id.max(id);
             }
-            println!("{} SSTs opened", sst_cnt);
 
             next_sst_id += 1;
 
             // recover memtables
             if options.enable_wal {
                 let mut wal_cnt = 0;
-                for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
-                    if !memtable.is_empty() {
-                        state.imm_memtables.insert(0, Arc::new(memtable));
+                let mut wal_damaged = false;
+                for wal_id in wal_ids {
+                    let wal_path = Self::path_of_wal_static(path, wal_id);
+                    // The WAL is removed once the memtables of all column families are flushed.
+                    if !backend.exists(&wal_path) {
+                        continue;
+                    }
+                    // Point-in-time recovery drops everything logged after a damaged record.
+                    if options.recovery_mode == RecoveryMode::PointInTimeRecovery && wal_damaged {
+                        backend.delete(&wal_path)?;
+                        recovery_report.dropped_wals.push(wal_path);
+                        continue;
+                    }
+                    let (wal, memtables, dropped_tail) = Wal::recover_column_families(
+                        backend.as_ref(),
+                        &wal_path,
+                        options.recovery_mode,
+                    )?;
+                    wal_damaged |= dropped_tail.is_some();
+                    recovery_report.dropped_tails.extend(dropped_tail);
+                    let mut in_use = false;
+                    for (cf_id, memtable) in memtables {
+                        if flushed.contains(&(cf_id, wal_id)) {
+                            continue;
+                        }
+                        let Some((_, state)) = column_families.get_mut(&cf_id) else {
+                            bail!(
+                                "WAL {} has records of unknown column family {}",
+                                wal_id,
+                                cf_id
+                            );
+                        };
+                        // Only the memtables of the default column family have the id of their WAL.
+                        let id = if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+                            wal_id
+                        } else {
+                            next_sst_id += 1;
+                            next_sst_id - 1
+                        };
+                        let memtable = MemTable::recover_from_shared_wal(
+                            id,
+                            wal_id,
+                            wal.for_column_family(cf_id),
+                            memtable,
+                        );
+                        let max_ts = memtable
+                            .map
+                            .iter()
+                            .map(|x| x.key().ts())
+                            .max()
+                            .unwrap_or_default();
+                        last_commit_ts = last_commit_ts.max(max_ts);
+                        if !memtable.is_empty() {
+                            state.imm_memtables.insert(0, Arc::new(memtable));
+                            in_use = true;
+                        }
+                    }
+                    if in_use {
                         wal_cnt += 1;
+                    } else {
+                        // Left behind by a flush that did not finish removing it.
+                        backend.delete(&wal_path)?;
                     }
                 }
-                println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
-            } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                log::info!("{} WALs recovered", wal_cnt);
+            }
+            if !recovery_report.is_clean() {
+                log::warn!("recovery dropped damaged records: {:?}", recovery_report);
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
             next_sst_id += 1;
-            manifest = m;
+            (m, next_sst_id - 1)
         };
 
+        // Create the column families that are new in the options.
+        for (name, _) in &cf_options {
+            if !column_families.values().any(|(cf, _)| cf.name() == name) {
+                let id = column_families.keys().max().unwrap() + 1;
+                manifest
+                    .add_record_when_init(ManifestRecord::CreateColumnFamily(id, name.clone()))?;
+                column_families.insert(id, new_cf(id, name)?);
+            }
+        }
+        let memtables = Self::create_memtables(
+            backend.as_ref(),
+            path,
+            options.enable_wal,
+            generation,
+            column_families.keys().copied(),
+            || {
+                next_sst_id += 1;
+                next_sst_id - 1
+            },
+        )?;
+        for ((_, state), memtable) in column_families.values_mut().zip(memtables) {
+            state.memtable = memtable;
+        }
+        manifest.add_record_when_init(ManifestRecord::NewMemtable(generation))?;
+
+        let column_families = column_families
+            .into_values()
+            .map(|(cf, state)| {
+                *cf.state.write() = Arc::new(state);
+                Arc::new(cf)
+            })
+            .collect::<Vec<_>>();
+        let write_controller = WriteController::new(
+            options.write_stall_options.clone(),
+            options.event_listeners.clone(),
+            statistics.clone(),
+        );
         let storage = Self {
-            state: Arc::new(RwLock::new(Arc::new(state))),
+            state: column_families[0].state.clone(),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
             block_cache,
             next_sst_id: AtomicUsize::new(next_sst_id),
-            compaction_controller,
+            column_families,
             manifest: Some(manifest),
             options: options.into(),
             mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            value_log,
+            recovery_report,
+            write_queue: WriteQueue::default(),
+            running_compactions: RunningCompactions::default(),
+            write_controller,
+            statistics,
+            background_error: BackgroundErrorHandler::default(),
         };
         storage.sync_dir()?;
+        if recovered {
+            storage.rotate_manifest(&storage.state_lock.lock())?;
+        }
+        // Remove the value log files left behind by flushes and compactions that did not finish.
+        storage.gc_value_log()?;
+        storage.write_controller.update(&storage.column_families);
 
         Ok(storage)
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+    /// Creates the memtables of the given column families for a new WAL generation, which all log
+    /// to the same WAL. The memtable of the default column family has the id of the generation,
+    /// the others get ids from `next_id`.
+    fn create_memtables(
+        backend: &dyn StorageBackend,
+        path: &Path,
+        enable_wal: bool,
+        generation: usize,
+        column_families: impl IntoIterator<Item = u32>,
+        mut next_id: impl FnMut() -> usize,
+    ) -> Result<Vec<Arc<MemTable>>> {
+        let wal = if enable_wal {
+            Some(Wal::create_with_backend(
+                backend,
+                Self::path_of_wal_static(path, generation),
+            )?)
+        } else {
+            None
+        };
+        let mut memtables = Vec::new();
+        for cf_id in column_families {
+            let id = if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+                generation
+            } else {
+                next_id()
+            };
+            memtables.push(Arc::new(match &wal {
+                Some(wal) => {
+                    MemTable::create_with_shared_wal(id, generation, wal.for_column_family(cf_id))
+                }
+                None => MemTable::create(id),
+            }));
+        }
+        Ok(memtables)
+    }
+
+    pub fn add_compaction_filter(&self, compaction_filter: Arc<dyn CompactionFilter>) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
     }
 
     pub fn sync(&self) -> Result<()> {
-        self.state.read().memtable.sync_wal()
+        let memtable = self.state.read().memtable.clone();
+        self.sync_wal(&memtable)
+    }
+
+    /// Syncs the WAL of a memtable, if it has one.
+    fn sync_wal(&self, memtable: &MemTable) -> Result<()> {
+        if memtable.wal().is_some() {
+            memtable.sync_wal()?;
+            self.statistics.record(Ticker::WalSyncs, 1);
+        }
+        Ok(())
     }
 
     /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
     pub fn get(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf(self.default_cf(), key)
+    }
+
+    pub fn get_cf(self: &Arc<Self>, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
         let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-        txn.get(key)
+        txn.get_cf(cf, key)
     }
 
-    pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+    pub(crate) fn get_with_ts(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        read_ts: u64,
+    ) -> Result<Option<Bytes>> {
+        let start = Instant::now();
         let snapshot = {
-            let guard = self.state.read();
+            let guard = cf.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
@@ -492,8 +1149,10 @@ impl LsmStorageInner {
             ) {
                 if let Some(bloom) = &table.bloom {
                     if bloom.may_contain(farmhash::fingerprint32(key)) {
+                        self.statistics.record(Ticker::BloomFilterPositive, 1);
                         return true;
                     }
+                    self.statistics.record(Ticker::BloomFilterUseful, 1);
                 } else {
                     return true;
                 }
@@ -533,105 +1192,273 @@ impl LsmStorageInner {
                 MergeIterator::create(level_iters),
             )?,
             Bound::Unbounded,
+            Bound::Unbounded,
             read_ts,
+            snapshot.range_tombstones(Bound::Included(key), Bound::Included(key), read_ts),
+            self.options.ttl.as_ref().map(TtlOptions::now_millis),
         )?;
 
-        if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        let value = if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
+            Some(Bytes::copy_from_slice(iter.value()))
+        } else {
+            None
+        };
+        self.statistics.record_latency(HistogramType::Get, start);
+        Ok(value)
+    }
+
+    /// Writes a batch to the memtables of the column families under a single commit timestamp.
+    ///
+    /// Concurrent batches are committed in groups, with one WAL append for the whole group.
+    pub fn write_batch_inner<'a, T: AsRef<[u8]> + 'a>(
+        &self,
+        batch: impl IntoIterator<Item = (&'a ColumnFamily, &'a WriteBatchRecord<T>)>,
+        options: &WriteOptions,
+    ) -> Result<u64> {
+        let expiry = match &self.options.ttl {
+            Some(ttl_options) => Some(ttl_options.expiry_time(options.ttl)),
+            None if options.ttl.is_some() => bail!("TTL is not enabled for this DB"),
+            None => None,
+        };
+        // Validate the batch before queueing it, so that a bad batch never fails a whole group.
+        let batch = batch
+            .into_iter()
+            .map(|(cf, record)| {
+                let record = match record {
+                    WriteBatchRecord::Del(key) => {
+                        let key = key.as_ref();
+                        assert!(!key.is_empty(), "key cannot be empty");
+                        WriteBatchRecord::Del(Bytes::copy_from_slice(key))
+                    }
+                    WriteBatchRecord::Put(key, value) => {
+                        let key = key.as_ref();
+                        let value = value.as_ref();
+                        assert!(!key.is_empty(), "key cannot be empty");
+                        assert!(!value.is_empty(), "value cannot be empty");
+                        let value = match expiry {
+                            Some(expiry) => ttl::encode_value(value, expiry),
+                            None => Bytes::copy_from_slice(value),
+                        };
+                        WriteBatchRecord::Put(Bytes::copy_from_slice(key), value)
+                    }
+                    WriteBatchRecord::DelRange(start, end) => {
+                        let start = start.as_ref();
+                        let end = end.as_ref();
+                        assert!(!start.is_empty(), "key cannot be empty");
+                        assert!(start < end, "range cannot be empty");
+                        WriteBatchRecord::DelRange(
+                            Bytes::copy_from_slice(start),
+                            Bytes::copy_from_slice(end),
+                        )
+                    }
+                };
+                (cf.id(), record)
+            })
+            .collect::<Vec<_>>();
+        let mut cf_ids = batch.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        cf_ids.sort_unstable();
+        cf_ids.dedup();
+
+        self.background_error.check_writable()?;
+        let start = Instant::now();
+        self.write_controller.wait(&self.background_error)?;
+        let write = PendingWrite::new(batch, options.sync);
+        let ts = self
+            .write_queue
+            .write(write, |group| self.commit_write_group(group))?;
+
+        // Freezing takes the write lock to switch all column families to a new WAL at once.
+        for id in cf_ids {
+            let cf = self.column_family_by_id(id).expect("unknown column family");
+            let size = cf.state.read().memtable.approximate_size();
+            self.try_freeze(cf, size)?;
         }
-        Ok(None)
+        self.statistics.record_latency(HistogramType::Write, start);
+        Ok(ts)
     }
 
-    pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
+    /// Commits a group of write batches under consecutive timestamps, which is done by the leader
+    /// of the write queue. The whole group is logged with a single WAL append.
+    fn commit_write_group(&self, group: &[Arc<PendingWrite>]) -> Result<Vec<u64>> {
         let _lck = self.mvcc().write_lock.lock();
-        let ts = self.mvcc().latest_commit_ts() + 1;
-        for record in batch {
-            match record {
-                WriteBatchRecord::Del(key) => {
-                    let key = key.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
-                        size = guard.memtable.approximate_size();
+        let first_ts = self.mvcc().latest_commit_ts() + 1;
+        let timestamps = (first_ts..first_ts + group.len() as u64).collect::<Vec<_>>();
+
+        // The memtables of all column families share one WAL, which is only switched while the
+        // write lock is held.
+        let memtable = self.default_cf().state.read().memtable.clone();
+        if let Some(wal) = memtable.wal() {
+            let mut buf = Vec::new();
+            for (write, &ts) in group.iter().zip(&timestamps) {
+                // Each write batch is logged as one WAL batch, which is recovered all-or-nothing.
+                let mut batch = WalBatch::new(ts);
+                for (cf_id, record) in &write.batch {
+                    match record {
+                        WriteBatchRecord::Del(key) => batch.put(*cf_id, key, b""),
+                        WriteBatchRecord::Put(key, value) => batch.put(*cf_id, key, value),
+                        WriteBatchRecord::DelRange(start, end) => {
+                            batch.delete_range(*cf_id, start, end)
+                        }
                     }
-                    self.try_freeze(size)?;
                 }
-                WriteBatchRecord::Put(key, value) => {
-                    let key = key.as_ref();
-                    let value = value.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
-                        size = guard.memtable.approximate_size();
+                batch.encode(&mut buf);
+            }
+            let sync = group.iter().any(|write| write.sync);
+            wal.append(&buf, sync)?;
+            if sync {
+                self.statistics.record(Ticker::WalSyncs, 1);
+            }
+        }
+
+        for (write, &ts) in group.iter().zip(&timestamps) {
+            for (cf_id, record) in &write.batch {
+                let cf = self
+                    .column_family_by_id(*cf_id)
+                    .expect("unknown column family");
+                let guard = cf.state.read();
+                match record {
+                    WriteBatchRecord::Del(key) => {
+                        guard.memtable.insert(KeySlice::from_slice(key, ts), b"")
                     }
-                    self.try_freeze(size)?;
+                    WriteBatchRecord::Put(key, value) => {
+                        guard.memtable.insert(KeySlice::from_slice(key, ts), value)
+                    }
+                    WriteBatchRecord::DelRange(start, end) => guard
+                        .memtable
+                        .insert_range_tombstone(KeySlice::from_slice(start, ts), end),
                 }
             }
         }
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        self.mvcc().update_commit_ts(*timestamps.last().unwrap());
+        Ok(timestamps)
     }
 
     pub fn write_batch<T: AsRef<[u8]>>(
         self: &Arc<Self>,
         batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.write_batch_opt(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_opt<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        let cf = self.default_cf();
+        self.write_batch_records(batch.iter().map(|record| (&**cf, record)), options)
+    }
+
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        self.write_batch_cf_opt(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_cf_opt<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.write_batch_records(batch.iter().map(|(cf, record)| (*cf, record)), options)
+    }
+
+    fn write_batch_records<'a, T: AsRef<[u8]> + 'a>(
+        self: &Arc<Self>,
+        batch: impl IntoIterator<Item = (&'a ColumnFamily, &'a WriteBatchRecord<T>)>,
+        options: &WriteOptions,
     ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(batch)?;
+            self.write_batch_inner(batch, options)?;
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            for record in batch {
+            for (cf, record) in batch {
                 match record {
                     WriteBatchRecord::Del(key) => {
-                        txn.delete(key.as_ref());
+                        txn.delete_cf(cf, key.as_ref());
                     }
                     WriteBatchRecord::Put(key, value) => {
-                        txn.put(key.as_ref(), value.as_ref());
+                        txn.put_cf(cf, key.as_ref(), value.as_ref());
+                    }
+                    WriteBatchRecord::DelRange(_, _) => {
+                        bail!("range deletions cannot be part of a serializable write batch");
                     }
                 }
             }
-            txn.commit()?;
+            txn.commit_opt(options)?;
         }
         Ok(())
     }
 
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(self: &Arc<Self>, key: &[u8], value: &[u8]) -> Result<()> {
-        if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Put(key, value)])?;
-        } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            txn.put(key, value);
-            txn.commit()?;
-        }
-        Ok(())
+        self.put_cf(self.default_cf(), key, value)
+    }
+
+    pub fn put_opt(
+        self: &Arc<Self>,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.write_batch_opt(&[WriteBatchRecord::Put(key, value)], options)
+    }
+
+    /// Put a key-value pair that expires after `ttl`.
+    pub fn put_with_ttl(self: &Arc<Self>, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        let options = WriteOptions {
+            ttl: Some(ttl),
+            ..Default::default()
+        };
+        self.put_opt(key, value, &options)
+    }
+
+    pub fn put_cf(self: &Arc<Self>, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch_records(
+            [(cf, &WriteBatchRecord::Put(key, value))],
+            &WriteOptions::default(),
+        )
     }
 
     /// Remove a key from the storage by writing an empty value.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
-        if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Del(key)])?;
-        } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            txn.delete(key);
-            txn.commit()?;
-        }
+        self.delete_cf(self.default_cf(), key)
+    }
+
+    pub fn delete_opt(self: &Arc<Self>, key: &[u8], options: &WriteOptions) -> Result<()> {
+        self.write_batch_opt(&[WriteBatchRecord::Del(key)], options)
+    }
+
+    pub fn delete_cf(self: &Arc<Self>, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.write_batch_records(
+            [(cf, &WriteBatchRecord::Del(key))],
+            &WriteOptions::default(),
+        )
+    }
+
+    /// Remove all keys in `start..end` by writing a range tombstone. Range deletions are not
+    /// tracked by the conflict detection of serializable transactions.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.delete_range_cf(self.default_cf(), start, end)
+    }
+
+    pub fn delete_range_cf(&self, cf: &ColumnFamily, start: &[u8], end: &[u8]) -> Result<()> {
+        self.write_batch_inner(
+            [(cf, &WriteBatchRecord::DelRange(start, end))],
+            &WriteOptions::default(),
+        )?;
         Ok(())
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
-        if estimated_size >= self.options.target_sst_size {
+    fn try_freeze(&self, cf: &ColumnFamily, estimated_size: usize) -> Result<()> {
+        if estimated_size >= cf.options.target_sst_size {
             let state_lock = self.state_lock.lock();
-            let guard = self.state.read();
+            let guard = cf.state.read();
             // the memtable could have already been frozen, check again to ensure we really need to freeze
-            if guard.memtable.approximate_size() >= self.options.target_sst_size {
+            if guard.memtable.approximate_size() >= cf.options.target_sst_size {
                 drop(guard);
-                self.force_freeze_memtable(&state_lock)?;
+                self.freeze_memtables(Some(cf), &state_lock)?;
             }
         }
         Ok(())
@@ -645,6 +1472,47 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
+    pub(crate) fn path_of_vlog_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.vlog", id))
+    }
+
+    pub(crate) fn path_of_vlog(&self, id: usize) -> PathBuf {
+        Self::path_of_vlog_static(&self.path, id)
+    }
+
+    /// Creates an SST builder for `level` of a column family that stores large values in a new
+    /// value log file.
+    pub(crate) fn new_sst_builder(&self, cf: &ColumnFamily, level: usize) -> SsTableBuilder {
+        let mut builder = SsTableBuilder::new(cf.options.block_size);
+        builder.set_compression(cf.options.compression_for_level(level));
+        builder.set_value_log(self.value_log.new_writer(self.next_sst_id()));
+        if let Some(extractor) = cf.options.prefix_extractor {
+            builder.set_prefix_extractor(extractor);
+        }
+        builder
+    }
+
+    /// Writes the SST of a flush or a compaction, which reads its blocks through the block cache.
+    /// The writes are charged to the rate limiter, if any, at `priority`.
+    pub(crate) fn build_sst(
+        &self,
+        mut builder: SsTableBuilder,
+        sst_id: usize,
+        priority: IoPriority,
+    ) -> Result<Arc<SsTable>> {
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            builder.set_rate_limiter(rate_limiter.clone(), priority);
+        }
+        let mut sst = builder.build_with_backend(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.backend(),
+            self.path_of_sst(sst_id),
+        )?;
+        sst.attach_statistics(&self.statistics);
+        Ok(Arc::new(sst))
+    }
+
     pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
@@ -654,57 +1522,90 @@ impl LsmStorageInner {
     }
 
     pub(super) fn sync_dir(&self) -> Result<()> {
-        File::open(&self.path)?.sync_all()?;
-        Ok(())
+        self.backend().sync_dir(&self.path)
     }
 
-    fn freeze_memtable_with_memtable(&self, memtable: Arc<MemTable>) -> Result<()> {
-        let mut guard = self.state.write();
-        // Swap the current memtable with a new one.
-        let mut snapshot = guard.as_ref().clone();
-        let old_memtable = std::mem::replace(&mut snapshot.memtable, memtable);
-        // Add the memtable to the immutable memtables.
-        snapshot.imm_memtables.insert(0, old_memtable.clone());
-        // Update the snapshot.
-        *guard = Arc::new(snapshot);
-
-        drop(guard);
-        old_memtable.sync_wal()?;
-
-        Ok(())
+    /// Whether the current memtable of any column family has data.
+    pub(crate) fn has_unfrozen_data(&self) -> bool {
+        self.column_families
+            .iter()
+            .any(|cf| !cf.state.read().memtable.is_empty())
     }
 
-    /// Force freeze the current memtable to an immutable memtable
-    pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
-        let memtable_id = self.next_sst_id();
-        let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
-                memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
-        } else {
-            Arc::new(MemTable::create(memtable_id))
-        };
+    /// Switches all column families to new memtables logging to a new WAL. The memtable of
+    /// `trigger` is frozen even if empty, while empty memtables of other column families are
+    /// dropped.
+    pub(crate) fn freeze_memtables(
+        &self,
+        trigger: Option<&ColumnFamily>,
+        state_lock_observer: &MutexGuard<'_, ()>,
+    ) -> Result<()> {
+        let generation = self.next_sst_id();
+        let memtables = Self::create_memtables(
+            self.backend(),
+            &self.path,
+            self.options.enable_wal,
+            generation,
+            self.column_families.iter().map(|cf| cf.id()),
+            || self.next_sst_id(),
+        )?;
 
-        self.freeze_memtable_with_memtable(memtable)?;
+        let mut frozen = Vec::new();
+        {
+            // Block writes, so that a write batch never spans two WALs.
+            let _lck = self.mvcc().write_lock.lock();
+            for (cf, memtable) in self.column_families.iter().zip(memtables) {
+                let mut guard = cf.state.write();
+                // Swap the current memtable with a new one.
+                let mut snapshot = guard.as_ref().clone();
+                let old_memtable = std::mem::replace(&mut snapshot.memtable, memtable);
+                // Add the memtable to the immutable memtables.
+                if !old_memtable.is_empty() || trigger.is_some_and(|x| x.id() == cf.id()) {
+                    snapshot.imm_memtables.insert(0, old_memtable.clone());
+                    frozen.push(old_memtable);
+                }
+                // Update the snapshot.
+                *guard = Arc::new(snapshot);
+            }
+        }
+        self.write_controller.update(&self.column_families);
+        // The frozen memtables share one WAL.
+        if let Some(memtable) = frozen.first() {
+            self.sync_wal(memtable)?;
+        }
 
-        self.manifest().add_record(
-            state_lock_observer,
-            ManifestRecord::NewMemtable(memtable_id),
-        )?;
+        self.add_manifest_record(state_lock_observer, ManifestRecord::NewMemtable(generation))?;
         self.sync_dir()?;
 
         Ok(())
     }
 
-    /// Force flush the earliest-created immutable memtable to disk
+    /// Force freeze the current memtable of the default column family to an immutable memtable
+    #[cfg(test)]
+    pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
+        self.freeze_memtables(Some(self.default_cf()), state_lock_observer)
+    }
+
+    /// Force flush the earliest-created immutable memtable of the default column family to disk
+    #[cfg(test)]
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
-        let state_lock = self.state_lock.lock();
+        self.flush_next_imm_memtable(self.default_cf())
+    }
+
+    /// Flush the earliest-created immutable memtable of a column family to disk
+    pub(crate) fn flush_next_imm_memtable(&self, cf: &ColumnFamily) -> Result<()> {
+        self.flush_next_imm_memtable_with_lock(cf, &self.state_lock.lock())
+    }
 
+    pub(crate) fn flush_next_imm_memtable_with_lock(
+        &self,
+        cf: &ColumnFamily,
+        state_lock: &MutexGuard<'_, ()>,
+    ) -> Result<()> {
         let flush_memtable;
 
         {
-            let guard = self.state.read();
+            let guard = cf.state.read();
             flush_memtable = guard
                 .imm_memtables
                 .last()
@@ -712,73 +1613,154 @@ impl LsmStorageInner {
                 .clone();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
+        let mut builder = self.new_sst_builder(cf, 0);
         flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+        let sst = self.build_sst(builder, sst_id, IoPriority::High)?;
+        self.statistics
+            .record(Ticker::BytesFlushed, sst.table_size());
 
         // Add the flushed L0 table to the list.
         {
-            let mut guard = self.state.write();
+            let mut guard = cf.state.write();
             let mut snapshot = guard.as_ref().clone();
             // Remove the memtable from the immutable memtables.
             let mem = snapshot.imm_memtables.pop().unwrap();
             assert_eq!(mem.id(), sst_id);
             // Add L0 table
-            if self.compaction_controller.flush_to_l0() {
+            if cf.compaction_controller.flush_to_l0() {
                 // In leveled compaction or no compaction, simply flush to L0
                 snapshot.l0_sstables.insert(0, sst_id);
             } else {
                 // In tiered compaction, create a new tier
                 snapshot.levels.insert(0, (sst_id, vec![sst_id]));
             }
-            println!("flushed {}.sst with size={}", sst_id, sst.table_size());
-            snapshot.sstables.insert(sst_id, sst);
+            log::info!("flushed {}.sst with size={}", sst_id, sst.table_size());
+            snapshot.sstables.insert(sst_id, sst.clone());
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        self.write_controller.update(&self.column_families);
 
-        if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
-        }
+        let wal_id = flush_memtable.wal_id();
+        self.add_manifest_record(state_lock, ManifestRecord::flush(cf.id(), wal_id, sst_id))?;
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        // The WAL is shared with the memtables of other column families.
+        if self.options.enable_wal && !self.is_wal_in_use(wal_id) {
+            self.backend().delete(&self.path_of_wal(wal_id))?;
+        }
 
         self.sync_dir()?;
 
+        let info = FlushJobInfo {
+            column_family: cf.name().to_string(),
+            sst_id,
+            file_size: sst.table_size(),
+        };
+        self.notify(|listener| listener.on_flush_completed(&info));
+
         Ok(())
     }
 
+    /// Whether a memtable of any column family still logs to the WAL `wal_id`.
+    fn is_wal_in_use(&self, wal_id: usize) -> bool {
+        self.column_families.iter().any(|cf| {
+            let state = cf.state.read();
+            std::iter::once(&state.memtable)
+                .chain(state.imm_memtables.iter())
+                .any(|memtable| memtable.wal_id() == wal_id)
+        })
+    }
+
     pub fn new_txn(self: &Arc<Self>) -> Result<Arc<Transaction>> {
         Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
     }
 
+    /// Create a read-only snapshot at the latest commit.
+    pub fn snapshot(self: &Arc<Self>) -> Snapshot {
+        Snapshot::new(self.mvcc().new_txn(self.clone(), false))
+    }
+
+    /// Create a read-only snapshot at `ts`, which must be within the retained history.
+    pub fn snapshot_at(self: &Arc<Self>, ts: u64) -> Result<Snapshot> {
+        let txn = self.mvcc().new_txn_at(self.clone(), ts, false)?;
+        Ok(Snapshot::new(txn))
+    }
+
+    pub fn get_at(self: &Arc<Self>, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.snapshot_at(ts)?.get(key)
+    }
+
+    pub fn scan_at(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<TxnIterator> {
+        self.snapshot_at(ts)?.scan(lower, upper)
+    }
+
     /// Create an iterator over a range of keys.
     pub fn scan<'a>(
         self: &'a Arc<Self>,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_cf(self.default_cf(), lower, upper)
+    }
+
+    pub fn scan_cf(
+        self: &Arc<Self>,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.scan_cf(cf, lower, upper)
+    }
+
+    /// Create an iterator over the keys that start with `prefix`.
+    pub fn scan_prefix(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        self.scan_prefix_cf(self.default_cf(), prefix)
+    }
+
+    pub fn scan_prefix_cf(
+        self: &Arc<Self>,
+        cf: &ColumnFamily,
+        prefix: &[u8],
     ) -> Result<TxnIterator> {
         let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-        txn.scan(lower, upper)
+        txn.scan_prefix_cf(cf, prefix)
     }
 
+    /// Create an iterator over a range of keys of a column family at `read_ts`. If `prefix` is
+    /// given, all keys in the range start with it, and SSTs whose prefix bloom filter rules it out
+    /// are skipped.
     pub(crate) fn scan_with_ts(
         &self,
+        cf: &ColumnFamily,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
         read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
+        let start = Instant::now();
         let snapshot = {
-            let guard = self.state.read();
+            let guard = cf.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
+        // All keys starting with `prefix` share its extracted prefix.
+        let extracted_prefix = cf
+            .options
+            .prefix_extractor
+            .as_ref()
+            .and_then(|extractor| Some((extractor, extractor.extract(prefix?)?)));
+        let may_contain_prefix = |table: &SsTable| match extracted_prefix {
+            Some((extractor, prefix)) => table.may_contain_prefix(extractor, prefix),
+            None => true,
+        };
+
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(
             map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
@@ -800,7 +1782,8 @@ impl LsmStorageInner {
                 upper,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
-            ) {
+            ) && may_contain_prefix(&table)
+            {
                 let iter = match lower {
                     Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
                         table,
@@ -834,7 +1817,8 @@ impl LsmStorageInner {
                     upper,
                     table.first_key().as_key_slice(),
                     table.last_key().as_key_slice(),
-                ) {
+                ) && may_contain_prefix(&table)
+                {
                     level_ssts.push(table);
                 }
             }
@@ -862,10 +1846,15 @@ impl LsmStorageInner {
         let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
         let iter = TwoMergeIterator::create(iter, MergeIterator::create(level_iters))?;
 
-        Ok(FusedIterator::new(LsmIterator::new(
+        let iter = LsmIterator::new(
             iter,
+            map_bound(lower),
             map_bound(upper),
             read_ts,
-        )?))
+            snapshot.range_tombstones(lower, upper, read_ts),
+            self.options.ttl.as_ref().map(TtlOptions::now_millis),
+        )?;
+        self.statistics.record_latency(HistogramType::Scan, start);
+        Ok(FusedIterator::new(iter))
     }
 }
@@ -1,6 +1,5 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
@@ -8,62 +7,575 @@ use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
-use crate::compact::CompactionTask;
+use crate::backend::{StorageBackend, StorageFile};
+use crate::background_error::ManifestWriteError;
+use crate::block::{get_varint, put_varint};
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::compact::{
+    CompactionTask, LeveledCompactionTask, SimpleLeveledCompactionTask, TieredCompactionTask,
+};
+use crate::event_listener::BackgroundErrorReason;
+use crate::lsm_storage::LsmStorageInner;
+use crate::recovery::{DroppedTail, RecoveryMode};
 
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    backend: Arc<dyn StorageBackend>,
+    dir: PathBuf,
+    file: Arc<Mutex<ManifestFile>>,
 }
 
+/// A change to the LSM state. `Flush` and `Compaction` apply to the default column family, the
+/// `ColumnFamily*` records to the column family with the given id.
 #[derive(Serialize, Deserialize)]
 pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    CreateColumnFamily(u32, String),
+    /// A memtable logged to a WAL was flushed to an SST: `(column family, WAL id, SST id)`.
+    ColumnFamilyFlush(u32, usize, usize),
+    ColumnFamilyCompaction(u32, CompactionTask, Vec<usize>),
+    /// The SSTs of a column family: `(column family, L0 SSTs, levels)`. Replaces the SSTs added by
+    /// the records before it.
+    ColumnFamilySnapshot(u32, Vec<usize>, Vec<(usize, Vec<usize>)>),
+    /// The memtable of a column family logged to a WAL has been flushed: `(column family, WAL
+    /// id)`. Used instead of the flush records when rotating the manifest.
+    ColumnFamilyWalFlushed(u32, usize),
+    /// Whether values are stored with their expiry time, i.e. whether `LsmStorageOptions::ttl` was
+    /// set when the DB was created.
+    Ttl(bool),
+}
+
+impl ManifestRecord {
+    /// The record of flushing the memtable logged to WAL `wal_id` of a column family.
+    pub(crate) fn flush(column_family: u32, wal_id: usize, sst_id: usize) -> Self {
+        if column_family == DEFAULT_COLUMN_FAMILY_ID {
+            // The memtables of the default column family have the id of their WAL.
+            Self::Flush(sst_id)
+        } else {
+            Self::ColumnFamilyFlush(column_family, wal_id, sst_id)
+        }
+    }
+
+    /// The record of a compaction of a column family.
+    pub(crate) fn compaction(column_family: u32, task: CompactionTask, output: Vec<usize>) -> Self {
+        if column_family == DEFAULT_COLUMN_FAMILY_ID {
+            Self::Compaction(task, output)
+        } else {
+            Self::ColumnFamilyCompaction(column_family, task, output)
+        }
+    }
+}
+
+/// Magic number at the beginning of versioned manifest files. Files without it were written in the
+/// legacy format.
+const MANIFEST_MAGIC: u32 = 0x4d4c_4d46;
+/// The original format: each record is `len: u64 | JSON | crc32`, no header.
+const MANIFEST_FORMAT_LEGACY: u32 = 1;
+/// Each record is `len: u32 | binary record | crc32`, and the file starts with a `magic | version`
+/// header.
+const MANIFEST_FORMAT_V2: u32 = 2;
+
+const RECORD_FLUSH: u8 = 0;
+const RECORD_NEW_MEMTABLE: u8 = 1;
+const RECORD_COMPACTION: u8 = 2;
+const RECORD_CREATE_COLUMN_FAMILY: u8 = 3;
+const RECORD_COLUMN_FAMILY_FLUSH: u8 = 4;
+const RECORD_COLUMN_FAMILY_COMPACTION: u8 = 5;
+const RECORD_COLUMN_FAMILY_SNAPSHOT: u8 = 6;
+const RECORD_COLUMN_FAMILY_WAL_FLUSHED: u8 = 7;
+const RECORD_TTL: u8 = 8;
+
+const TASK_LEVELED: u8 = 0;
+const TASK_TIERED: u8 = 1;
+const TASK_SIMPLE: u8 = 2;
+const TASK_FORCE_FULL_COMPACTION: u8 = 3;
+
+fn put_ids(buf: &mut Vec<u8>, ids: &[usize]) {
+    put_varint(buf, ids.len() as u64);
+    for id in ids {
+        put_varint(buf, *id as u64);
+    }
+}
+
+fn get_ids(buf: &mut &[u8]) -> Vec<usize> {
+    let len = get_varint(buf) as usize;
+    (0..len).map(|_| get_varint(buf) as usize).collect()
+}
+
+fn put_levels(buf: &mut Vec<u8>, levels: &[(usize, Vec<usize>)]) {
+    put_varint(buf, levels.len() as u64);
+    for (level, ids) in levels {
+        put_varint(buf, *level as u64);
+        put_ids(buf, ids);
+    }
+}
+
+fn get_levels(buf: &mut &[u8]) -> Vec<(usize, Vec<usize>)> {
+    let len = get_varint(buf) as usize;
+    (0..len)
+        .map(|_| (get_varint(buf) as usize, get_ids(buf)))
+        .collect()
+}
+
+/// Encodes the fields shared by leveled and simple leveled compaction tasks. An upper level of
+/// `None` is encoded as 0.
+fn put_leveled_task(
+    buf: &mut Vec<u8>,
+    upper_level: Option<usize>,
+    upper_level_sst_ids: &[usize],
+    lower_level: usize,
+    lower_level_sst_ids: &[usize],
+    is_lower_level_bottom_level: bool,
+) {
+    put_varint(buf, upper_level.map_or(0, |level| level as u64 + 1));
+    put_ids(buf, upper_level_sst_ids);
+    put_varint(buf, lower_level as u64);
+    put_ids(buf, lower_level_sst_ids);
+    buf.put_u8(is_lower_level_bottom_level as u8);
+}
+
+type LeveledTaskFields = (Option<usize>, Vec<usize>, usize, Vec<usize>, bool);
+
+fn get_leveled_task(buf: &mut &[u8]) -> LeveledTaskFields {
+    let upper_level = get_varint(buf).checked_sub(1).map(|level| level as usize);
+    let upper_level_sst_ids = get_ids(buf);
+    let lower_level = get_varint(buf) as usize;
+    let lower_level_sst_ids = get_ids(buf);
+    let is_lower_level_bottom_level = buf.get_u8() != 0;
+    (
+        upper_level,
+        upper_level_sst_ids,
+        lower_level,
+        lower_level_sst_ids,
+        is_lower_level_bottom_level,
+    )
+}
+
+fn put_task(buf: &mut Vec<u8>, task: &CompactionTask) {
+    match task {
+        CompactionTask::Leveled(task) => {
+            buf.put_u8(TASK_LEVELED);
+            put_leveled_task(
+                buf,
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                task.lower_level,
+                &task.lower_level_sst_ids,
+                task.is_lower_level_bottom_level,
+            );
+        }
+        CompactionTask::Tiered(task) => {
+            buf.put_u8(TASK_TIERED);
+            put_levels(buf, &task.tiers);
+            buf.put_u8(task.bottom_tier_included as u8);
+        }
+        CompactionTask::Simple(task) => {
+            buf.put_u8(TASK_SIMPLE);
+            put_leveled_task(
+                buf,
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                task.lower_level,
+                &task.lower_level_sst_ids,
+                task.is_lower_level_bottom_level,
+            );
+        }
+        CompactionTask::ForceFullCompaction {
+            l0_sstables,
+            l1_sstables,
+        } => {
+            buf.put_u8(TASK_FORCE_FULL_COMPACTION);
+            put_ids(buf, l0_sstables);
+            put_ids(buf, l1_sstables);
+        }
+    }
+}
+
+fn get_task(buf: &mut &[u8]) -> Result<CompactionTask> {
+    let task = match buf.get_u8() {
+        TASK_LEVELED => {
+            let (
+                upper_level,
+                upper_level_sst_ids,
+                lower_level,
+                lower_level_sst_ids,
+                is_lower_level_bottom_level,
+            ) = get_leveled_task(buf);
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level,
+                lower_level_sst_ids,
+                is_lower_level_bottom_level,
+            })
+        }
+        TASK_TIERED => CompactionTask::Tiered(TieredCompactionTask {
+            tiers: get_levels(buf),
+            bottom_tier_included: buf.get_u8() != 0,
+        }),
+        TASK_SIMPLE => {
+            let (
+                upper_level,
+                upper_level_sst_ids,
+                lower_level,
+                lower_level_sst_ids,
+                is_lower_level_bottom_level,
+            ) = get_leveled_task(buf);
+            CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level,
+                lower_level_sst_ids,
+                is_lower_level_bottom_level,
+            })
+        }
+        TASK_FORCE_FULL_COMPACTION => CompactionTask::ForceFullCompaction {
+            l0_sstables: get_ids(buf),
+            l1_sstables: get_ids(buf),
+        },
+        kind => bail!("unknown compaction task kind {}", kind),
+    };
+    Ok(task)
+}
+
+impl ManifestRecord {
+    /// Encodes the record in the binary format.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ManifestRecord::Flush(sst_id) => {
+                buf.put_u8(RECORD_FLUSH);
+                put_varint(buf, *sst_id as u64);
+            }
+            ManifestRecord::NewMemtable(id) => {
+                buf.put_u8(RECORD_NEW_MEMTABLE);
+                put_varint(buf, *id as u64);
+            }
+            ManifestRecord::Compaction(task, output) => {
+                buf.put_u8(RECORD_COMPACTION);
+                put_task(buf, task);
+                put_ids(buf, output);
+            }
+            ManifestRecord::CreateColumnFamily(cf_id, name) => {
+                buf.put_u8(RECORD_CREATE_COLUMN_FAMILY);
+                put_varint(buf, *cf_id as u64);
+                put_varint(buf, name.len() as u64);
+                buf.put_slice(name.as_bytes());
+            }
+            ManifestRecord::ColumnFamilyFlush(cf_id, wal_id, sst_id) => {
+                buf.put_u8(RECORD_COLUMN_FAMILY_FLUSH);
+                put_varint(buf, *cf_id as u64);
+                put_varint(buf, *wal_id as u64);
+                put_varint(buf, *sst_id as u64);
+            }
+            ManifestRecord::ColumnFamilyCompaction(cf_id, task, output) => {
+                buf.put_u8(RECORD_COLUMN_FAMILY_COMPACTION);
+                put_varint(buf, *cf_id as u64);
+                put_task(buf, task);
+                put_ids(buf, output);
+            }
+            ManifestRecord::ColumnFamilySnapshot(cf_id, l0_sstables, levels) => {
+                buf.put_u8(RECORD_COLUMN_FAMILY_SNAPSHOT);
+                put_varint(buf, *cf_id as u64);
+                put_ids(buf, l0_sstables);
+                put_levels(buf, levels);
+            }
+            ManifestRecord::ColumnFamilyWalFlushed(cf_id, wal_id) => {
+                buf.put_u8(RECORD_COLUMN_FAMILY_WAL_FLUSHED);
+                put_varint(buf, *cf_id as u64);
+                put_varint(buf, *wal_id as u64);
+            }
+            ManifestRecord::Ttl(enabled) => {
+                buf.put_u8(RECORD_TTL);
+                buf.put_u8(*enabled as u8);
+            }
+        }
+    }
+
+    /// Decodes a record written by `encode`.
+    fn decode(mut buf: &[u8]) -> Result<Self> {
+        let buf = &mut buf;
+        let record = match buf.get_u8() {
+            RECORD_FLUSH => ManifestRecord::Flush(get_varint(buf) as usize),
+            RECORD_NEW_MEMTABLE => ManifestRecord::NewMemtable(get_varint(buf) as usize),
+            RECORD_COMPACTION => ManifestRecord::Compaction(get_task(buf)?, get_ids(buf)),
+            RECORD_CREATE_COLUMN_FAMILY => {
+                let cf_id = get_varint(buf) as u32;
+                let len = get_varint(buf) as usize;
+                let name = String::from_utf8(buf.copy_to_bytes(len).to_vec())
+                    .context("invalid column family name")?;
+                ManifestRecord::CreateColumnFamily(cf_id, name)
+            }
+            RECORD_COLUMN_FAMILY_FLUSH => ManifestRecord::ColumnFamilyFlush(
+                get_varint(buf) as u32,
+                get_varint(buf) as usize,
+                get_varint(buf) as usize,
+            ),
+            RECORD_COLUMN_FAMILY_COMPACTION => ManifestRecord::ColumnFamilyCompaction(
+                get_varint(buf) as u32,
+                get_task(buf)?,
+                get_ids(buf),
+            ),
+            RECORD_COLUMN_FAMILY_SNAPSHOT => ManifestRecord::ColumnFamilySnapshot(
+                get_varint(buf) as u32,
+                get_ids(buf),
+                get_levels(buf),
+            ),
+            RECORD_COLUMN_FAMILY_WAL_FLUSHED => ManifestRecord::ColumnFamilyWalFlushed(
+                get_varint(buf) as u32,
+                get_varint(buf) as usize,
+            ),
+            RECORD_TTL => ManifestRecord::Ttl(buf.get_u8() != 0),
+            kind => bail!("unknown manifest record kind {}", kind),
+        };
+        if buf.has_remaining() {
+            bail!("{} trailing bytes in manifest record", buf.remaining());
+        }
+        Ok(record)
+    }
+}
+
+/// The name of the file that points to the current manifest.
+const CURRENT: &str = "CURRENT";
+/// The manifest of databases created before manifests were rotated.
+const LEGACY_MANIFEST: &str = "MANIFEST";
+
+struct ManifestFile {
+    id: usize,
+    file: Arc<dyn StorageFile>,
+    /// The format the file was created with. Records appended to it use the same format.
+    version: u32,
+    /// The number of bytes written to the file.
+    size: u64,
 }
 
 impl Manifest {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    fn path_of_manifest(dir: &Path, id: usize) -> PathBuf {
+        if id == 0 {
+            dir.join(LEGACY_MANIFEST)
+        } else {
+            dir.join(format!("{}-{:05}", LEGACY_MANIFEST, id))
+        }
+    }
+
+    /// Writes a new manifest file with the given records, and makes it the current manifest.
+    fn create_file(
+        backend: &dyn StorageBackend,
+        dir: &Path,
+        id: usize,
+        records: impl IntoIterator<Item = ManifestRecord>,
+    ) -> Result<ManifestFile> {
+        let file = backend
+            .create(&Self::path_of_manifest(dir, id))
+            .context("failed to create manifest")?;
+        let mut buf = Vec::new();
+        buf.put_u32(MANIFEST_MAGIC);
+        buf.put_u32(MANIFEST_FORMAT_V2);
+        for record in records {
+            Self::encode_record(&record, MANIFEST_FORMAT_V2, &mut buf)?;
+        }
+        file.append(&buf)?;
+        file.sync()?;
+
+        // Switch to the new manifest by atomically replacing `CURRENT`.
+        let current_tmp = dir.join(format!("{}.tmp", CURRENT));
+        if backend.exists(&current_tmp) {
+            backend.delete(&current_tmp)?;
+        }
+        let current = backend.create(&current_tmp)?;
+        current.append(format!("{}-{:05}\n", LEGACY_MANIFEST, id).as_bytes())?;
+        current.sync()?;
+        backend.rename(&current_tmp, &dir.join(CURRENT))?;
+        backend.sync_dir(dir)?;
+        Ok(ManifestFile {
+            id,
+            file,
+            version: MANIFEST_FORMAT_V2,
+            size: buf.len() as u64,
+        })
+    }
+
+    /// Creates the manifest of a new database in `dir`, starting with the given records.
+    pub fn create(
+        backend: Arc<dyn StorageBackend>,
+        dir: impl AsRef<Path>,
+        records: impl IntoIterator<Item = ManifestRecord>,
+    ) -> Result<Self> {
+        let dir = dir.as_ref();
+        let file = Self::create_file(backend.as_ref(), dir, 1, records)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create manifest")?,
-            )),
+            backend,
+            dir: dir.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(path)
-            .context("failed to recover manifest")?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
+    /// Whether `dir` contains a manifest.
+    pub fn exists(backend: &dyn StorageBackend, dir: impl AsRef<Path>) -> bool {
+        let dir = dir.as_ref();
+        backend.exists(&dir.join(CURRENT)) || backend.exists(&dir.join(LEGACY_MANIFEST))
+    }
+
+    /// Opens the current manifest in `dir`, and removes the manifests left behind by a rotation
+    /// that did not finish. A damaged tail is dropped if `mode` allows it, and returned.
+    pub fn recover(
+        backend: Arc<dyn StorageBackend>,
+        dir: impl AsRef<Path>,
+        mode: RecoveryMode,
+    ) -> Result<(Self, Vec<ManifestRecord>, Option<DroppedTail>)> {
+        let dir = dir.as_ref();
+        let current_path = dir.join(CURRENT);
+        let id = if backend.exists(&current_path) {
+            let current = backend
+                .open(&current_path)
+                .and_then(|file| file.read_all())
+                .context("failed to read the current manifest")?;
+            let current = String::from_utf8_lossy(&current);
+            current
+                .trim()
+                .strip_prefix(&format!("{}-", LEGACY_MANIFEST))
+                .and_then(|id| id.parse().ok())
+                .with_context(|| format!("invalid {} file: {:?}", CURRENT, current))?
+        } else {
+            0
+        };
+        let path = Self::path_of_manifest(dir, id);
+        let file = backend.open(&path).context("failed to recover manifest")?;
+        let buf = file.read_all()?;
         let mut buf_ptr = buf.as_slice();
+        let version = Self::read_header(&mut buf_ptr)?;
+        // Records after a damaged one cannot be trusted, as the files they refer to may be gone.
+        let mode = match mode {
+            RecoveryMode::PointInTimeRecovery => RecoveryMode::TolerateCorruptedTailRecords,
+            mode => mode,
+        };
+        let len_size = if version == MANIFEST_FORMAT_LEGACY {
+            8
+        } else {
+            4
+        };
         let mut records = Vec::new();
+        let mut dropped_tail = None;
         while buf_ptr.has_remaining() {
-            let len = buf_ptr.get_u64();
-            let slice = &buf_ptr[..len as usize];
-            let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-            buf_ptr.advance(len as usize);
+            let offset = (buf.len() - buf_ptr.remaining()) as u64;
+            let len = if buf_ptr.remaining() < len_size {
+                None
+            } else if version == MANIFEST_FORMAT_LEGACY {
+                Some(buf_ptr.get_u64() as usize)
+            } else {
+                Some(buf_ptr.get_u32() as usize)
+            };
+            let Some(len) =
+                len.filter(|len| buf_ptr.remaining() >= 4 && buf_ptr.remaining() - 4 >= *len)
+            else {
+                mode.check_damaged_record(&path, offset, true, "incomplete manifest record")?;
+                dropped_tail = Some(DroppedTail::truncate(
+                    file.as_ref(),
+                    &path,
+                    offset,
+                    buf.len() as u64,
+                )?);
+                break;
+            };
+            let slice = &buf_ptr[..len];
+            buf_ptr.advance(len);
             let checksum = buf_ptr.get_u32();
             if checksum != crc32fast::hash(slice) {
-                bail!("checksum mismatched!");
+                let is_tail = !buf_ptr.has_remaining();
+                mode.check_damaged_record(&path, offset, is_tail, "manifest checksum mismatch")?;
+                dropped_tail = Some(DroppedTail::truncate(
+                    file.as_ref(),
+                    &path,
+                    offset,
+                    buf.len() as u64,
+                )?);
+                break;
             }
-            records.push(json);
+            let record = if version == MANIFEST_FORMAT_LEGACY {
+                serde_json::from_slice::<ManifestRecord>(slice)?
+            } else {
+                ManifestRecord::decode(slice)?
+            };
+            records.push(record);
         }
+
+        let current = Self::path_of_manifest(dir, id);
+        for path in backend.list(dir)? {
+            let is_manifest = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(LEGACY_MANIFEST));
+            if is_manifest && path != current {
+                backend.delete(&path)?;
+            }
+        }
+
         Ok((
             Self {
-                file: Arc::new(Mutex::new(file)),
+                backend,
+                dir: dir.to_path_buf(),
+                file: Arc::new(Mutex::new(ManifestFile {
+                    id,
+                    file,
+                    version,
+                    size: buf.len() as u64 - dropped_tail.as_ref().map_or(0, |dropped| dropped.len),
+                })),
             },
             records,
+            dropped_tail,
         ))
     }
 
+    /// The number of bytes in the current manifest file.
+    pub fn size(&self) -> u64 {
+        self.file.lock().size
+    }
+
+    /// Replaces the current manifest with a new file that only contains the given records, which
+    /// must describe the same state as the records added so far.
+    pub fn rotate(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        records: impl IntoIterator<Item = ManifestRecord>,
+    ) -> Result<()> {
+        let mut file = self.file.lock();
+        let old_id = file.id;
+        *file = Self::create_file(self.backend.as_ref(), &self.dir, old_id + 1, records)?;
+        self.backend
+            .delete(&Self::path_of_manifest(&self.dir, old_id))?;
+        Ok(())
+    }
+
+    /// Strips the header from the manifest content and returns the format version.
+    fn read_header(buf: &mut &[u8]) -> Result<u32> {
+        if buf.len() < 8 || (&buf[..4]).get_u32() != MANIFEST_MAGIC {
+            return Ok(MANIFEST_FORMAT_LEGACY);
+        }
+        buf.advance(4);
+        let version = buf.get_u32();
+        if version != MANIFEST_FORMAT_V2 {
+            bail!("unsupported manifest format version {}", version);
+        }
+        Ok(version)
+    }
+
+    /// Appends a record to the buffer in the given format.
+    fn encode_record(record: &ManifestRecord, version: u32, buf: &mut Vec<u8>) -> Result<()> {
+        if version == MANIFEST_FORMAT_LEGACY {
+            let json = serde_json::to_vec(record)?;
+            buf.put_u64(json.len() as u64);
+            buf.extend_from_slice(&json);
+            buf.put_u32(crc32fast::hash(&json));
+        } else {
+            let mut data = Vec::new();
+            record.encode(&mut data);
+            buf.put_u32(data.len() as u32);
+            buf.extend_from_slice(&data);
+            buf.put_u32(crc32fast::hash(&data));
+        }
+        Ok(())
+    }
+
     pub fn add_record(
         &self,
         _state_lock_observer: &MutexGuard<()>,
@@ -74,12 +586,91 @@ impl Manifest {
 
     pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
         let mut file = self.file.lock();
-        let mut buf = serde_json::to_vec(&record)?;
-        let hash = crc32fast::hash(&buf);
-        file.write_all(&(buf.len() as u64).to_be_bytes())?;
-        buf.put_u32(hash);
-        file.write_all(&buf)?;
-        file.sync_all()?;
+        let mut buf = Vec::new();
+        Self::encode_record(&record, file.version, &mut buf)?;
+        file.file.append(&buf)?;
+        file.file.sync()?;
+        file.size += buf.len() as u64;
         Ok(())
     }
 }
+
+impl LsmStorageInner {
+    /// The records that describe the current state, which start a rotated manifest.
+    pub(crate) fn manifest_snapshot(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+    ) -> Vec<ManifestRecord> {
+        let snapshots = self
+            .column_families
+            .iter()
+            .map(|cf| (cf, cf.state.read().clone()))
+            .collect::<Vec<_>>();
+        let mut records = vec![ManifestRecord::Ttl(self.options.ttl.is_some())];
+        for (cf, _) in &snapshots {
+            if cf.id() != DEFAULT_COLUMN_FAMILY_ID {
+                records.push(ManifestRecord::CreateColumnFamily(
+                    cf.id(),
+                    cf.name().to_string(),
+                ));
+            }
+        }
+        // The WALs that still have memtables, and the column families whose memtable in each WAL
+        // has already been flushed.
+        let wal_ids = snapshots
+            .iter()
+            .flat_map(|(_, snapshot)| {
+                std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter())
+            })
+            .map(|memtable| memtable.wal_id())
+            .collect::<BTreeSet<_>>();
+        for wal_id in wal_ids {
+            records.push(ManifestRecord::NewMemtable(wal_id));
+            for (cf, snapshot) in &snapshots {
+                let in_use = std::iter::once(&snapshot.memtable)
+                    .chain(snapshot.imm_memtables.iter())
+                    .any(|memtable| memtable.wal_id() == wal_id);
+                if !in_use {
+                    records.push(ManifestRecord::ColumnFamilyWalFlushed(cf.id(), wal_id));
+                }
+            }
+        }
+        for (cf, snapshot) in snapshots {
+            records.push(ManifestRecord::ColumnFamilySnapshot(
+                cf.id(),
+                snapshot.l0_sstables.clone(),
+                snapshot.levels.clone(),
+            ));
+        }
+        records
+    }
+
+    /// Replaces the manifest with a snapshot of the current state.
+    pub(crate) fn rotate_manifest(&self, state_lock_observer: &MutexGuard<()>) -> Result<()> {
+        let records = self.manifest_snapshot(state_lock_observer);
+        self.manifest().rotate(state_lock_observer, records)
+    }
+
+    /// Adds a record to the manifest, and rotates it once it grows past `max_manifest_size`.
+    pub(crate) fn add_manifest_record(
+        &self,
+        state_lock_observer: &MutexGuard<()>,
+        record: ManifestRecord,
+    ) -> Result<()> {
+        let manifest = self.manifest();
+        let result = manifest
+            .add_record(state_lock_observer, record)
+            .and_then(|()| {
+                if manifest.size() > self.options.max_manifest_size as u64 {
+                    self.rotate_manifest(state_lock_observer)?;
+                }
+                Ok(())
+            });
+        // The state in memory was already changed, so it no longer matches the manifest.
+        result.map_err(|e| {
+            let e = e.context(ManifestWriteError);
+            self.report_background_error(BackgroundErrorReason::ManifestWrite, None, &e);
+            e
+        })
+    }
+}
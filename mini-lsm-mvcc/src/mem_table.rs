@@ -9,10 +9,11 @@ use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
 
-use crate::iterators::StorageIterator;
-use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
+use crate::iterators::{DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator};
+use crate::key::{KeyBytes, KeySlice, TS_DEFAULT, TS_RANGE_BEGIN};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{RecoveredMemTable, Wal};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
@@ -20,8 +21,13 @@ use crate::wal::Wal;
 /// chapters of week 1 and week 2.
 pub struct MemTable {
     pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    /// Range tombstones, keyed by their start key and mapping to their end key.
+    range_tombstones: Arc<SkipMap<KeyBytes, Bytes>>,
     wal: Option<Wal>,
     id: usize,
+    /// The id of the WAL file the memtable logs to, which may be shared with the memtables of
+    /// other column families.
+    wal_id: usize,
     approximate_size: Arc<AtomicUsize>,
 }
 
@@ -63,7 +69,9 @@ impl MemTable {
     pub fn create(id: usize) -> Self {
         Self {
             id,
+            wal_id: id,
             map: Arc::new(SkipMap::new()),
+            range_tombstones: Arc::new(SkipMap::new()),
             wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
         }
@@ -71,25 +79,57 @@ impl MemTable {
 
     /// Create a new mem-table with WAL
     pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
+        Ok(Self::create_with_shared_wal(
+            id,
             id,
+            Wal::create(path.as_ref())?,
+        ))
+    }
+
+    /// Create a new mem-table that logs to the WAL `wal_id`, shared with other column families.
+    pub(crate) fn create_with_shared_wal(id: usize, wal_id: usize, wal: Wal) -> Self {
+        Self {
+            id,
+            wal_id,
             map: Arc::new(SkipMap::new()),
-            wal: Some(Wal::create(path.as_ref())?),
+            range_tombstones: Arc::new(SkipMap::new()),
+            wal: Some(wal),
             approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+        }
     }
 
     /// Create a memtable from WAL
     pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
         let map = Arc::new(SkipMap::new());
+        let range_tombstones = Arc::new(SkipMap::new());
         Ok(Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
+            wal_id: id,
+            wal: Some(Wal::recover(path.as_ref(), &map, &range_tombstones)?),
             map,
+            range_tombstones,
             approximate_size: Arc::new(AtomicUsize::new(0)),
         })
     }
 
+    /// Create a mem-table from the contents of a column family recovered from the shared WAL
+    /// `wal_id`.
+    pub(crate) fn recover_from_shared_wal(
+        id: usize,
+        wal_id: usize,
+        wal: Wal,
+        (map, range_tombstones): RecoveredMemTable,
+    ) -> Self {
+        Self {
+            id,
+            wal_id,
+            map: Arc::new(map),
+            range_tombstones: Arc::new(range_tombstones),
+            wal: Some(wal),
+            approximate_size: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
         let key_bytes = KeyBytes::from_bytes_with_ts(
@@ -123,6 +163,15 @@ impl MemTable {
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.insert(key, value);
+        if let Some(ref wal) = self.wal {
+            wal.put(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Put a key-value pair that has already been logged to the WAL.
+    pub(crate) fn insert(&self, key: KeySlice, value: &[u8]) {
         let estimated_size = key.raw_len() + value.len();
         self.map.insert(
             key.to_key_vec().into_key_bytes(),
@@ -130,12 +179,45 @@ impl MemTable {
         );
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Delete all keys in `start..end` written before the timestamp of `start`.
+    pub fn delete_range(&self, start: KeySlice, end: &[u8]) -> Result<()> {
+        self.insert_range_tombstone(start, end);
         if let Some(ref wal) = self.wal {
-            wal.put(key, value)?;
+            wal.put_range_tombstone(start, end)?;
         }
         Ok(())
     }
 
+    /// Add a range tombstone that has already been logged to the WAL.
+    pub(crate) fn insert_range_tombstone(&self, start: KeySlice, end: &[u8]) {
+        let estimated_size = start.raw_len() + end.len();
+        self.range_tombstones.insert(
+            start.to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(end),
+        );
+        self.approximate_size
+            .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// The WAL the memtable logs to, if the WAL is enabled.
+    pub(crate) fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
+    /// Get the range tombstones in the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones
+            .iter()
+            .map(|entry| RangeTombstone {
+                start: entry.key().clone().into_inner(),
+                end: entry.value().clone(),
+                ts: entry.key().ts(),
+            })
+            .collect()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -146,10 +228,13 @@ impl MemTable {
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
+        let range = (lower.clone(), upper.clone());
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
+            iter_builder: |map| map.range(range),
             item: (KeyBytes::new(), Bytes::new()),
+            lower,
+            upper,
         }
         .build();
         let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
@@ -162,6 +247,9 @@ impl MemTable {
         for entry in self.map.iter() {
             builder.add(entry.key().as_key_slice(), &entry.value()[..]);
         }
+        for tombstone in self.range_tombstones() {
+            builder.add_range_tombstone(tombstone);
+        }
         Ok(())
     }
 
@@ -169,6 +257,10 @@ impl MemTable {
         self.id
     }
 
+    pub fn wal_id(&self) -> usize {
+        self.wal_id
+    }
+
     pub fn approximate_size(&self) -> usize {
         self.approximate_size
             .load(std::sync::atomic::Ordering::Relaxed)
@@ -176,7 +268,7 @@ impl MemTable {
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.is_empty()
     }
 }
 
@@ -202,6 +294,9 @@ pub struct MemTableIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (KeyBytes, Bytes),
+    /// The range of the iterator, used when seeking or moving backward.
+    lower: Bound<KeyBytes>,
+    upper: Bound<KeyBytes>,
 }
 
 impl MemTableIterator {
@@ -210,6 +305,24 @@ impl MemTableIterator {
             .map(|x| (x.key().clone(), x.value().clone()))
             .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
     }
+
+    /// Move to the first entry after `lower`.
+    fn seek_to_first_after(&mut self, lower: Bound<KeyBytes>) {
+        self.with_mut(|x| {
+            *x.iter = x.map.range((lower, x.upper.clone()));
+            *x.item = MemTableIterator::entry_to_item(x.iter.next());
+        });
+    }
+
+    /// Move to the last entry before `upper`, and continue iterating forward from there.
+    fn seek_to_last_before(&mut self, upper: Bound<KeyBytes>) {
+        self.with_mut(|x| {
+            let range = (x.lower.clone(), upper);
+            *x.item = MemTableIterator::entry_to_item(x.map.range(range).next_back());
+            let range = (Bound::Excluded(x.item.0.clone()), x.upper.clone());
+            *x.iter = x.map.range(range);
+        });
+    }
 }
 
 impl StorageIterator for MemTableIterator {
@@ -233,3 +346,38 @@ impl StorageIterator for MemTableIterator {
         Ok(())
     }
 }
+
+impl DoubleEndedStorageIterator for MemTableIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        let lower = self.borrow_lower().clone();
+        self.seek_to_first_after(lower);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let upper = self.borrow_upper().clone();
+        self.seek_to_last_before(upper);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        let upper = Bound::Excluded(self.borrow_item().0.clone());
+        self.seek_to_last_before(upper);
+        Ok(())
+    }
+}
+
+impl SeekableStorageIterator for MemTableIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let key = KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key), TS_RANGE_BEGIN);
+        // Stay within the range of the iterator.
+        let lower = match self.borrow_lower() {
+            Bound::Included(lower) | Bound::Excluded(lower) if *lower >= key => {
+                self.borrow_lower().clone()
+            }
+            _ => Bound::Included(key),
+        };
+        self.seek_to_first_after(lower);
+        Ok(())
+    }
+}
@@ -1,14 +1,19 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+pub mod snapshot;
 pub mod txn;
 pub mod watermark;
 
 use std::{
     collections::{BTreeMap, HashSet},
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
 };
 
+use anyhow::{bail, Result};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
@@ -29,6 +34,10 @@ pub(crate) struct LsmMvccInner {
     pub(crate) commit_lock: Mutex<()>,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    /// The highest watermark used to garbage collect old versions. Reads below it may miss
+    /// versions. History from before the storage was opened is not tracked, so it starts at the
+    /// recovered commit ts.
+    gc_ts: AtomicU64,
 }
 
 impl LsmMvccInner {
@@ -38,6 +47,7 @@ impl LsmMvccInner {
             commit_lock: Mutex::new(()),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            gc_ts: AtomicU64::new(initial_ts),
         }
     }
 
@@ -55,10 +65,44 @@ impl LsmMvccInner {
         ts.1.watermark().unwrap_or(ts.0)
     }
 
+    /// Returns the watermark, below which versions are garbage collected by the caller. Reads at
+    /// older timestamps are no longer allowed afterwards.
+    pub fn gc_watermark(&self) -> u64 {
+        let ts = self.ts.lock();
+        let watermark = ts.1.watermark().unwrap_or(ts.0);
+        self.gc_ts.fetch_max(watermark, Ordering::SeqCst);
+        watermark
+    }
+
     pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
         let mut ts = self.ts.lock();
         let read_ts = ts.0;
         ts.1.add_reader(read_ts);
+        Self::txn_at(inner, read_ts, serializable)
+    }
+
+    /// Create a transaction reading at `read_ts`, which must not be newer than the latest commit or
+    /// older than the versions retained by garbage collection.
+    pub fn new_txn_at(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+        serializable: bool,
+    ) -> Result<Arc<Transaction>> {
+        let mut ts = self.ts.lock();
+        if read_ts > ts.0 {
+            bail!("ts {} is newer than the latest commit ts {}", read_ts, ts.0);
+        }
+        let gc_ts = self.gc_ts.load(Ordering::SeqCst);
+        if read_ts < gc_ts {
+            bail!("versions before ts {} have been garbage collected", gc_ts);
+        }
+        ts.1.add_reader(read_ts);
+        Ok(Self::txn_at(inner, read_ts, serializable))
+    }
+
+    /// Create a transaction at `read_ts`, which must already be registered as a reader.
+    fn txn_at(inner: Arc<LsmStorageInner>, read_ts: u64, serializable: bool) -> Arc<Transaction> {
         Arc::new(Transaction {
             inner,
             read_ts,
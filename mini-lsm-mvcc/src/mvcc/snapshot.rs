@@ -0,0 +1,48 @@
+use std::{ops::Bound, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::column_family::ColumnFamily;
+
+use super::txn::{Transaction, TxnIterator};
+
+/// A read-only view of the storage at a fixed timestamp. The versions it reads are retained until
+/// the snapshot and all of its iterators are dropped.
+pub struct Snapshot {
+    /// A transaction that is never written to or committed, which keeps the snapshot registered
+    /// as a reader.
+    txn: Arc<Transaction>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(txn: Arc<Transaction>) -> Self {
+        Self { txn }
+    }
+
+    /// The timestamp the snapshot reads at.
+    pub fn read_ts(&self) -> u64 {
+        self.txn.read_ts
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.txn.get(key)
+    }
+
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.txn.get_cf(cf, key)
+    }
+
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.txn.scan(lower, upper)
+    }
+
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.txn.scan_cf(cf, lower, upper)
+    }
+}
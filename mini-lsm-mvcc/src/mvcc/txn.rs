@@ -14,50 +14,127 @@ use ouroboros::self_referencing;
 use parking_lot::Mutex;
 
 use crate::{
-    iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
+    column_family::ColumnFamily,
+    iterators::{
+        two_merge_iterator::TwoMergeIterator, DoubleEndedStorageIterator, SeekableStorageIterator,
+        StorageIterator,
+    },
     lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::{LsmStorageInner, WriteBatchRecord},
+    lsm_storage::{LsmStorageInner, WriteBatchRecord, WriteOptions},
     mem_table::map_bound,
     mvcc::CommittedTxnData,
 };
 
+/// The smallest key that is greater than all keys starting with `prefix`, or `None` if there is no
+/// such key.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last != u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
+
+/// The hash of a key in the read and write sets. Keys of different column families are hashed
+/// with different seeds.
+fn key_hash(cf_id: u32, key: &[u8]) -> u32 {
+    farmhash::hash32_with_seed(key, cf_id)
+}
+
 pub struct Transaction {
     pub(crate) read_ts: u64,
     pub(crate) inner: Arc<LsmStorageInner>,
-    pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
+    /// The writes of the transaction, by column family id.
+    pub(crate) local_storage: Arc<SkipMap<u32, Arc<SkipMap<Bytes, Bytes>>>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
     pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
 }
 
 impl Transaction {
+    /// The writes of the transaction to a column family.
+    fn local_storage(&self, cf: &ColumnFamily) -> Arc<SkipMap<Bytes, Bytes>> {
+        self.local_storage
+            .get_or_insert_with(cf.id(), || Arc::new(SkipMap::new()))
+            .value()
+            .clone()
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf(self.inner.default_cf(), key)
+    }
+
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
         if let Some(guard) = &self.key_hashes {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            read_set.insert(key_hash(cf.id(), key));
         }
-        if let Some(entry) = self.local_storage.get(key) {
+        if let Some(entry) = self.local_storage(cf).get(key) {
             if entry.value().is_empty() {
                 return Ok(None);
             } else {
                 return Ok(Some(entry.value().clone()));
             }
         }
-        self.inner.get_with_ts(key, self.read_ts)
+        self.inner.get_with_ts(cf, key, self.read_ts)
     }
 
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.scan_inner(self.inner.default_cf(), lower, upper, None)
+    }
+
+    pub fn scan_cf(
+        self: &Arc<Self>,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_inner(cf, lower, upper, None)
+    }
+
+    /// Create an iterator over the keys that start with `prefix`.
+    pub fn scan_prefix(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        self.scan_prefix_cf(self.inner.default_cf(), prefix)
+    }
+
+    /// Create an iterator over the keys of a column family that start with `prefix`.
+    pub fn scan_prefix_cf(
+        self: &Arc<Self>,
+        cf: &ColumnFamily,
+        prefix: &[u8],
+    ) -> Result<TxnIterator> {
+        let upper = prefix_upper_bound(prefix);
+        let upper = match &upper {
+            Some(upper) => Bound::Excluded(&upper[..]),
+            None => Bound::Unbounded,
+        };
+        self.scan_inner(cf, Bound::Included(prefix), upper, Some(prefix))
+    }
+
+    fn scan_inner(
+        self: &Arc<Self>,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
+    ) -> Result<TxnIterator> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        let range = (map_bound(lower), map_bound(upper));
         let mut local_iter = TxnLocalIteratorBuilder {
-            map: self.local_storage.clone(),
-            iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
+            map: self.local_storage(cf),
+            iter_builder: |map| map.range(range),
             item: (Bytes::new(), Bytes::new()),
+            lower: map_bound(lower),
+            upper: map_bound(upper),
         }
         .build();
         let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
@@ -65,40 +142,54 @@ impl Transaction {
 
         TxnIterator::create(
             self.clone(),
+            cf.id(),
             TwoMergeIterator::create(
                 local_iter,
-                self.inner.scan_with_ts(lower, upper, self.read_ts)?,
+                self.inner
+                    .scan_with_ts(cf, lower, upper, prefix, self.read_ts)?,
             )?,
         )
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) {
+        self.put_cf(self.inner.default_cf(), key, value)
+    }
+
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
+        self.local_storage(cf)
             .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+            write_hashes.insert(key_hash(cf.id(), key));
         }
     }
 
     pub fn delete(&self, key: &[u8]) {
+        self.delete_cf(self.inner.default_cf(), key)
+    }
+
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
+        self.local_storage(cf)
             .insert(Bytes::copy_from_slice(key), Bytes::new());
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+            write_hashes.insert(key_hash(cf.id(), key));
         }
     }
 
     pub fn commit(&self) -> Result<()> {
+        self.commit_opt(&WriteOptions::default())
+    }
+
+    pub fn commit_opt(&self, options: &WriteOptions) -> Result<()> {
         self.committed
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .expect("cannot operate on committed txn!");
@@ -107,7 +198,7 @@ impl Transaction {
         if let Some(guard) = &self.key_hashes {
             let guard = guard.lock();
             let (write_set, read_set) = &*guard;
-            println!(
+            log::debug!(
                 "commit txn: write_set: {:?}, read_set: {:?}",
                 write_set, read_set
             );
@@ -125,18 +216,24 @@ impl Transaction {
         } else {
             serializability_check = false;
         }
-        let batch = self
-            .local_storage
-            .iter()
-            .map(|entry| {
-                if entry.value().is_empty() {
+        let mut batch = Vec::new();
+        for local_storage in self.local_storage.iter() {
+            let cf = self
+                .inner
+                .column_family_by_id(*local_storage.key())
+                .expect("unknown column family");
+            for entry in local_storage.value().iter() {
+                let record = if entry.value().is_empty() {
                     WriteBatchRecord::Del(entry.key().clone())
                 } else {
                     WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
-                }
-            })
-            .collect::<Vec<_>>();
-        let ts = self.inner.write_batch_inner(&batch)?;
+                };
+                batch.push((&**cf, record));
+            }
+        }
+        let ts = self
+            .inner
+            .write_batch_inner(batch.iter().map(|(cf, record)| (*cf, record)), options)?;
         if serializability_check {
             let mut committed_txns = self.inner.mvcc().committed_txns.lock();
             let mut key_hashes = self.key_hashes.as_ref().unwrap().lock();
@@ -185,6 +282,9 @@ pub struct TxnLocalIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (Bytes, Bytes),
+    /// The range of the iterator, used when seeking or moving backward.
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
 }
 
 impl TxnLocalIterator {
@@ -193,6 +293,24 @@ impl TxnLocalIterator {
             .map(|x| (x.key().clone(), x.value().clone()))
             .unwrap_or_else(|| (Bytes::new(), Bytes::new()))
     }
+
+    /// Move to the first entry after `lower`.
+    fn seek_to_first_after(&mut self, lower: Bound<Bytes>) {
+        self.with_mut(|x| {
+            *x.iter = x.map.range((lower, x.upper.clone()));
+            *x.item = TxnLocalIterator::entry_to_item(x.iter.next());
+        });
+    }
+
+    /// Move to the last entry before `upper`, and continue iterating forward from there.
+    fn seek_to_last_before(&mut self, upper: Bound<Bytes>) {
+        self.with_mut(|x| {
+            let range = (x.lower.clone(), upper);
+            *x.item = TxnLocalIterator::entry_to_item(x.map.range(range).next_back());
+            let range = (Bound::Excluded(x.item.0.clone()), x.upper.clone());
+            *x.iter = x.map.range(range);
+        });
+    }
 }
 
 impl StorageIterator for TxnLocalIterator {
@@ -217,17 +335,54 @@ impl StorageIterator for TxnLocalIterator {
     }
 }
 
+impl DoubleEndedStorageIterator for TxnLocalIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        let lower = self.borrow_lower().clone();
+        self.seek_to_first_after(lower);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let upper = self.borrow_upper().clone();
+        self.seek_to_last_before(upper);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        let upper = Bound::Excluded(self.borrow_item().0.clone());
+        self.seek_to_last_before(upper);
+        Ok(())
+    }
+}
+
+impl SeekableStorageIterator for TxnLocalIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        // Stay within the range of the iterator.
+        let lower = match self.borrow_lower() {
+            Bound::Included(lower) | Bound::Excluded(lower) if lower[..] >= *key => {
+                self.borrow_lower().clone()
+            }
+            _ => Bound::Included(Bytes::copy_from_slice(key)),
+        };
+        self.seek_to_first_after(lower);
+        Ok(())
+    }
+}
+
 pub struct TxnIterator {
     txn: Arc<Transaction>,
+    /// The column family iterated over, for the read set.
+    cf_id: u32,
     iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
 }
 
 impl TxnIterator {
     pub fn create(
         txn: Arc<Transaction>,
+        cf_id: u32,
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
-        let mut iter = Self { txn, iter };
+        let mut iter = Self { txn, cf_id, iter };
         iter.skip_deletes()?;
         if iter.is_valid() {
             iter.add_to_read_set(iter.key());
@@ -242,11 +397,18 @@ impl TxnIterator {
         Ok(())
     }
 
+    fn skip_deletes_backward(&mut self) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value().is_empty() {
+            self.iter.prev()?;
+        }
+        Ok(())
+    }
+
     fn add_to_read_set(&self, key: &[u8]) {
         if let Some(guard) = &self.txn.key_hashes {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            read_set.insert(key_hash(self.cf_id, key));
         }
     }
 }
@@ -279,3 +441,43 @@ impl StorageIterator for TxnIterator {
         self.iter.num_active_iterators()
     }
 }
+
+impl DoubleEndedStorageIterator for TxnIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.iter.seek_to_first()?;
+        self.skip_deletes()?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.iter.seek_to_last()?;
+        self.skip_deletes_backward()?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.iter.prev()?;
+        self.skip_deletes_backward()?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+}
+
+impl SeekableStorageIterator for TxnIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)?;
+        self.skip_deletes()?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+}
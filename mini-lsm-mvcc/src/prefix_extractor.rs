@@ -0,0 +1,70 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+const PREFIX_FIXED_LENGTH: u8 = 1;
+const PREFIX_DELIMITED: u8 = 2;
+
+/// Extracts the prefix of a key. SSTs keep a bloom filter over the prefixes of their keys, so that
+/// prefix scans can skip SSTs that have no key with the prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrefixExtractor {
+    /// The first `n` bytes of the key. Shorter keys have no prefix.
+    FixedLength(usize),
+    /// The key up to and including the `count`-th `delimiter`, e.g. `tenant/` for
+    /// `Delimited { delimiter: b'/', count: 1 }`. Keys with fewer delimiters have no prefix.
+    Delimited { delimiter: u8, count: usize },
+}
+
+impl PrefixExtractor {
+    /// Returns the prefix of `key`, or `None` if the key is not in the domain of the extractor.
+    ///
+    /// The prefix only depends on the bytes it contains, so all keys that start with a key `k` have
+    /// the same prefix as `k`.
+    pub fn extract<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        match *self {
+            PrefixExtractor::FixedLength(len) => key.get(..len),
+            PrefixExtractor::Delimited { delimiter, count } => {
+                if count == 0 {
+                    return Some(&key[..0]);
+                }
+                let (end, _) = key
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, byte)| **byte == delimiter)
+                    .nth(count - 1)?;
+                Some(&key[..=end])
+            }
+        }
+    }
+
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        match *self {
+            PrefixExtractor::FixedLength(len) => {
+                buf.put_u8(PREFIX_FIXED_LENGTH);
+                buf.put_u32(len as u32);
+            }
+            PrefixExtractor::Delimited { delimiter, count } => {
+                buf.put_u8(PREFIX_DELIMITED);
+                buf.put_u8(delimiter);
+                buf.put_u32(count as u32);
+            }
+        }
+    }
+
+    pub(crate) fn decode(buf: &mut &[u8]) -> Result<Self> {
+        if buf.is_empty() {
+            bail!("prefix extractor too small");
+        }
+        let extractor = match buf.get_u8() {
+            PREFIX_FIXED_LENGTH if buf.remaining() >= 4 => {
+                PrefixExtractor::FixedLength(buf.get_u32() as usize)
+            }
+            PREFIX_DELIMITED if buf.remaining() >= 5 => PrefixExtractor::Delimited {
+                delimiter: buf.get_u8(),
+                count: buf.get_u32() as usize,
+            },
+            kind => bail!("invalid prefix extractor {}", kind),
+        };
+        Ok(extractor)
+    }
+}
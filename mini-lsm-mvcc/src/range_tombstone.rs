@@ -0,0 +1,109 @@
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+/// Deletes all keys in `start..end` written before `ts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], ts: u64) -> Self {
+        Self {
+            start: Bytes::copy_from_slice(start),
+            end: Bytes::copy_from_slice(end),
+            ts,
+        }
+    }
+
+    /// Whether the version of `key` written at `ts` is deleted by this tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        ts < self.ts && self.contains(key)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.start.as_ref() <= key && key < self.end.as_ref()
+    }
+
+    /// Whether the tombstone overlaps the key range `lower..=upper`.
+    pub fn overlaps(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let after_lower = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => self.end.as_ref() > key,
+            Bound::Unbounded => true,
+        };
+        let before_upper = match upper {
+            Bound::Included(key) => self.start.as_ref() <= key,
+            Bound::Excluded(key) => self.start.as_ref() < key,
+            Bound::Unbounded => true,
+        };
+        after_lower && before_upper
+    }
+
+    /// Encode a list of range tombstones with a checksum.
+    pub fn encode_list(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u32(tombstones.len() as u32);
+        for tombstone in tombstones {
+            buf.put_u32(tombstone.start.len() as u32);
+            buf.put_slice(&tombstone.start);
+            buf.put_u32(tombstone.end.len() as u32);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.ts);
+        }
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    /// Decode a list of range tombstones written by `encode_list`.
+    pub fn decode_list(buf: &[u8]) -> Result<Vec<RangeTombstone>> {
+        if buf.len() < 8 {
+            bail!("range tombstones too small");
+        }
+        let (mut data, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(data) {
+            bail!("range tombstones checksum mismatched");
+        }
+        let num = data.get_u32() as usize;
+        let mut tombstones = Vec::with_capacity(num);
+        for _ in 0..num {
+            let start_len = data.get_u32() as usize;
+            let start = data.copy_to_bytes(start_len);
+            let end_len = data.get_u32() as usize;
+            let end = data.copy_to_bytes(end_len);
+            let ts = data.get_u64();
+            tombstones.push(RangeTombstone { start, end, ts });
+        }
+        Ok(tombstones)
+    }
+}
+
+/// A set of range tombstones that can be checked against versions of keys.
+#[derive(Clone, Debug, Default)]
+pub struct RangeTombstones {
+    /// Sorted by start key.
+    tombstones: Vec<RangeTombstone>,
+}
+
+impl RangeTombstones {
+    pub fn new(mut tombstones: Vec<RangeTombstone>) -> Self {
+        tombstones.sort_by(|a, b| a.start.cmp(&b.start));
+        Self { tombstones }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    /// Whether the version of `key` written at `ts` is deleted by any of the tombstones.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        let candidates = self
+            .tombstones
+            .partition_point(|tombstone| tombstone.start.as_ref() <= key);
+        self.tombstones[..candidates]
+            .iter()
+            .any(|tombstone| tombstone.covers(key, ts))
+    }
+}
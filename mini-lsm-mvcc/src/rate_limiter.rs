@@ -0,0 +1,151 @@
+//! A token-bucket rate limiter for the I/O of flushes and compactions, which keeps them from
+//! saturating the disk and hurting the latency of foreground reads.
+
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// Tokens are refilled continuously, and at most this much of the rate can accumulate.
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+/// The priority of a request. Low-priority requests wait while high-priority ones are waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Used by flushes, which block writes when they fall behind.
+    High,
+    /// Used by compactions.
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOp {
+    Read,
+    Write,
+}
+
+/// Which I/O is rate limited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RateLimiterMode {
+    #[default]
+    WritesOnly,
+    ReadsOnly,
+    AllIo,
+}
+
+#[derive(Debug)]
+struct RateLimiterState {
+    bytes_per_second: u64,
+    available: f64,
+    last_refill: Instant,
+    high_priority_waiting: usize,
+    /// The bytes granted so far, by priority.
+    total_bytes: [u64; 2],
+}
+
+impl RateLimiterState {
+    /// The most tokens that can accumulate, which is also the largest single grant.
+    fn burst(&self) -> u64 {
+        (self.bytes_per_second as f64 * REFILL_PERIOD.as_secs_f64()).max(1.0) as u64
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.available =
+            (self.available + elapsed * self.bytes_per_second as f64).min(self.burst() as f64);
+        self.last_refill = now;
+    }
+}
+
+/// Limits the bytes per second of flush and compaction I/O. The limiter is shared through
+/// `LsmStorageOptions`, and its rate can be changed while the database is running.
+#[derive(Debug)]
+pub struct RateLimiter {
+    mode: RateLimiterMode,
+    state: Mutex<RateLimiterState>,
+    cv: Condvar,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64, mode: RateLimiterMode) -> Self {
+        assert!(bytes_per_second > 0, "the rate must be positive");
+        let mut state = RateLimiterState {
+            bytes_per_second,
+            available: 0.0,
+            last_refill: Instant::now(),
+            high_priority_waiting: 0,
+            total_bytes: [0; 2],
+        };
+        state.available = state.burst() as f64;
+        Self {
+            mode,
+            state: Mutex::new(state),
+            cv: Condvar::new(),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.state.lock().bytes_per_second
+    }
+
+    /// Changes the rate, which applies to waiting requests as well.
+    pub fn set_bytes_per_second(&self, bytes_per_second: u64) {
+        assert!(bytes_per_second > 0, "the rate must be positive");
+        let mut state = self.state.lock();
+        state.refill();
+        state.bytes_per_second = bytes_per_second;
+        state.available = state.available.min(state.burst() as f64);
+        self.cv.notify_all();
+    }
+
+    /// The bytes granted to requests of a priority so far.
+    pub fn total_bytes_through(&self, priority: IoPriority) -> u64 {
+        self.state.lock().total_bytes[priority as usize]
+    }
+
+    /// Waits until `bytes` bytes of I/O may be done. Returns immediately if the mode does not limit
+    /// the kind of I/O.
+    pub fn request(&self, bytes: usize, priority: IoPriority, op: IoOp) {
+        let limited = match self.mode {
+            RateLimiterMode::WritesOnly => op == IoOp::Write,
+            RateLimiterMode::ReadsOnly => op == IoOp::Read,
+            RateLimiterMode::AllIo => true,
+        };
+        if !limited {
+            return;
+        }
+        let mut remaining = bytes as u64;
+        while remaining > 0 {
+            remaining -= self.acquire(remaining, priority);
+        }
+    }
+
+    /// Waits until up to a burst of `bytes` bytes may be done, and returns how many.
+    fn acquire(&self, bytes: u64, priority: IoPriority) -> u64 {
+        let mut state = self.state.lock();
+        if priority == IoPriority::High {
+            state.high_priority_waiting += 1;
+        }
+        let granted = loop {
+            state.refill();
+            let granted = bytes.min(state.burst());
+            let turn = priority == IoPriority::High || state.high_priority_waiting == 0;
+            if turn && state.available >= granted as f64 {
+                state.available -= granted as f64;
+                break granted;
+            }
+            let missing = (granted as f64 - state.available).max(0.0);
+            let wait = Duration::from_secs_f64(missing / state.bytes_per_second as f64)
+                .clamp(Duration::from_millis(1), REFILL_PERIOD);
+            self.cv.wait_for(&mut state, wait);
+        };
+        state.total_bytes[priority as usize] += granted;
+        if priority == IoPriority::High {
+            state.high_priority_waiting -= 1;
+            if state.high_priority_waiting == 0 {
+                self.cv.notify_all();
+            }
+        }
+        granted
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::backend::StorageFile;
+
+/// How recovery handles a WAL or manifest record that is incomplete or fails its checksum.
+///
+/// The manifest is always required to be intact up to its last record, as the files removed after
+/// a record was written cannot be brought back. Only its tail is dropped, unless the mode is
+/// `AbsoluteConsistency`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// Fail to open the database if any record is damaged.
+    AbsoluteConsistency,
+    /// Drop a damaged last record of a file, which is left behind when a write is interrupted, and
+    /// fail to open the database if any other record is damaged.
+    #[default]
+    TolerateCorruptedTailRecords,
+    /// Stop replaying the WALs at the first damaged record, and drop everything logged after it,
+    /// including all later WALs. The database is recovered to a consistent point in time.
+    PointInTimeRecovery,
+}
+
+impl RecoveryMode {
+    /// Checks whether a damaged record at `offset` of a file may be dropped along with the rest of
+    /// the file. `is_tail` is whether it is the last record of the file.
+    pub(crate) fn check_damaged_record(
+        self,
+        path: &Path,
+        offset: u64,
+        is_tail: bool,
+        reason: &str,
+    ) -> Result<()> {
+        match self {
+            RecoveryMode::TolerateCorruptedTailRecords if is_tail => Ok(()),
+            RecoveryMode::PointInTimeRecovery => Ok(()),
+            _ => bail!("{} at offset {} of {}", reason, offset, path.display()),
+        }
+    }
+}
+
+/// The damaged tail of a file that was dropped during recovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedTail {
+    pub path: PathBuf,
+    /// The offset of the first damaged record, which the file was truncated to.
+    pub offset: u64,
+    /// The number of bytes dropped.
+    pub len: u64,
+}
+
+impl DroppedTail {
+    /// Truncates `file` at `offset`, dropping its damaged tail.
+    pub(crate) fn truncate(
+        file: &dyn StorageFile,
+        path: &Path,
+        offset: u64,
+        file_len: u64,
+    ) -> Result<Self> {
+        file.truncate(offset)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            offset,
+            len: file_len - offset,
+        })
+    }
+}
+
+/// What was dropped while opening the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// The WAL and manifest files whose damaged tail was dropped.
+    pub dropped_tails: Vec<DroppedTail>,
+    /// The WALs logged after a damaged record, which were dropped by point-in-time recovery.
+    pub dropped_wals: Vec<PathBuf>,
+}
+
+impl RecoveryReport {
+    /// Whether nothing was dropped.
+    pub fn is_clean(&self) -> bool {
+        self.dropped_tails.is_empty() && self.dropped_wals.is_empty()
+    }
+}
@@ -0,0 +1,242 @@
+//! Counters and latency histograms of the engine. Like RocksDB's statistics, they are always
+//! collected, and can be read or dumped as text at any time.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// A counter of the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ticker {
+    /// Point lookups that skipped an SST because its bloom filter ruled the key out.
+    BloomFilterUseful,
+    /// Point lookups that read an SST because its bloom filter may contain the key.
+    BloomFilterPositive,
+    BlockCacheHit,
+    BlockCacheMiss,
+    /// The size of the SSTs written by flushes.
+    BytesFlushed,
+    /// Writes delayed because a slowdown trigger was crossed.
+    WriteStallDelays,
+    /// Writes blocked because a stop trigger was crossed.
+    WriteStallStops,
+    /// The time writes spent delayed or blocked.
+    WriteStallMicros,
+    WalSyncs,
+}
+
+impl Ticker {
+    pub const ALL: [Ticker; 9] = [
+        Ticker::BloomFilterUseful,
+        Ticker::BloomFilterPositive,
+        Ticker::BlockCacheHit,
+        Ticker::BlockCacheMiss,
+        Ticker::BytesFlushed,
+        Ticker::WriteStallDelays,
+        Ticker::WriteStallStops,
+        Ticker::WriteStallMicros,
+        Ticker::WalSyncs,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ticker::BloomFilterUseful => "bloom.filter.useful",
+            Ticker::BloomFilterPositive => "bloom.filter.positive",
+            Ticker::BlockCacheHit => "block.cache.hit",
+            Ticker::BlockCacheMiss => "block.cache.miss",
+            Ticker::BytesFlushed => "flush.bytes",
+            Ticker::WriteStallDelays => "write.stall.delays",
+            Ticker::WriteStallStops => "write.stall.stops",
+            Ticker::WriteStallMicros => "write.stall.micros",
+            Ticker::WalSyncs => "wal.syncs",
+        }
+    }
+}
+
+/// An operation whose latency is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramType {
+    Get,
+    /// Puts, deletes and write batches.
+    Write,
+    /// Creating a scan iterator, which seeks to its first key.
+    Scan,
+}
+
+impl HistogramType {
+    pub const ALL: [HistogramType; 3] = [
+        HistogramType::Get,
+        HistogramType::Write,
+        HistogramType::Scan,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HistogramType::Get => "get.micros",
+            HistogramType::Write => "write.micros",
+            HistogramType::Scan => "scan.micros",
+        }
+    }
+}
+
+/// A histogram of microseconds, with a bucket for each power of two.
+struct Histogram {
+    buckets: [AtomicU64; 64],
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    /// The bucket of values with the same number of significant bits.
+    fn bucket(micros: u64) -> usize {
+        (u64::BITS - micros.leading_zeros()) as usize
+    }
+
+    fn record(&self, micros: u64) {
+        self.buckets[Self::bucket(micros).min(63)].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn data(&self) -> HistogramData {
+        let buckets = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let count = buckets.iter().sum::<u64>();
+        let max = self.max.load(Ordering::Relaxed);
+        // The upper bound of the bucket that holds the value at a rank.
+        let percentile = |p: f64| {
+            let rank = (count as f64 * p).ceil().max(1.0) as u64;
+            let mut seen = 0;
+            for (i, bucket) in buckets.iter().enumerate() {
+                seen += bucket;
+                if seen >= rank {
+                    let upper = if i == 0 { 0 } else { (1u64 << i) - 1 };
+                    return upper.min(max);
+                }
+            }
+            max
+        };
+        HistogramData {
+            count,
+            sum: self.sum.load(Ordering::Relaxed),
+            max,
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+/// A snapshot of a histogram, in microseconds. The percentiles are rounded up to a power of two.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistogramData {
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+    pub p50: u64,
+    pub p99: u64,
+}
+
+impl HistogramData {
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+}
+
+/// The statistics of a DB.
+#[derive(Default)]
+pub struct Statistics {
+    tickers: [AtomicU64; Ticker::ALL.len()],
+    histograms: [Histogram; HistogramType::ALL.len()],
+    /// The size of the SSTs written by compactions, by output level.
+    bytes_compacted: Mutex<BTreeMap<usize, u64>>,
+}
+
+impl Statistics {
+    pub fn ticker(&self, ticker: Ticker) -> u64 {
+        self.tickers[ticker as usize].load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, ticker: Ticker, count: u64) {
+        self.tickers[ticker as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn histogram(&self, histogram: HistogramType) -> HistogramData {
+        self.histograms[histogram as usize].data()
+    }
+
+    /// Records the time since `start`.
+    pub(crate) fn record_latency(&self, histogram: HistogramType, start: Instant) {
+        let micros = start.elapsed().as_micros().min(u64::MAX as u128) as u64;
+        self.histograms[histogram as usize].record(micros);
+    }
+
+    /// The size of the SSTs written by compactions, by output level. Tiered compactions that
+    /// include the bottom tier are recorded under `usize::MAX`.
+    pub fn bytes_compacted(&self) -> BTreeMap<usize, u64> {
+        self.bytes_compacted.lock().clone()
+    }
+
+    pub(crate) fn record_compaction(&self, output_level: usize, bytes: u64) {
+        *self.bytes_compacted.lock().entry(output_level).or_default() += bytes;
+    }
+
+    pub(crate) fn record_write_stall(&self, stopped: bool, duration: Duration) {
+        let ticker = if stopped {
+            Ticker::WriteStallStops
+        } else {
+            Ticker::WriteStallDelays
+        };
+        self.record(ticker, 1);
+        self.record(Ticker::WriteStallMicros, duration.as_micros() as u64);
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ticker in Ticker::ALL {
+            writeln!(f, "{} COUNT : {}", ticker.name(), self.ticker(ticker))?;
+        }
+        for (level, bytes) in self.bytes_compacted() {
+            if level == usize::MAX {
+                writeln!(f, "compaction.bytes.bottom COUNT : {}", bytes)?;
+            } else {
+                writeln!(f, "compaction.bytes.L{} COUNT : {}", level, bytes)?;
+            }
+        }
+        for histogram in HistogramType::ALL {
+            let data = self.histogram(histogram);
+            writeln!(
+                f,
+                "{} P50 : {} P99 : {} MAX : {} COUNT : {} SUM : {} AVG : {:.1}",
+                histogram.name(),
+                data.p50,
+                data.p99,
+                data.max,
+                data.count,
+                data.sum,
+                data.average()
+            )?;
+        }
+        Ok(())
+    }
+}
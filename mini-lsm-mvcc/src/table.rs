@@ -1,22 +1,50 @@
 pub(crate) mod bloom;
 mod builder;
+mod compression;
 mod iterator;
 
-use std::fs::File;
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Result};
 pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
+pub use compression::CompressionType;
 pub use iterator::SsTableIterator;
 
+use crate::backend::{LocalBackend, StorageBackend, StorageFile};
 use crate::block::Block;
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
+use crate::statistics::{Statistics, Ticker};
+use crate::value_log::{ValueLog, ValueLogFile, ValuePointer};
 
 use self::bloom::Bloom;
 
+/// Magic number stored at the very end of versioned SST files. Files that do not end with it were
+/// written in the legacy format.
+pub(crate) const SST_MAGIC: u32 = 0x4d4c_534d;
+/// The original format: key/value lengths and block offsets are `u16`, no footer.
+pub(crate) const SST_FORMAT_LEGACY: u32 = 1;
+/// Lengths inside blocks are varints, block offsets and block meta key lengths are `u32`, and the
+/// file ends with a `version | magic` footer.
+pub(crate) const SST_FORMAT_V2: u32 = 2;
+/// Each block entry stores how its value is kept (inline or in the value log), and the bloom
+/// filter is followed by the value log references of the SST.
+pub(crate) const SST_FORMAT_V3: u32 = 3;
+/// The value log references are followed by the range tombstones of the SST.
+pub(crate) const SST_FORMAT_V4: u32 = 4;
+/// The range tombstones are followed by the prefix bloom filter of the SST.
+pub(crate) const SST_FORMAT_V5: u32 = 5;
+/// Each block is followed by a byte for its compression type, and the checksum covers the
+/// compressed block.
+pub(crate) const SST_FORMAT_V6: u32 = 6;
+/// The format version used when building new SSTs.
+pub(crate) const SST_FORMAT_CURRENT: u32 = SST_FORMAT_V6;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
     /// Offset of this data block.
@@ -35,11 +63,11 @@ impl BlockMeta {
             // The size of offset
             estimated_size += std::mem::size_of::<u32>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += std::mem::size_of::<u32>();
             // The size of actual key
             estimated_size += meta.first_key.raw_len();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += std::mem::size_of::<u32>();
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
@@ -53,10 +81,10 @@ impl BlockMeta {
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put_u32(meta.first_key.key_len() as u32);
             buf.put_slice(meta.first_key.key_ref());
             buf.put_u64(meta.first_key.ts());
-            buf.put_u16(meta.last_key.key_len() as u16);
+            buf.put_u32(meta.last_key.key_len() as u32);
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
@@ -66,16 +94,31 @@ impl BlockMeta {
     }
 
     /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    pub fn decode_block_meta(buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+        Self::decode_block_meta_with_version(buf, SST_FORMAT_CURRENT)
+    }
+
+    /// Decode block meta written in the given SST format version.
+    pub(crate) fn decode_block_meta_with_version(
+        mut buf: &[u8],
+        version: u32,
+    ) -> Result<(Vec<BlockMeta>, u64)> {
+        let get_key_len = |buf: &mut &[u8]| {
+            if version == SST_FORMAT_LEGACY {
+                buf.get_u16() as usize
+            } else {
+                buf.get_u32() as usize
+            }
+        };
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
         for _ in 0..num {
             let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let first_key_len = get_key_len(&mut buf);
             let first_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
-            let last_key_len: usize = buf.get_u16() as usize;
+            let last_key_len = get_key_len(&mut buf);
             let last_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
             block_meta.push(BlockMeta {
@@ -93,17 +136,62 @@ impl BlockMeta {
     }
 }
 
+/// Encode the number of bytes an SST references in each value log file.
+fn encode_value_log_refs(refs: &[(usize, u64)], buf: &mut Vec<u8>) {
+    let original_len = buf.len();
+    buf.put_u32(refs.len() as u32);
+    for (file_id, bytes) in refs {
+        buf.put_u64(*file_id as u64);
+        buf.put_u64(*bytes);
+    }
+    buf.put_u32(crc32fast::hash(&buf[original_len..]));
+}
+
+/// Decode the value log references of an SST.
+fn decode_value_log_refs(buf: &[u8]) -> Result<Vec<(usize, u64)>> {
+    if buf.len() < 8 {
+        bail!("value log refs too small");
+    }
+    let (mut data, mut checksum) = buf.split_at(buf.len() - 4);
+    if checksum.get_u32() != crc32fast::hash(data) {
+        bail!("value log refs checksum mismatched");
+    }
+    let num = data.get_u32() as usize;
+    let mut refs = Vec::with_capacity(num);
+    for _ in 0..num {
+        refs.push((data.get_u64() as usize, data.get_u64()));
+    }
+    Ok(refs)
+}
+
+/// Encode the prefix bloom filter of an SST. SSTs built without a prefix extractor have an empty
+/// section.
+fn encode_prefix_bloom(prefix_bloom: Option<&(PrefixExtractor, Bloom)>, buf: &mut Vec<u8>) {
+    if let Some((extractor, bloom)) = prefix_bloom {
+        extractor.encode(buf);
+        bloom.encode(buf);
+    }
+}
+
+/// Decode the prefix bloom filter of an SST.
+fn decode_prefix_bloom(mut buf: &[u8]) -> Result<Option<(PrefixExtractor, Bloom)>> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    let extractor = PrefixExtractor::decode(&mut buf)?;
+    if buf.len() < 5 {
+        bail!("prefix bloom too small");
+    }
+    Ok(Some((extractor, Bloom::decode(buf)?)))
+}
+
 /// A file object.
-pub struct FileObject(Option<File>, u64);
+pub struct FileObject(Option<Arc<dyn StorageFile>>, u64);
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
-        use std::os::unix::fs::FileExt;
         let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
+        self.0.as_ref().unwrap().read_at(&mut data[..], offset)?;
         Ok(data)
     }
 
@@ -113,17 +201,48 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
-        Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
-            data.len() as u64,
-        ))
+        Self::create_with_backend(&LocalBackend, path, data)
     }
 
     pub fn open(path: &Path) -> Result<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
+        Self::open_with_backend(&LocalBackend, path)
+    }
+
+    /// Create a new file object and write the file to the storage backend.
+    pub fn create_with_backend(
+        backend: &dyn StorageBackend,
+        path: &Path,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        Self::create_in_chunks(backend, path, data, std::iter::empty(), |_| {})
+    }
+
+    /// Create a new file object and write the file with one append per chunk, where the chunks end
+    /// at `chunk_ends` and at the end of `data`. `before_append` is called with the size of each
+    /// chunk before it is appended.
+    pub(crate) fn create_in_chunks(
+        backend: &dyn StorageBackend,
+        path: &Path,
+        data: Vec<u8>,
+        chunk_ends: impl IntoIterator<Item = usize>,
+        mut before_append: impl FnMut(usize),
+    ) -> Result<Self> {
+        let file = backend.create(path)?;
+        let mut start = 0;
+        for end in chunk_ends.into_iter().chain(std::iter::once(data.len())) {
+            if end > start {
+                before_append(end - start);
+                file.append(&data[start..end])?;
+                start = end;
+            }
+        }
+        file.sync()?;
+        Ok(FileObject(Some(file), data.len() as u64))
+    }
+
+    pub fn open_with_backend(backend: &dyn StorageBackend, path: &Path) -> Result<Self> {
+        let file = backend.open(path)?;
+        let size = file.size()?;
         Ok(FileObject(Some(file), size))
     }
 }
@@ -142,6 +261,18 @@ pub struct SsTable {
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
     max_ts: u64,
+    /// The on-disk format version of this SST.
+    pub(crate) format_version: u32,
+    /// The number of bytes referenced in each value log file, sorted by file id.
+    value_log_refs: Vec<(usize, u64)>,
+    /// The value log files referenced by this SST, set by `attach_value_log`.
+    value_log_files: HashMap<usize, Arc<ValueLogFile>>,
+    /// Range tombstones stored in this SST. They are not reflected in the key range of the SST.
+    range_tombstones: Vec<RangeTombstone>,
+    /// A bloom filter over the key prefixes, along with the extractor that produced them.
+    prefix_bloom: Option<(PrefixExtractor, Bloom)>,
+    /// The statistics of the DB, set by `attach_statistics`.
+    statistics: Option<Arc<Statistics>>,
 }
 impl SsTable {
     #[cfg(test)]
@@ -151,7 +282,31 @@ impl SsTable {
 
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
+        let (format_version, mut len) = Self::read_footer(&file)?;
+        let mut prefix_bloom = None;
+        if format_version >= SST_FORMAT_V5 {
+            let raw_prefix_bloom_offset = file.read(len - 4, 4)?;
+            let prefix_bloom_offset = (&raw_prefix_bloom_offset[..]).get_u32() as u64;
+            let raw_prefix_bloom = file.read(prefix_bloom_offset, len - 4 - prefix_bloom_offset)?;
+            prefix_bloom = decode_prefix_bloom(&raw_prefix_bloom)?;
+            len = prefix_bloom_offset;
+        }
+        let mut range_tombstones = Vec::new();
+        if format_version >= SST_FORMAT_V4 {
+            let raw_tombstones_offset = file.read(len - 4, 4)?;
+            let tombstones_offset = (&raw_tombstones_offset[..]).get_u32() as u64;
+            let raw_tombstones = file.read(tombstones_offset, len - 4 - tombstones_offset)?;
+            range_tombstones = RangeTombstone::decode_list(&raw_tombstones)?;
+            len = tombstones_offset;
+        }
+        let mut value_log_refs = Vec::new();
+        if format_version >= SST_FORMAT_V3 {
+            let raw_refs_offset = file.read(len - 4, 4)?;
+            let refs_offset = (&raw_refs_offset[..]).get_u32() as u64;
+            let raw_refs = file.read(refs_offset, len - 4 - refs_offset)?;
+            value_log_refs = decode_value_log_refs(&raw_refs)?;
+            len = refs_offset;
+        }
         let raw_bloom_offset = file.read(len - 4, 4)?;
         let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
         let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
@@ -159,7 +314,8 @@ impl SsTable {
         let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
         let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let (block_meta, max_ts) =
+            BlockMeta::decode_block_meta_with_version(&raw_meta[..], format_version)?;
         Ok(Self {
             file,
             first_key: block_meta.first().unwrap().first_key.clone(),
@@ -170,9 +326,33 @@ impl SsTable {
             block_cache,
             bloom: Some(bloom_filter),
             max_ts,
+            format_version,
+            value_log_refs,
+            value_log_files: HashMap::new(),
+            range_tombstones,
+            prefix_bloom,
+            statistics: None,
         })
     }
 
+    /// Read the format version from the footer. Returns the version and the length of the file
+    /// excluding the footer.
+    fn read_footer(file: &FileObject) -> Result<(u32, u64)> {
+        let len = file.size();
+        if len < 8 {
+            bail!("SST file too small");
+        }
+        let mut footer = &file.read(len - 8, 8)?[..];
+        let version = footer.get_u32();
+        if footer.get_u32() != SST_MAGIC {
+            return Ok((SST_FORMAT_LEGACY, len));
+        }
+        if !(SST_FORMAT_V2..=SST_FORMAT_V6).contains(&version) {
+            bail!("unsupported SST format version {}", version);
+        }
+        Ok((version, len - 8))
+    }
+
     /// Create a mock SST with only first key + last key metadata
     pub fn create_meta_only(
         id: usize,
@@ -190,6 +370,12 @@ impl SsTable {
             last_key,
             bloom: None,
             max_ts: 0,
+            format_version: SST_FORMAT_CURRENT,
+            value_log_refs: Vec::new(),
+            value_log_files: HashMap::new(),
+            range_tombstones: Vec::new(),
+            prefix_bloom: None,
+            statistics: None,
         }
     }
 
@@ -209,21 +395,90 @@ impl SsTable {
         if checksum != crc32fast::hash(block_data) {
             bail!("block checksum mismatched");
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        let block_data = if self.format_version >= SST_FORMAT_V6 {
+            CompressionType::decompress_block(block_data)?
+        } else {
+            block_data.into()
+        };
+        Ok(Arc::new(Block::decode_with_version(
+            &block_data,
+            self.format_version,
+        )?))
     }
 
     /// Read a block from disk, with block cache.
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
         if let Some(ref block_cache) = self.block_cache {
+            let mut miss = false;
             let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
+                .try_get_with((self.id, block_idx), || {
+                    miss = true;
+                    self.read_block(block_idx)
+                })
                 .map_err(|e| anyhow!("{}", e))?;
+            if let Some(statistics) = &self.statistics {
+                let ticker = if miss {
+                    Ticker::BlockCacheMiss
+                } else {
+                    Ticker::BlockCacheHit
+                };
+                statistics.record(ticker, 1);
+            }
             Ok(blk)
         } else {
             self.read_block(block_idx)
         }
     }
 
+    /// Records the block cache hits and misses of this SST in the statistics of the DB.
+    pub(crate) fn attach_statistics(&mut self, statistics: &Arc<Statistics>) {
+        self.statistics = Some(statistics.clone());
+    }
+
+    /// Opens the value log files referenced by this SST, so that its value pointers can be
+    /// resolved.
+    pub(crate) fn attach_value_log(&mut self, value_log: &ValueLog) -> Result<()> {
+        for (file_id, _) in &self.value_log_refs {
+            self.value_log_files
+                .insert(*file_id, value_log.file(*file_id)?);
+        }
+        Ok(())
+    }
+
+    /// Reads the value an encoded value pointer of this SST refers to.
+    pub fn read_value(&self, pointer: &[u8]) -> Result<Bytes> {
+        let pointer = ValuePointer::decode(pointer)?;
+        let Some(file) = self.value_log_files.get(&pointer.file_id) else {
+            bail!(
+                "value log file {} is not attached to SST {}",
+                pointer.file_id,
+                self.id
+            );
+        };
+        file.read(&pointer)
+    }
+
+    /// The number of bytes referenced in each value log file.
+    pub fn value_log_refs(&self) -> &[(usize, u64)] {
+        &self.value_log_refs
+    }
+
+    /// The range tombstones stored in this SST.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Whether the SST may contain keys with the given prefix, as extracted by `extractor`. Always
+    /// true if the SST was built without a prefix bloom filter or with a different extractor.
+    pub fn may_contain_prefix(&self, extractor: &PrefixExtractor, prefix: &[u8]) -> bool {
+        match &self.prefix_bloom {
+            Some((sst_extractor, bloom)) if sst_extractor == extractor => {
+                bloom.may_contain(farmhash::fingerprint32(prefix))
+            }
+            _ => true,
+        }
+    }
+
     /// Find the block that may contain `key`.
     pub fn find_block_idx(&self, key: KeySlice) -> usize {
         self.block_meta
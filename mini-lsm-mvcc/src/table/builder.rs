@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -5,10 +6,19 @@ use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
-use crate::block::BlockBuilder;
+use super::compression::CompressionType;
+use super::{
+    encode_prefix_bloom, encode_value_log_refs, BlockMeta, FileObject, SsTable, SST_FORMAT_CURRENT,
+    SST_MAGIC,
+};
+use crate::backend::{LocalBackend, StorageBackend};
+use crate::block::{BlockBuilder, ValueKind};
 use crate::key::{KeySlice, KeyVec};
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
+use crate::rate_limiter::{IoOp, IoPriority, RateLimiter};
+use crate::value_log::{ValueLogWriter, ValuePointer};
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -20,6 +30,17 @@ pub struct SsTableBuilder {
     block_size: usize,
     key_hashes: Vec<u32>,
     max_ts: u64,
+    /// Receives the values that are separated from the SST.
+    value_log: Option<ValueLogWriter>,
+    /// The number of bytes referenced in each value log file.
+    value_log_refs: BTreeMap<usize, u64>,
+    range_tombstones: Vec<RangeTombstone>,
+    prefix_extractor: Option<PrefixExtractor>,
+    compression: CompressionType,
+    /// Hashes of the distinct key prefixes, if there is a prefix extractor.
+    prefix_hashes: Vec<u32>,
+    /// Charged for each block before it is written to the file.
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
 }
 
 impl SsTableBuilder {
@@ -34,32 +55,102 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            value_log: None,
+            value_log_refs: BTreeMap::new(),
+            range_tombstones: Vec::new(),
+            prefix_extractor: None,
+            compression: CompressionType::None,
+            prefix_hashes: Vec::new(),
+            rate_limiter: None,
         }
     }
 
+    /// Stores values above the value log threshold in the value log instead of the SST.
+    pub(crate) fn set_value_log(&mut self, writer: ValueLogWriter) {
+        self.value_log = Some(writer);
+    }
+
+    /// Builds a bloom filter over the key prefixes produced by `extractor`.
+    pub fn set_prefix_extractor(&mut self, extractor: PrefixExtractor) {
+        self.prefix_extractor = Some(extractor);
+    }
+
+    /// Compresses the data blocks with `compression`.
+    pub fn set_compression(&mut self, compression: CompressionType) {
+        self.compression = compression;
+    }
+
+    /// Waits for `rate_limiter` before writing each block, and once more before the meta sections.
+    pub(crate) fn set_rate_limiter(
+        &mut self,
+        rate_limiter: Arc<RateLimiter>,
+        priority: IoPriority,
+    ) {
+        self.rate_limiter = Some((rate_limiter, priority));
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
-        if self.first_key.is_empty() {
-            self.first_key.set_from_slice(key);
+        if let Some(writer) = self.value_log.as_mut() {
+            if writer.should_separate(value.len()) {
+                let pointer = writer.append(value);
+                self.add_value_pointer(key, &pointer.encode());
+                return;
+            }
         }
+        self.add_entry(key, ValueKind::Inline, value);
+    }
 
+    /// Adds a key whose value is stored in the value log. `pointer` is an encoded `ValuePointer`.
+    pub(crate) fn add_value_pointer(&mut self, key: KeySlice, pointer: &[u8]) {
+        let decoded = ValuePointer::decode(pointer).expect("invalid value pointer");
+        *self.value_log_refs.entry(decoded.file_id).or_default() += decoded.record_size();
+        self.add_entry(key, ValueKind::Pointer, pointer);
+    }
+
+    fn add_entry(&mut self, key: KeySlice, kind: ValueKind, value: &[u8]) {
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        if let Some(prefix) = self
+            .prefix_extractor
+            .and_then(|extractor| extractor.extract(key.key_ref()))
+        {
+            // Keys are added in order, so the keys with the same prefix are next to each other.
+            let hash = farmhash::fingerprint32(prefix);
+            if self.prefix_hashes.last() != Some(&hash) {
+                self.prefix_hashes.push(hash);
+            }
+        }
 
-        if self.builder.add(key, value) {
-            self.last_key.set_from_slice(key);
-            return;
+        // An entry that cannot fit into a block on its own gets a dedicated block, so that it does
+        // not drag its neighbours into an oversized block.
+        let oversized = key.raw_len() + value.len() >= self.block_size;
+        if oversized && !self.builder.is_empty() {
+            self.finish_block();
         }
 
-        // create a new block builder and append block data
-        self.finish_block();
+        if !self.builder.add_with_kind(key, kind, value) {
+            // create a new block builder and append block data
+            self.finish_block();
 
-        // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
-        self.first_key.set_from_slice(key);
+            // add the key-value pair to the next block
+            assert!(self.builder.add_with_kind(key, kind, value));
+        }
+        if self.first_key.is_empty() {
+            self.first_key.set_from_slice(key);
+        }
         self.last_key.set_from_slice(key);
+
+        if oversized {
+            self.finish_block();
+        }
+    }
+
+    /// Adds a range tombstone to the SSTable. Range tombstones can be added in any order.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.range_tombstones.push(tombstone);
     }
 
     /// Get the estimated size of the SSTable.
@@ -68,26 +159,60 @@ impl SsTableBuilder {
     }
 
     fn finish_block(&mut self) {
+        if self.builder.is_empty() {
+            return;
+        }
         let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
         let encoded_block = builder.build().encode();
+        let block_offset = self.data.len();
         self.meta.push(BlockMeta {
-            offset: self.data.len(),
+            offset: block_offset,
             first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
         });
-        let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
+        self.compression
+            .compress_block(&encoded_block, &mut self.data);
+        let checksum = crc32fast::hash(&self.data[block_offset..]);
         self.data.put_u32(checksum);
     }
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
     pub fn build(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        self.build_with_backend(id, block_cache, &LocalBackend, path)
+    }
+
+    /// Builds the SSTable and writes it to the given path of the storage backend.
+    pub fn build_with_backend(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
+        backend: &dyn StorageBackend,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
+        if self.meta.is_empty() && self.builder.is_empty() {
+            // An SST needs at least one key. The start of a range tombstone is deleted by the
+            // tombstone anyway, so a point deletion there does not change what readers see.
+            let mut tombstones = self.range_tombstones.clone();
+            tombstones.sort_by(|a, b| (&a.start, b.ts).cmp(&(&b.start, a.ts)));
+            for tombstone in tombstones {
+                self.add(KeySlice::from_slice(&tombstone.start, tombstone.ts), &[]);
+            }
+        }
         self.finish_block();
+        // The values must be durable before the SST that points to them.
+        let value_log = match self.value_log {
+            Some(writer) => {
+                let value_log = writer.value_log().clone();
+                writer.finish()?;
+                Some(value_log)
+            }
+            None => None,
+        };
         let mut buf = self.data;
         let meta_offset = buf.len();
         BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
@@ -99,8 +224,38 @@ impl SsTableBuilder {
         let bloom_offset = buf.len();
         bloom.encode(&mut buf);
         buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
-        Ok(SsTable {
+        let value_log_refs = self.value_log_refs.into_iter().collect::<Vec<_>>();
+        let refs_offset = buf.len();
+        encode_value_log_refs(&value_log_refs, &mut buf);
+        buf.put_u32(refs_offset as u32);
+        let range_tombstones_offset = buf.len();
+        RangeTombstone::encode_list(&self.range_tombstones, &mut buf);
+        buf.put_u32(range_tombstones_offset as u32);
+        let prefix_bloom = self.prefix_extractor.map(|extractor| {
+            let bloom = Bloom::build_from_key_hashes(
+                &self.prefix_hashes,
+                Bloom::bloom_bits_per_key(self.prefix_hashes.len(), 0.01),
+            );
+            (extractor, bloom)
+        });
+        let prefix_bloom_offset = buf.len();
+        encode_prefix_bloom(prefix_bloom.as_ref(), &mut buf);
+        buf.put_u32(prefix_bloom_offset as u32);
+        buf.put_u32(SST_FORMAT_CURRENT);
+        buf.put_u32(SST_MAGIC);
+        let block_ends = self.meta.iter().skip(1).map(|meta| meta.offset);
+        let file = FileObject::create_in_chunks(
+            backend,
+            path.as_ref(),
+            buf,
+            block_ends.chain(std::iter::once(meta_offset)),
+            |len| {
+                if let Some((rate_limiter, priority)) = &self.rate_limiter {
+                    rate_limiter.request(len, *priority, IoOp::Write);
+                }
+            },
+        )?;
+        let mut table = SsTable {
             id,
             file,
             first_key: self.meta.first().unwrap().first_key.clone(),
@@ -110,7 +265,17 @@ impl SsTableBuilder {
             block_cache,
             bloom: Some(bloom),
             max_ts: self.max_ts,
-        })
+            format_version: SST_FORMAT_CURRENT,
+            value_log_refs,
+            value_log_files: Default::default(),
+            range_tombstones: self.range_tombstones,
+            prefix_bloom,
+            statistics: None,
+        };
+        if let Some(value_log) = value_log {
+            table.attach_value_log(&value_log)?;
+        }
+        Ok(table)
     }
 
     #[cfg(test)]
@@ -0,0 +1,59 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, bail, Result};
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_LZ4: u8 = 1;
+const COMPRESSION_DEFLATE: u8 = 2;
+
+/// How the blocks of an SST are compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    #[default]
+    None,
+    /// Fast compression with a moderate ratio.
+    Lz4,
+    /// Slower compression with a higher ratio. The level ranges from 0 (fastest) to 10 (smallest).
+    Deflate { level: u8 },
+}
+
+impl CompressionType {
+    /// Compress a block and append it to `buf`, followed by the compression type byte. The block
+    /// is stored uncompressed if compressing does not make it smaller.
+    pub(crate) fn compress_block(&self, block: &[u8], buf: &mut Vec<u8>) {
+        let compressed = match *self {
+            CompressionType::None => None,
+            CompressionType::Lz4 => Some((lz4_flex::compress_prepend_size(block), COMPRESSION_LZ4)),
+            CompressionType::Deflate { level } => Some((
+                miniz_oxide::deflate::compress_to_vec(block, level.min(10)),
+                COMPRESSION_DEFLATE,
+            )),
+        };
+        match compressed {
+            Some((compressed, kind)) if compressed.len() < block.len() => {
+                buf.extend(compressed);
+                buf.push(kind);
+            }
+            _ => {
+                buf.extend(block);
+                buf.push(COMPRESSION_NONE);
+            }
+        }
+    }
+
+    /// Decompress a block written by `compress_block`.
+    pub(crate) fn decompress_block(data: &[u8]) -> Result<Cow<'_, [u8]>> {
+        let Some((&kind, data)) = data.split_last() else {
+            bail!("block too small");
+        };
+        match kind {
+            COMPRESSION_NONE => Ok(Cow::Borrowed(data)),
+            COMPRESSION_LZ4 => Ok(Cow::Owned(lz4_flex::decompress_size_prepended(data)?)),
+            COMPRESSION_DEFLATE => Ok(Cow::Owned(
+                miniz_oxide::inflate::decompress_to_vec(data)
+                    .map_err(|e| anyhow!("failed to inflate block: {:?}", e))?,
+            )),
+            _ => bail!("unsupported block compression {}", kind),
+        }
+    }
+}
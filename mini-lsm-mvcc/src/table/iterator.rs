@@ -1,20 +1,54 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use super::SsTable;
-use crate::block::BlockIterator;
-use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
+use crate::block::{BlockIterator, ValueKind};
+use crate::iterators::{DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator};
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// Whether values stored in the value log are read. Otherwise the encoded pointers are
+    /// returned, which lets compaction move them without copying the values.
+    resolve_values: bool,
+    /// The value of the current entry, if it is stored in the value log.
+    resolved_value: Option<Bytes>,
 }
 
 impl SsTableIterator {
+    fn new(
+        table: Arc<SsTable>,
+        blk_idx: usize,
+        blk_iter: BlockIterator,
+        resolve_values: bool,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            table,
+            blk_iter,
+            blk_idx,
+            resolve_values,
+            resolved_value: None,
+        };
+        iter.resolve_value()?;
+        Ok(iter)
+    }
+
+    fn resolve_value(&mut self) -> Result<()> {
+        self.resolved_value = None;
+        if self.resolve_values
+            && self.blk_iter.is_valid()
+            && self.blk_iter.value_kind() == ValueKind::Pointer
+        {
+            self.resolved_value = Some(self.table.read_value(self.blk_iter.value())?);
+        }
+        Ok(())
+    }
+
     fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
         Ok((
             0,
@@ -25,12 +59,14 @@ impl SsTableIterator {
     /// Create a new iterator and seek to the first key-value pair.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
         let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
-        Ok(iter)
+        Self::new(table, blk_idx, blk_iter, true)
+    }
+
+    /// Create a new iterator that returns value pointers as they are stored, and seek to the first
+    /// key-value pair.
+    pub(crate) fn create_and_seek_to_first_raw(table: Arc<SsTable>) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
+        Self::new(table, blk_idx, blk_iter, false)
     }
 
     /// Seek to the first key-value pair.
@@ -38,7 +74,29 @@ impl SsTableIterator {
         let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
         self.blk_idx = blk_idx;
         self.blk_iter = blk_iter;
-        Ok(())
+        self.resolve_value()
+    }
+
+    fn seek_to_last_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        let blk_idx = table.num_of_blocks() - 1;
+        Ok((
+            blk_idx,
+            BlockIterator::create_and_seek_to_last(table.read_block_cached(blk_idx)?),
+        ))
+    }
+
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&table)?;
+        Self::new(table, blk_idx, blk_iter, true)
+    }
+
+    /// Seek to the last key-value pair.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&self.table)?;
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        self.resolve_value()
     }
 
     fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
@@ -58,12 +116,7 @@ impl SsTableIterator {
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
         let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
-        Ok(iter)
+        Self::new(table, blk_idx, blk_iter, true)
     }
 
     /// Seek to the first key-value pair which >= `key`.
@@ -71,7 +124,7 @@ impl SsTableIterator {
         let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
-        Ok(())
+        self.resolve_value()
     }
 }
 
@@ -79,7 +132,10 @@ impl StorageIterator for SsTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        self.blk_iter.value()
+        match &self.resolved_value {
+            Some(value) => value,
+            None => self.blk_iter.value(),
+        }
     }
 
     fn key(&self) -> KeySlice {
@@ -100,6 +156,36 @@ impl StorageIterator for SsTableIterator {
                 );
             }
         }
-        Ok(())
+        self.resolve_value()
+    }
+
+    fn is_value_pointer(&self) -> bool {
+        !self.resolve_values && self.blk_iter.value_kind() == ValueKind::Pointer
+    }
+}
+
+impl DoubleEndedStorageIterator for SsTableIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        SsTableIterator::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        SsTableIterator::seek_to_last(self)
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter =
+                BlockIterator::create_and_seek_to_last(self.table.read_block_cached(self.blk_idx)?);
+        }
+        self.resolve_value()
+    }
+}
+
+impl SeekableStorageIterator for SsTableIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.seek_to_key(KeySlice::from_slice(key, TS_RANGE_BEGIN))
     }
 }
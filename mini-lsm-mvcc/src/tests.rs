@@ -1,4 +1,27 @@
+mod backend;
+mod background_error;
+mod checkpoint;
+mod column_family;
+mod compaction_filter;
+mod compaction_scheduler;
+mod compression;
+mod crash;
+mod event_listener;
+mod fixtures;
+mod group_commit;
 mod harness;
+mod large_kv;
+mod manifest;
+mod prefix_scan;
+mod range_delete;
+mod rate_limiter;
+mod recovery;
+mod reverse_iter;
+mod seek;
+mod snapshot;
+mod statistics;
+mod ttl;
+mod value_log;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -19,3 +42,4 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_stall;
@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::{
+    backend::{FaultInjectionBackend, MemoryBackend, StorageBackend},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteOptions},
+    tests::fixtures::simple_options,
+};
+
+#[test]
+fn test_memory_backend() {
+    let backend = Arc::new(MemoryBackend::new());
+    let path = Path::new("/mini-lsm-memory-test");
+    let storage = MiniLsm::open(
+        path,
+        LsmStorageOptions {
+            backend: backend.clone(),
+            ..simple_options(true)
+        },
+    )
+    .unwrap();
+    for i in 0..4 {
+        for j in 0..100 {
+            let key = format!("key{:03}", j);
+            let value = format!("value{}_{}", j, i);
+            storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.delete(b"key000").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert!(!path.exists());
+    assert!(backend
+        .list(path)
+        .unwrap()
+        .iter()
+        .any(|path| path.extension().is_some_and(|ext| ext == "sst")));
+
+    let storage = MiniLsm::open(
+        path,
+        LsmStorageOptions {
+            backend,
+            ..simple_options(true)
+        },
+    )
+    .unwrap();
+    assert_eq!(storage.get(b"key000").unwrap(), None);
+    for j in 1..100 {
+        let key = format!("key{:03}", j);
+        assert_eq!(
+            storage.get(key.as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value{}_3", j)))
+        );
+    }
+}
+
+#[test]
+fn test_drop_unsynced_writes() {
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let path = Path::new("/mini-lsm-fault-test");
+    let storage = MiniLsm::open(
+        path,
+        LsmStorageOptions {
+            backend: Arc::new(backend.clone()),
+            ..simple_options(true)
+        },
+    )
+    .unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .put_opt(
+            b"b",
+            b"2",
+            &WriteOptions {
+                sync: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    storage.put(b"c", b"3").unwrap();
+    // Crash without closing the storage.
+    drop(storage);
+    backend.drop_unsynced_writes().unwrap();
+
+    let storage = MiniLsm::open(
+        path,
+        LsmStorageOptions {
+            backend: Arc::new(backend),
+            ..simple_options(true)
+        },
+    )
+    .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.get(b"c").unwrap(), None);
+}
+
+#[test]
+fn test_fail_writes() {
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let path = Path::new("/mini-lsm-fault-test");
+    let storage = MiniLsm::open(
+        path,
+        LsmStorageOptions {
+            backend: Arc::new(backend.clone()),
+            ..simple_options(true)
+        },
+    )
+    .unwrap();
+    storage.put(b"a", b"1").unwrap();
+    backend.set_fail_writes(true);
+    assert!(storage.put(b"b", b"2").is_err());
+    assert!(storage.force_flush().is_err());
+    backend.set_fail_writes(false);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    storage.put(b"c", b"3").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("3")));
+
+    // The next two writes succeed: the WAL append and the sync.
+    backend.fail_writes_after(2);
+    storage
+        .put_opt(
+            b"d",
+            b"4",
+            &WriteOptions {
+                sync: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(storage.put(b"e", b"5").is_err());
+}
@@ -0,0 +1,235 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    backend::{FaultInjectionBackend, MemoryBackend},
+    background_error::{BackgroundError, ErrorSeverity, ManifestWriteError},
+    column_family::ColumnFamily,
+    event_listener::{BackgroundErrorReason, EventListener},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+    tests::fixtures::{no_compaction_options, simple_options, with_users_column_family},
+};
+
+fn open(backend: &FaultInjectionBackend) -> Arc<MiniLsm> {
+    let mut options = no_compaction_options(false);
+    options.backend = Arc::new(backend.clone());
+    MiniLsm::open(Path::new("/mini-lsm-background-error-test"), options).unwrap()
+}
+
+/// Fills the immutable memtables up to the limit, so that the flush thread flushes one.
+fn fill_imm_memtables(storage: &MiniLsm) {
+    for i in 0..2 {
+        storage
+            .put(format!("key_{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+    }
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn wait_for_error(storage: &MiniLsm) -> BackgroundError {
+    let mut error = None;
+    wait_for(|| {
+        error = storage.background_error();
+        error.is_some()
+    });
+    error.unwrap()
+}
+
+#[test]
+fn test_transient_error() {
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let storage = open(&backend);
+    fill_imm_memtables(&storage);
+    backend.set_write_error_kind(io::ErrorKind::StorageFull);
+    backend.set_fail_writes(true);
+
+    let error = wait_for_error(&storage);
+    assert_eq!(error.reason, BackgroundErrorReason::Flush);
+    assert_eq!(error.severity, ErrorSeverity::Transient);
+    assert!(storage.put(b"key", b"value").is_err());
+    // Reads still work.
+    assert_eq!(storage.get(b"key_0").unwrap(), Some(Bytes::from("value")));
+    // The flush is retried with backoff until the disk has space again.
+    wait_for(|| {
+        storage
+            .background_error()
+            .is_some_and(|error| error.retries > 0)
+    });
+    assert!(storage.resume().is_err());
+
+    backend.set_fail_writes(false);
+    wait_for(|| storage.background_error().is_none());
+    storage.put(b"key", b"value").unwrap();
+    wait_for(|| storage.inner.state.read().imm_memtables.len() < 2);
+}
+
+#[test]
+fn test_resume() {
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let storage = open(&backend);
+    fill_imm_memtables(&storage);
+    backend.set_write_error_kind(io::ErrorKind::StorageFull);
+    backend.set_fail_writes(true);
+    wait_for_error(&storage);
+
+    backend.set_fail_writes(false);
+    storage.resume().unwrap();
+    assert_eq!(storage.background_error(), None);
+    assert!(storage.inner.state.read().imm_memtables.len() < 2);
+    storage.put(b"key", b"value").unwrap();
+}
+
+#[test]
+fn test_fatal_error() {
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let storage = open(&backend);
+    fill_imm_memtables(&storage);
+    backend.set_fail_writes(true);
+
+    let error = wait_for_error(&storage);
+    assert_eq!(error.reason, BackgroundErrorReason::Flush);
+    assert_eq!(error.severity, ErrorSeverity::Fatal);
+    // The DB stays read-only even once writes would succeed again.
+    backend.set_fail_writes(false);
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(storage.background_error(), Some(error));
+    assert!(storage.put(b"key", b"value").is_err());
+    assert!(storage.resume().is_err());
+    assert!(storage.force_flush().is_err());
+    assert_eq!(storage.get(b"key_1").unwrap(), Some(Bytes::from("value")));
+}
+
+#[test]
+fn test_compaction_error_cleared_by_its_column_family() {
+    let dir = tempdir().unwrap();
+    let options = with_users_column_family(simple_options(false));
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let users = storage.column_family("users").unwrap();
+    // Two L0 SSTs are enough to trigger a compaction.
+    let fill_l0 = |cf: &ColumnFamily| {
+        for _ in 0..2 {
+            storage.put_cf(cf, b"key", b"value").unwrap();
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            storage.flush_next_imm_memtable(cf).unwrap();
+        }
+    };
+    fill_l0(storage.default_cf());
+    fill_l0(&users);
+    // Stands in for a compaction of `users` that is still running.
+    let snapshot = users.state.read().clone();
+    let task = users
+        .compaction_controller
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    let reservation = storage
+        .running_compactions
+        .try_start(&users, &task, &snapshot)
+        .unwrap();
+    storage.report_background_error(
+        BackgroundErrorReason::Compaction,
+        Some("users"),
+        &anyhow!(io::Error::from(io::ErrorKind::StorageFull)),
+    );
+    wait_for(|| storage.background_error.may_run());
+
+    // Neither a compaction of another column family nor an idle tick clear the error.
+    storage.compact_column_families(&storage.column_families);
+    assert!(storage.default_cf().state.read().l0_sstables.is_empty());
+    assert_eq!(users.state.read().l0_sstables.len(), 2);
+    storage.compact_column_families(&storage.column_families);
+    assert!(storage.background_error.error().is_some());
+    assert!(storage.put(b"key", b"value").is_err());
+
+    drop(reservation);
+    storage.compact_column_families(&storage.column_families);
+    assert!(users.state.read().l0_sstables.is_empty());
+    assert_eq!(storage.background_error.error(), None);
+    storage.put(b"key", b"value").unwrap();
+}
+
+/// Counts the background errors it is notified of.
+#[derive(Debug, Default)]
+struct ErrorCounter(AtomicUsize);
+
+impl EventListener for ErrorCounter {
+    fn on_background_error(&self, _reason: BackgroundErrorReason, _error: &anyhow::Error) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_manifest_write_error_reported_once() {
+    // Finds the number of writes after which the compaction fails to write the manifest, but not
+    // the new SST.
+    for writes in 0.. {
+        let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+        let counter = Arc::new(ErrorCounter::default());
+        let options = LsmStorageOptions {
+            backend: Arc::new(backend.clone()),
+            event_listeners: vec![counter.clone()],
+            ..simple_options(false)
+        };
+        let storage = Arc::new(
+            LsmStorageInner::open(Path::new("/mini-lsm-manifest-error-test"), options).unwrap(),
+        );
+        for _ in 0..2 {
+            storage.put(b"key", b"value").unwrap();
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            storage
+                .flush_next_imm_memtable(storage.default_cf())
+                .unwrap();
+        }
+        backend.fail_writes_after(writes);
+        storage.compact_column_families(&storage.column_families);
+        let error = storage.background_error.error().unwrap();
+        if error.reason == BackgroundErrorReason::ManifestWrite {
+            assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+            break;
+        }
+        assert_eq!(error.reason, BackgroundErrorReason::Compaction);
+    }
+}
+
+#[test]
+fn test_error_severity() {
+    let full = || anyhow!(io::Error::from(io::ErrorKind::StorageFull));
+    assert_eq!(ErrorSeverity::of(&full()), ErrorSeverity::Transient);
+    assert_eq!(
+        ErrorSeverity::of(&full().context("failed to write SST")),
+        ErrorSeverity::Transient
+    );
+    assert_eq!(
+        ErrorSeverity::of(&full().context(ManifestWriteError)),
+        ErrorSeverity::Fatal
+    );
+    assert_eq!(
+        ErrorSeverity::of(&anyhow!("checksum mismatch")),
+        ErrorSeverity::Fatal
+    );
+    assert_eq!(
+        ErrorSeverity::of(&anyhow!(io::Error::from(io::ErrorKind::PermissionDenied))),
+        ErrorSeverity::Fatal
+    );
+}
@@ -0,0 +1,130 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    backend::{MemoryBackend, StorageBackend},
+    checkpoint::PinnedFile,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    tests::fixtures::{kv, no_compaction_options, with_users_column_family},
+    tests::harness::check_lsm_iter_result_by_key,
+    value_log::ValueLogOptions,
+};
+
+#[test]
+fn test_checkpoint_fixed_view() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(true);
+    let storage = MiniLsm::open(dir.path().join("db"), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+
+    let checkpoint_dir = dir.path().join("checkpoint");
+    storage.checkpoint(&checkpoint_dir).unwrap();
+    assert!(storage.checkpoint(&checkpoint_dir).is_err());
+    storage.put(b"a", b"3").unwrap();
+    storage.put(b"d", b"3").unwrap();
+
+    // The checkpoint has no WAL, as all memtables were flushed into it.
+    assert!(!std::fs::read_dir(&checkpoint_dir)
+        .unwrap()
+        .any(|entry| entry
+            .unwrap()
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == "wal")));
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options).unwrap();
+    check_lsm_iter_result_by_key(
+        &mut checkpoint.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "2"), kv("c", "2")],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "3"), kv("c", "2"), kv("d", "3")],
+    );
+
+    // The checkpoint and the database evolve independently.
+    checkpoint.put(b"e", b"4").unwrap();
+    checkpoint.force_flush().unwrap();
+    assert_eq!(storage.get(b"e").unwrap(), None);
+    storage.close().unwrap();
+    drop(storage);
+    std::fs::remove_dir_all(dir.path().join("db")).unwrap();
+    checkpoint.close().unwrap();
+    drop(checkpoint);
+    let checkpoint = MiniLsm::open(&checkpoint_dir, no_compaction_options(false)).unwrap();
+    assert_eq!(checkpoint.get(b"a").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(checkpoint.get(b"e").unwrap(), Some(Bytes::from("4")));
+}
+
+#[test]
+fn test_checkpoint_column_families_and_value_log() {
+    let dir = tempdir().unwrap();
+    let options = with_users_column_family(LsmStorageOptions {
+        value_log_options: Some(ValueLogOptions {
+            value_threshold: 1024,
+            gc_live_ratio: 0.5,
+        }),
+        ..no_compaction_options(false)
+    });
+    let storage = MiniLsm::open(dir.path().join("db"), options.clone()).unwrap();
+    let users = storage.column_family("users").unwrap();
+    let large_value = |i: usize| format!("value_{:03}_", i).repeat(200);
+    for i in 0..20 {
+        let key = format!("key_{:03}", i);
+        storage
+            .put(key.as_bytes(), large_value(i).as_bytes())
+            .unwrap();
+        storage.put_cf(&users, key.as_bytes(), b"user").unwrap();
+        if i % 5 == 4 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.force_full_compaction().unwrap();
+
+    let checkpoint_dir = dir.path().join("checkpoint");
+    storage.checkpoint(&checkpoint_dir).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    std::fs::remove_dir_all(dir.path().join("db")).unwrap();
+
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options).unwrap();
+    let users = checkpoint.column_family("users").unwrap();
+    for i in 0..20 {
+        let key = format!("key_{:03}", i);
+        assert_eq!(
+            checkpoint.get(key.as_bytes()).unwrap(),
+            Some(Bytes::from(large_value(i)))
+        );
+        assert_eq!(
+            checkpoint.get_cf(&users, key.as_bytes()).unwrap(),
+            Some(Bytes::from("user"))
+        );
+    }
+}
+
+#[test]
+fn test_checkpoint_copies_deleted_file() {
+    let backend = MemoryBackend::new();
+    let db_dir = Path::new("/mini-lsm-checkpoint-test/db");
+    let checkpoint_dir = Path::new("/mini-lsm-checkpoint-test/checkpoint");
+    backend.create_dir_all(db_dir).unwrap();
+    backend.create_dir_all(checkpoint_dir).unwrap();
+    // Larger than a copy chunk.
+    let data = (0..(5 << 19)).map(|i| i as u8).collect::<Vec<_>>();
+    let path = db_dir.join("00001.sst");
+    backend.create(&path).unwrap().append(&data).unwrap();
+
+    // A file deleted after it was pinned is copied from the open file.
+    let file = PinnedFile::open(&backend, path.clone()).unwrap();
+    backend.delete(&path).unwrap();
+    file.link_or_copy(&backend, checkpoint_dir).unwrap();
+    let copy = backend.open(&checkpoint_dir.join("00001.sst")).unwrap();
+    assert_eq!(copy.read_all().unwrap(), data);
+}
@@ -0,0 +1,235 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    column_family::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY},
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{MiniLsm, WriteBatchRecord},
+    tests::fixtures::{kv, no_compaction_options, with_users_column_family},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+#[test]
+fn test_column_family_isolation() {
+    let dir = tempdir().unwrap();
+    let storage =
+        MiniLsm::open(&dir, with_users_column_family(no_compaction_options(false))).unwrap();
+    assert!(storage.column_family("missing").is_none());
+    let default = storage.column_family(DEFAULT_COLUMN_FAMILY).unwrap();
+    let users = storage.column_family("users").unwrap();
+    assert_eq!(default.id(), 0);
+    assert_ne!(users.id(), 0);
+
+    storage.put(b"a", b"default").unwrap();
+    storage.put_cf(&users, b"a", b"users").unwrap();
+    storage.put_cf(&users, b"b", b"users").unwrap();
+    storage.force_flush().unwrap();
+    storage.delete_cf(&users, b"a").unwrap();
+    storage.put_cf(&default, b"c", b"default").unwrap();
+
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("default")));
+    assert_eq!(storage.get_cf(&users, b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "default"), kv("c", "default")],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf(&users, Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![kv("b", "users")],
+    );
+    // Each column family flushed to its own SST.
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    assert_eq!(users.state.read().l0_sstables.len(), 1);
+}
+
+#[test]
+fn test_write_batch_across_column_families() {
+    let dir = tempdir().unwrap();
+    let storage =
+        MiniLsm::open(&dir, with_users_column_family(no_compaction_options(false))).unwrap();
+    let default = storage.column_family(DEFAULT_COLUMN_FAMILY).unwrap();
+    let users = storage.column_family("users").unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage
+        .write_batch_cf(&[
+            (&*default, WriteBatchRecord::Put(b"a", b"1")),
+            (&*users, WriteBatchRecord::Put(b"a", b"2")),
+            (&*users, WriteBatchRecord::DelRange(b"b", b"c")),
+        ])
+        .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(
+        storage.get_cf(&users, b"a").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    // The whole batch shares one commit timestamp.
+    assert_eq!(snapshot.get(b"a").unwrap(), None);
+    assert_eq!(snapshot.get_cf(&users, b"a").unwrap(), None);
+
+    let txn = storage.new_txn().unwrap();
+    txn.put_cf(&users, b"b", b"3");
+    txn.delete(b"a");
+    assert_eq!(txn.get_cf(&users, b"b").unwrap(), Some(Bytes::from("3")));
+    assert_eq!(txn.get(b"b").unwrap(), None);
+    assert_eq!(txn.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf(&users, Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![kv("a", "2"), kv("b", "3")],
+    );
+}
+
+#[test]
+fn test_serializable_across_column_families() {
+    let dir = tempdir().unwrap();
+    let mut options = with_users_column_family(no_compaction_options(false));
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let users = storage.column_family("users").unwrap();
+    storage.put_cf(&users, b"key", b"1").unwrap();
+
+    // Writing the same key in another column family does not conflict.
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    assert_eq!(txn1.get_cf(&users, b"key").unwrap(), Some(Bytes::from("1")));
+    txn1.put(b"other", b"1");
+    txn2.put(b"key", b"2");
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    assert_eq!(txn1.get_cf(&users, b"key").unwrap(), Some(Bytes::from("1")));
+    txn1.put(b"other", b"2");
+    txn2.put_cf(&users, b"key", b"2");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+    assert_eq!(storage.get(b"other").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_column_family_recovery() {
+    let dir = tempdir().unwrap();
+    let options = with_users_column_family(no_compaction_options(true));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let users = storage.column_family("users").unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put_cf(&users, b"a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.put_cf(&users, b"b", b"3").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"c", b"4").unwrap();
+    // All column families switch to a new WAL together.
+    assert_eq!(storage.inner.state.read().imm_memtables.len(), 1);
+    assert_eq!(users.state.read().imm_memtables.len(), 1);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let users = storage.column_family("users").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("4")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf(&users, Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![kv("a", "2"), kv("b", "3")],
+    );
+    // Flush the memtables recovered from the WALs, so that only the current WAL is left.
+    while !storage.inner.state.read().imm_memtables.is_empty()
+        || !users.state.read().imm_memtables.is_empty()
+    {
+        storage.force_flush().unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+    let wals = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "wal")
+        })
+        .count();
+    assert_eq!(wals, 1);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let users = storage.column_family("users").unwrap();
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("4")));
+    assert_eq!(
+        storage.get_cf(&users, b"b").unwrap(),
+        Some(Bytes::from("3"))
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    // All column families of the DB must be in the options.
+    let mut options = options;
+    options.column_families.clear();
+    assert!(MiniLsm::open(&dir, options).is_err());
+}
+
+#[test]
+fn test_column_family_compaction_options() {
+    let dir = tempdir().unwrap();
+    let mut options = with_users_column_family(no_compaction_options(false));
+    options.column_families.push((
+        "events".to_string(),
+        ColumnFamilyOptions {
+            target_sst_size: 1 << 12,
+            compaction_options: CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                size_ratio_percent: 200,
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 2,
+            }),
+            ..options.column_family_options()
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let events = storage.column_family("events").unwrap();
+    for i in 0..200 {
+        let key = format!("key_{:05}", i);
+        storage.put(key.as_bytes(), b"default").unwrap();
+        storage
+            .put_cf(&events, key.as_bytes(), &[b'x'; 100])
+            .unwrap();
+    }
+    // The small memtables of `events` are flushed and compacted in the background.
+    while {
+        let state = events.state.read();
+        state.l0_sstables.len() >= 2 || state.levels.iter().all(|(_, ids)| ids.is_empty())
+    } {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    // The default column family is frozen along with `events`, but never compacted.
+    let state = storage.inner.state.read().clone();
+    assert!(state.levels[0].1.is_empty());
+    for i in (0..200).step_by(20) {
+        let key = format!("key_{:05}", i);
+        assert_eq!(
+            storage.get(key.as_bytes()).unwrap(),
+            Some(Bytes::from("default"))
+        );
+        assert_eq!(
+            storage.get_cf(&events, key.as_bytes()).unwrap(),
+            Some(Bytes::copy_from_slice(&[b'x'; 100]))
+        );
+    }
+}
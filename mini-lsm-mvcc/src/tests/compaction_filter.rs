@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::{
+    compaction_filter::{CompactionDecision, CompactionFilter},
+    lsm_storage::{LsmStorageInner, MiniLsm},
+    tests::fixtures::{no_compaction_options, simple_options},
+};
+
+/// Upgrades `v1:` values to `v2:`, purges `tenant2/` and drops `tmp_` keys, recording the entries
+/// it sees.
+#[derive(Debug, Default)]
+struct TestFilter {
+    seen: Mutex<Vec<(usize, Bytes, Bytes)>>,
+}
+
+impl CompactionFilter for TestFilter {
+    fn filter(&self, level: usize, key: &[u8], _ts: u64, value: &[u8]) -> CompactionDecision {
+        self.seen.lock().push((
+            level,
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+        ));
+        if key.starts_with(b"tenant2/") {
+            CompactionDecision::RemoveAndSkipUntil(Bytes::from("tenant3/"))
+        } else if key.starts_with(b"tmp_") {
+            CompactionDecision::Remove
+        } else if let Some(rest) = value.strip_prefix(b"v1:") {
+            CompactionDecision::ChangeValue([b"v2:", rest].concat().into())
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
+
+fn open(dir: &tempfile::TempDir) -> Arc<MiniLsm> {
+    let options = no_compaction_options(false);
+    MiniLsm::open(dir, options).unwrap()
+}
+
+#[test]
+fn test_compaction_filter() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let filter = Arc::new(TestFilter::default());
+    storage.add_compaction_filter(filter.clone());
+    for (key, value) in [
+        ("a", "v1:a"),
+        ("b", "v2:b"),
+        ("tenant2/a", "1"),
+        ("tenant2/b", "2"),
+        ("tenant2/c", "3"),
+        ("tenant3/a", "v1:t"),
+        ("tmp_a", "1"),
+    ] {
+        storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+    storage.delete(b"b").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"tmp_a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("v2:a")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    for key in ["tenant2/a", "tenant2/b", "tenant2/c", "tmp_a"] {
+        assert_eq!(storage.get(key.as_bytes()).unwrap(), None);
+    }
+    assert_eq!(
+        storage.get(b"tenant3/a").unwrap(),
+        Some(Bytes::from("v2:t"))
+    );
+
+    // The tombstone of `b`, the keys skipped after `tenant2/a` and the older version of `tmp_a`
+    // are not passed to the filter.
+    let seen = std::mem::take(&mut *filter.seen.lock());
+    assert_eq!(
+        seen,
+        vec![
+            (1, Bytes::from("a"), Bytes::from("v1:a")),
+            (1, Bytes::from("tenant2/a"), Bytes::from("1")),
+            (1, Bytes::from("tenant3/a"), Bytes::from("v1:t")),
+            (1, Bytes::from("tmp_a"), Bytes::from("2")),
+        ]
+    );
+
+    // Changed values are written back, so they are seen again as they are by the next compaction.
+    storage.force_full_compaction().unwrap();
+    let seen = std::mem::take(&mut *filter.seen.lock());
+    assert_eq!(
+        seen,
+        vec![
+            (1, Bytes::from("a"), Bytes::from("v2:a")),
+            (1, Bytes::from("tenant3/a"), Bytes::from("v2:t")),
+        ]
+    );
+}
+
+#[test]
+fn test_compaction_filter_keeps_versions_above_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let filter = Arc::new(TestFilter::default());
+    storage.add_compaction_filter(filter.clone());
+    storage.put(b"a", b"v1:1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    storage.put(b"a", b"v1:2").unwrap();
+    storage.put(b"tmp_a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    // Only the version read by the transaction is filtered, and `tmp_a` was written after it.
+    let seen = std::mem::take(&mut *filter.seen.lock());
+    assert_eq!(seen, vec![(1, Bytes::from("a"), Bytes::from("v1:1"))]);
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from("v2:1")));
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("v1:2")));
+    assert_eq!(storage.get(b"tmp_a").unwrap(), Some(Bytes::from("1")));
+
+    drop(txn);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("v2:2")));
+    assert_eq!(storage.get(b"tmp_a").unwrap(), None);
+}
+
+#[test]
+fn test_skipped_keys_stay_deleted_above_bottom_level() {
+    let dir = tempdir().unwrap();
+    let options = simple_options(false);
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let cf = storage.default_cf();
+    let flush = |keys: &[(&str, &str)]| {
+        for (key, value) in keys {
+            storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    };
+    // The old versions end up in the bottom level.
+    let old = [
+        ("tenant2/a", "old"),
+        ("tenant2/b", "old"),
+        ("tenant2/c", "old"),
+        ("tenant3/a", "old"),
+    ];
+    flush(&old);
+    flush(&old);
+    while storage.trigger_compaction(cf).unwrap() {}
+    assert!(storage.state.read().levels[0].1.is_empty());
+
+    storage.add_compaction_filter(Arc::new(TestFilter::default()));
+    flush(&[("tenant2/a", "new"), ("tenant2/b", "new")]);
+    storage.delete(b"tenant2/c").unwrap();
+    flush(&[("tenant3/a", "new")]);
+    // Only compacts L0 into L1, which is not the bottom level.
+    assert!(storage.trigger_compaction(cf).unwrap());
+    let snapshot = storage.state.read().clone();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert!(!snapshot.levels[0].1.is_empty());
+
+    for key in ["tenant2/a", "tenant2/b", "tenant2/c"] {
+        assert_eq!(storage.get(key.as_bytes()).unwrap(), None);
+    }
+    assert_eq!(storage.get(b"tenant3/a").unwrap(), Some(Bytes::from("new")));
+    while storage.trigger_compaction(cf).unwrap() {}
+    for key in ["tenant2/a", "tenant2/b", "tenant2/c"] {
+        assert_eq!(storage.get(key.as_bytes()).unwrap(), None);
+    }
+}
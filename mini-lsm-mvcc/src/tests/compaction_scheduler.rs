@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, CompactionTask, LeveledCompactionOptions, LeveledCompactionTask},
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+    tests::fixtures::simple_options,
+};
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    while !storage.state.read().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+/// Checks that the SSTs of each level below L0 are sorted and do not overlap.
+fn check_levels(storage: &LsmStorageInner) {
+    let snapshot = storage.state.read().clone();
+    for (level, ids) in &snapshot.levels {
+        for pair in ids.windows(2) {
+            let (prev, next) = (&snapshot.sstables[&pair[0]], &snapshot.sstables[&pair[1]]);
+            assert!(
+                prev.last_key().key_ref() < next.first_key().key_ref(),
+                "SSTs {} and {} of level {} overlap",
+                pair[0],
+                pair[1],
+                level
+            );
+        }
+    }
+}
+
+fn key_of(i: usize) -> String {
+    format!("key_{:05}", i)
+}
+
+#[test]
+fn test_subcompactions() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions {
+                target_sst_size: 1 << 12,
+                max_subcompactions: 4,
+                ..simple_options(false)
+            },
+        )
+        .unwrap(),
+    );
+    let mut expected = BTreeMap::new();
+    for round in 0..4 {
+        for i in (round..1000).step_by(3) {
+            let value = format!("value_{}_{}", round, i);
+            storage.put(key_of(i).as_bytes(), value.as_bytes()).unwrap();
+            expected.insert(key_of(i), value);
+        }
+        if round == 2 {
+            // The range tombstone spans several subcompactions.
+            storage
+                .delete_range(key_of(200).as_bytes(), key_of(700).as_bytes())
+                .unwrap();
+            expected.retain(|key, _| key.as_str() < "key_00200" || key.as_str() >= "key_00700");
+        }
+        flush(&storage);
+    }
+    storage.trigger_compaction(storage.default_cf()).unwrap();
+
+    let snapshot = storage.state.read().clone();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert!(snapshot.levels[0].1.len() > 1);
+    check_levels(&storage);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in &expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key.as_bytes());
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(storage.get(key_of(400).as_bytes()).unwrap(), None);
+}
+
+#[test]
+fn test_conflicting_tasks() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions {
+                target_sst_size: 1 << 12,
+                max_subcompactions: 4,
+                ..simple_options(false)
+            },
+        )
+        .unwrap(),
+    );
+    for round in 0..2 {
+        for i in 0..100 {
+            storage.put(key_of(i).as_bytes(), &[round; 10]).unwrap();
+        }
+        flush(&storage);
+    }
+    let cf = storage.default_cf();
+    let snapshot = storage.state.read().clone();
+    let task = cf
+        .compaction_controller
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    let reservation = storage
+        .running_compactions
+        .try_start(cf, &task, &snapshot)
+        .unwrap();
+    assert!(storage
+        .running_compactions
+        .try_start(cf, &task, &snapshot)
+        .is_none());
+    // Simple leveled compaction runs one task at a time, so nothing is compacted.
+    storage.trigger_compaction(cf).unwrap();
+    assert_eq!(storage.state.read().l0_sstables, snapshot.l0_sstables);
+
+    drop(reservation);
+    storage.trigger_compaction(cf).unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+}
+
+#[test]
+fn test_leveled_task_conflicts() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 10,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    // Four SSTs: `d` overlaps `a` and `b`, and `c` overlaps neither.
+    for range in [0..100, 200..300, 100..200, 50..250] {
+        for i in range {
+            storage.put(key_of(i).as_bytes(), b"value").unwrap();
+        }
+        flush(&storage);
+    }
+    let cf = storage.default_cf();
+    let snapshot = storage.state.read().clone();
+    let [d, c, b, a] = snapshot.l0_sstables[..] else {
+        panic!("expected 4 L0 SSTs");
+    };
+    let task = |upper_level: Option<usize>, upper_level_sst_ids: Vec<usize>| {
+        CompactionTask::Leveled(LeveledCompactionTask {
+            upper_level,
+            upper_level_sst_ids,
+            lower_level: upper_level.unwrap_or_default() + 1,
+            lower_level_sst_ids: Vec::new(),
+            is_lower_level_bottom_level: false,
+        })
+    };
+    let running = &storage.running_compactions;
+    let reservation_a = running
+        .try_start(cf, &task(Some(1), vec![a]), &snapshot)
+        .unwrap();
+    // Disjoint keys can be written to the same level at the same time.
+    let _b = running
+        .try_start(cf, &task(Some(1), vec![b]), &snapshot)
+        .unwrap();
+    // Inputs cannot be shared.
+    assert!(running
+        .try_start(cf, &task(Some(1), vec![a, c]), &snapshot)
+        .is_none());
+    // Overlapping keys cannot be written to the same level, nor to a level that another task
+    // reads them from, but to other levels.
+    assert!(running
+        .try_start(cf, &task(Some(1), vec![d]), &snapshot)
+        .is_none());
+    assert!(running
+        .try_start(cf, &task(Some(2), vec![d]), &snapshot)
+        .is_none());
+    let _d = running
+        .try_start(cf, &task(Some(3), vec![d]), &snapshot)
+        .unwrap();
+    // Only one task may read L0.
+    let _c = running
+        .try_start(cf, &task(None, vec![c]), &snapshot)
+        .unwrap();
+    drop(reservation_a);
+    assert!(running
+        .try_start(cf, &task(None, vec![a]), &snapshot)
+        .is_none());
+
+    // The controller does not see the SSTs that are being compacted.
+    let pending = running.pending_state(cf, &snapshot).unwrap();
+    assert_eq!(pending.l0_sstables, vec![a]);
+}
+
+#[test]
+fn test_leveled_compaction_into_compacting_level() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let cf = storage.default_cf();
+    for _ in 0..2 {
+        for i in 100..200 {
+            storage.put(key_of(i).as_bytes(), b"old").unwrap();
+        }
+        flush(&storage);
+    }
+    storage.trigger_compaction(cf).unwrap();
+    let snapshot = storage.state.read().clone();
+    let [l1_sst] = snapshot.levels[0].1[..] else {
+        panic!("expected 1 L1 SST");
+    };
+    // Stands in for an L1 -> L2 compaction of the SST that is still running.
+    let reservation = storage
+        .running_compactions
+        .try_start(
+            cf,
+            &CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level: Some(1),
+                upper_level_sst_ids: vec![l1_sst],
+                lower_level: 2,
+                lower_level_sst_ids: Vec::new(),
+                is_lower_level_bottom_level: true,
+            }),
+            &snapshot,
+        )
+        .unwrap();
+    // The L0 SSTs overlap the L1 SST, and have keys beyond it.
+    for _ in 0..2 {
+        for i in 150..300 {
+            storage.put(key_of(i).as_bytes(), b"new").unwrap();
+        }
+        flush(&storage);
+    }
+
+    // Compacting L0 into L1 now would add an SST that overlaps the L1 SST.
+    storage.trigger_compaction(cf).unwrap();
+    check_levels(&storage);
+    assert_eq!(storage.state.read().l0_sstables.len(), 2);
+    assert_eq!(
+        storage.get(key_of(120).as_bytes()).unwrap(),
+        Some(Bytes::from("old"))
+    );
+    assert_eq!(
+        storage.get(key_of(250).as_bytes()).unwrap(),
+        Some(Bytes::from("new"))
+    );
+
+    drop(reservation);
+    storage.trigger_compaction(cf).unwrap();
+    check_levels(&storage);
+    assert!(storage.state.read().l0_sstables.is_empty());
+    for (i, value) in [(120, "old"), (170, "new"), (250, "new")] {
+        assert_eq!(
+            storage.get(key_of(i).as_bytes()).unwrap(),
+            Some(Bytes::from(value))
+        );
+    }
+}
+
+#[test]
+fn test_concurrent_compactions() {
+    const THREADS: usize = 4;
+    const KEYS: usize = 2000;
+    const ROUNDS: usize = 3;
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 1,
+        },
+    ));
+    options.target_sst_size = 1 << 14;
+    options.max_background_compactions = 4;
+    options.max_subcompactions = 2;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let value_of = |round: usize, i: usize| format!("{:0>200}", round * KEYS * THREADS + i);
+    for round in 0..ROUNDS {
+        let handles = (0..THREADS)
+            .map(|thread| {
+                let storage = Arc::clone(&storage);
+                std::thread::spawn(move || {
+                    // The threads write interleaved keys, so that all SSTs overlap.
+                    for i in (thread..KEYS * THREADS).step_by(THREADS) {
+                        storage
+                            .put(key_of(i).as_bytes(), value_of(round, i).as_bytes())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // Once the lower levels fill up, L0 compactions run alongside compactions of the levels.
+        while !storage.inner.state.read().l0_sstables.is_empty() {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        check_levels(&storage.inner);
+    }
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_levels(&storage.inner);
+    for i in 0..KEYS * THREADS {
+        assert_eq!(
+            storage.get(key_of(i).as_bytes()).unwrap(),
+            Some(Bytes::from(value_of(ROUNDS - 1, i)))
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("tenant/entity/key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}_", idx).repeat(8).into_bytes()
+}
+
+/// Returns the compression type byte of the first block of the SST.
+fn first_block_compression(sst: &SsTable) -> u8 {
+    let end = sst
+        .block_meta
+        .get(1)
+        .map_or(sst.block_meta_offset, |meta| meta.offset);
+    sst.file.read(end as u64 - 5, 1).unwrap()[0]
+}
+
+fn build_sst(path: &Path, compression: CompressionType) -> (Arc<SsTable>, u64) {
+    let mut builder = SsTableBuilder::new(4096);
+    builder.set_compression(compression);
+    for i in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key_of(i), 1),
+            &value_of(i),
+        );
+    }
+    let sst = builder.build_for_test(path).unwrap();
+    (Arc::new(sst), std::fs::metadata(path).unwrap().len())
+}
+
+#[test]
+fn test_sst_compression() {
+    let dir = tempdir().unwrap();
+    let (_, uncompressed_size) = build_sst(&dir.path().join("0.sst"), CompressionType::None);
+    for compression in [CompressionType::Lz4, CompressionType::Deflate { level: 9 }] {
+        let path = dir.path().join(format!("{:?}.sst", compression));
+        let (sst, size) = build_sst(&path, compression);
+        assert!(size * 2 < uncompressed_size, "{:?}", compression);
+        assert_ne!(first_block_compression(&sst), 0);
+        // Read the SST back from disk, without the block cache.
+        let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+        let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+        for i in 0..1000 {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key().key_ref(), &key_of(i)[..]);
+            assert_eq!(iter.value(), &value_of(i)[..]);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_incompressible_block_stored_raw() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    builder.set_compression(CompressionType::Lz4);
+    let value = (0..200).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"key", 1), &value);
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(first_block_compression(&sst), 0);
+    let iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    assert_eq!(iter.value(), &value[..]);
+}
+
+#[test]
+fn test_compressed_block_checksum() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    build_sst(&path, CompressionType::Lz4);
+    let mut data = std::fs::read(&path).unwrap();
+    data[10] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+    let sst = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.read_block(0).is_err());
+}
+
+#[test]
+fn test_compression_per_level() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.compression_per_level = vec![CompressionType::None, CompressionType::Lz4];
+    options.target_sst_size = 1 << 14;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for i in 0..500 {
+            storage.put(&key_of(i), &value_of(i + round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    while {
+        let snapshot = storage.inner.state.read();
+        snapshot.l0_sstables.len() >= 2
+    } {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    let state = storage.inner.state.read().clone();
+    assert!(state.levels.iter().any(|(_, ids)| !ids.is_empty()));
+    for id in &state.l0_sstables {
+        assert_eq!(first_block_compression(&state.sstables[id]), 0);
+    }
+    for (_, ids) in &state.levels {
+        for id in ids {
+            assert_eq!(first_block_compression(&state.sstables[id]), 1);
+        }
+    }
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for i in 0..500 {
+        assert_eq!(iter.key(), &key_of(i)[..]);
+        assert_eq!(iter.value(), &value_of(i + 2)[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(
+        storage.get(&key_of(42)).unwrap(),
+        Some(Bytes::from(value_of(44)))
+    );
+}
@@ -0,0 +1,243 @@
+//! Crash-consistency tests. A randomized workload runs against a storage backend that starts
+//! failing every write after a random number of them, which cuts the workload at an arbitrary
+//! point: between a WAL append and its sync, between writing an SST and recording it in the
+//! manifest, in the middle of a compaction, and so on. Everything that was not synced is then
+//! dropped as if the power went out, and the recovered database must match the workload up to
+//! some point after the last write it acknowledged as durable.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{
+    backend::{FaultInjectionBackend, MemoryBackend},
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, WriteBatchRecord, WriteOptions},
+    tests::fixtures::simple_options,
+};
+
+const KEYS: usize = 20;
+const OPS: usize = 100;
+/// Crash points are picked among roughly this many writes to the backend.
+const MAX_WRITES: usize = 400;
+
+type State = BTreeMap<Vec<u8>, Vec<u8>>;
+
+enum Op {
+    /// Writes a value to a key, or deletes the key if the value is `None`.
+    Write(usize, Option<usize>, WriteOptions),
+    Batch(Vec<(usize, Option<usize>)>, WriteOptions),
+    /// Flushes all memtables.
+    Flush,
+    Compact,
+}
+
+impl Op {
+    fn random(rng: &mut StdRng) -> Self {
+        let write = |rng: &mut StdRng| {
+            let key = rng.gen_range(0..KEYS);
+            let value = rng.gen_bool(0.8).then(|| rng.gen_range(0..1000));
+            (key, value)
+        };
+        let options = WriteOptions {
+            sync: rng.gen_bool(0.2),
+            ..Default::default()
+        };
+        match rng.gen_range(0..100) {
+            0..=59 => {
+                let (key, value) = write(rng);
+                Op::Write(key, value, options)
+            }
+            60..=79 => {
+                let len = rng.gen_range(2..5);
+                Op::Batch((0..len).map(|_| write(rng)).collect(), options)
+            }
+            80..=91 => Op::Flush,
+            _ => Op::Compact,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Write(..) => "write",
+            Op::Batch(..) => "batch",
+            Op::Flush => "flush",
+            Op::Compact => "compact",
+        }
+    }
+
+    /// Whether everything before and including the op is durable once it succeeds.
+    fn is_durable(&self) -> bool {
+        match self {
+            Op::Write(_, _, options) | Op::Batch(_, options) => options.sync,
+            Op::Flush => true,
+            Op::Compact => false,
+        }
+    }
+
+    fn apply(&self, state: &mut State) {
+        let writes = match self {
+            Op::Write(key, value, _) => vec![(*key, *value)],
+            Op::Batch(writes, _) => writes.clone(),
+            Op::Flush | Op::Compact => Vec::new(),
+        };
+        for (key, value) in writes {
+            match value {
+                Some(value) => state.insert(key_of(key), value_of(value)),
+                None => state.remove(&key_of(key)),
+            };
+        }
+    }
+
+    fn execute(&self, storage: &Arc<LsmStorageInner>) -> Result<()> {
+        match self {
+            Op::Write(key, Some(value), options) => {
+                storage.put_opt(&key_of(*key), &value_of(*value), options)
+            }
+            Op::Write(key, None, options) => storage.delete_opt(&key_of(*key), options),
+            Op::Batch(writes, options) => {
+                let batch = writes
+                    .iter()
+                    .map(|(key, value)| match value {
+                        Some(value) => WriteBatchRecord::Put(key_of(*key), value_of(*value)),
+                        None => WriteBatchRecord::Del(key_of(*key)),
+                    })
+                    .collect::<Vec<_>>();
+                storage.write_batch_opt(&batch, options)
+            }
+            Op::Flush => {
+                if storage.has_unfrozen_data() {
+                    storage.freeze_memtables(None, &storage.state_lock.lock())?;
+                }
+                while !storage.state.read().imm_memtables.is_empty() {
+                    storage.force_flush_next_imm_memtable()?;
+                }
+                Ok(())
+            }
+            Op::Compact => storage.trigger_compaction(storage.default_cf()).map(|_| ()),
+        }
+    }
+}
+
+fn key_of(key: usize) -> Vec<u8> {
+    format!("key_{:03}", key).into_bytes()
+}
+
+fn value_of(value: usize) -> Vec<u8> {
+    format!("value_{:04}", value).into_bytes()
+}
+
+/// Reads all key-value pairs of the storage.
+fn read_state(storage: &Arc<LsmStorageInner>) -> State {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut state = State::new();
+    while iter.is_valid() {
+        state.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    state
+}
+
+/// Runs the workload of a seed until the injected crash, and checks what is recovered. Returns the
+/// kind of op that was cut by the crash, if any.
+fn run_crash_test(seed: u64) -> Option<&'static str> {
+    let path = Path::new("/mini-lsm-crash-test");
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            path,
+            LsmStorageOptions {
+                backend: Arc::new(backend.clone()),
+                ..simple_options(true)
+            },
+        )
+        .unwrap(),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    backend.fail_writes_after(rng.gen_range(0..MAX_WRITES));
+
+    // The state after each op. The op cut by the crash may or may not have been applied.
+    let mut states = vec![State::new()];
+    // The number of ops whose effects must survive the crash.
+    let mut durable = 0;
+    let mut crashed_in = None;
+    for _ in 0..OPS {
+        let op = Op::random(&mut rng);
+        let mut state = states.last().unwrap().clone();
+        op.apply(&mut state);
+        states.push(state);
+        if op.execute(&storage).is_err() {
+            crashed_in = Some(op.name());
+            break;
+        }
+        if op.is_durable() {
+            durable = states.len() - 1;
+        }
+    }
+
+    drop(storage);
+    backend.set_fail_writes(false);
+    backend.drop_unsynced_writes().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            path,
+            LsmStorageOptions {
+                backend: Arc::new(backend.clone()),
+                ..simple_options(true)
+            },
+        )
+        .unwrap(),
+    );
+    let recovered = read_state(&storage);
+    assert!(
+        states[durable..].contains(&recovered),
+        "seed {}: the recovered state does not match the workload after op {} or later, crashed in {:?}",
+        seed,
+        durable,
+        crashed_in
+    );
+
+    // The recovered database keeps working.
+    storage.put(b"after_crash", b"1").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            path,
+            LsmStorageOptions {
+                backend: Arc::new(backend.clone()),
+                ..simple_options(true)
+            },
+        )
+        .unwrap(),
+    );
+    assert_eq!(
+        storage.get(b"after_crash").unwrap(),
+        Some(bytes::Bytes::from_static(b"1"))
+    );
+    crashed_in
+}
+
+#[test]
+fn test_crash_consistency() {
+    let mut crashes = HashMap::<&str, usize>::new();
+    for seed in 0..200 {
+        if let Some(op) = run_crash_test(seed) {
+            *crashes.entry(op).or_default() += 1;
+        }
+    }
+    // Make sure the crashes cut every kind of op.
+    for op in ["write", "batch", "flush", "compact"] {
+        assert!(
+            crashes.get(op).copied().unwrap_or_default() > 0,
+            "no crash in {}: {:?}",
+            op,
+            crashes
+        );
+    }
+}
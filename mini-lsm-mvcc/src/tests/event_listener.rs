@@ -0,0 +1,220 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::{
+    backend::{FaultInjectionBackend, MemoryBackend},
+    event_listener::{
+        BackgroundErrorReason, CompactionJobInfo, EventListener, FlushJobInfo,
+        TableFileDeletionInfo, WriteStallInfo,
+    },
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+    tests::fixtures::simple_options,
+    write_stall::{WriteStallCause, WriteStallOptions},
+};
+
+#[derive(Debug)]
+enum Event {
+    Flush(FlushJobInfo),
+    CompactionBegin(CompactionJobInfo),
+    CompactionCompleted(CompactionJobInfo),
+    TableFileDeleted(TableFileDeletionInfo),
+    StallConditionsChanged(WriteStallInfo),
+    BackgroundError(BackgroundErrorReason, String),
+}
+
+#[derive(Debug, Default)]
+struct RecordingListener {
+    events: Mutex<Vec<Event>>,
+}
+
+impl RecordingListener {
+    fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock())
+    }
+}
+
+impl EventListener for RecordingListener {
+    fn on_flush_completed(&self, info: &FlushJobInfo) {
+        self.events.lock().push(Event::Flush(info.clone()));
+    }
+
+    fn on_compaction_begin(&self, info: &CompactionJobInfo) {
+        self.events
+            .lock()
+            .push(Event::CompactionBegin(info.clone()));
+    }
+
+    fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+        self.events
+            .lock()
+            .push(Event::CompactionCompleted(info.clone()));
+    }
+
+    fn on_table_file_deleted(&self, info: &TableFileDeletionInfo) {
+        self.events
+            .lock()
+            .push(Event::TableFileDeleted(info.clone()));
+    }
+
+    fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+        self.events
+            .lock()
+            .push(Event::StallConditionsChanged(info.clone()));
+    }
+
+    fn on_background_error(&self, reason: BackgroundErrorReason, error: &anyhow::Error) {
+        self.events
+            .lock()
+            .push(Event::BackgroundError(reason, format!("{:#}", error)));
+    }
+}
+
+fn flush(storage: &Arc<LsmStorageInner>) {
+    storage.put(b"key", b"value").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_flush_and_compaction_events() {
+    let dir = tempdir().unwrap();
+    let listener = Arc::new(RecordingListener::default());
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions {
+                event_listeners: vec![listener.clone()],
+                ..simple_options(false)
+            },
+        )
+        .unwrap(),
+    );
+    flush(&storage);
+    flush(&storage);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    let events = listener.take();
+    assert_eq!(events.len(), 2);
+    for (event, &id) in events.iter().zip(l0_sstables.iter().rev()) {
+        let Event::Flush(info) = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(info.column_family, "default");
+        assert_eq!(info.sst_id, id);
+        assert_eq!(
+            info.file_size,
+            storage.state.read().sstables[&id].table_size()
+        );
+    }
+    let input_bytes = l0_sstables
+        .iter()
+        .map(|id| storage.state.read().sstables[id].table_size())
+        .sum::<u64>();
+
+    storage.trigger_compaction(storage.default_cf()).unwrap();
+    let events = listener.take();
+    assert_eq!(events.len(), 4, "{:?}", events);
+    let Event::CompactionBegin(begin) = &events[0] else {
+        panic!("unexpected event {:?}", events[0]);
+    };
+    assert_eq!(begin.input_sst_ids, l0_sstables);
+    assert_eq!(begin.input_bytes, input_bytes);
+    assert!(begin.output_sst_ids.is_empty());
+    let Event::CompactionCompleted(completed) = &events[1] else {
+        panic!("unexpected event {:?}", events[1]);
+    };
+    let snapshot = storage.state.read().clone();
+    assert_eq!(completed.input_sst_ids, l0_sstables);
+    assert_eq!(completed.output_sst_ids, snapshot.levels[0].1);
+    assert_eq!(
+        completed.output_bytes,
+        completed
+            .output_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>()
+    );
+    for (event, &id) in events[2..].iter().zip(&l0_sstables) {
+        let Event::TableFileDeleted(info) = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(info.sst_id, id);
+        assert!(!info.path.exists());
+    }
+}
+
+#[test]
+fn test_stall_conditions_changed() {
+    let dir = tempdir().unwrap();
+    let listener = Arc::new(RecordingListener::default());
+    let mut options = LsmStorageOptions {
+        event_listeners: vec![listener.clone()],
+        ..simple_options(false)
+    };
+    options.write_stall_options = Some(WriteStallOptions {
+        imm_memtable_slowdown_trigger: 2,
+        imm_memtable_stop_trigger: 3,
+        ..Default::default()
+    });
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for _ in 0..2 {
+        storage.put(b"key", b"value").unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+    }
+    storage.force_flush_next_imm_memtable().unwrap();
+    let events = listener.take();
+    let stalls = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::StallConditionsChanged(info) => Some(info),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(stalls.len(), 2, "{:?}", events);
+    assert!(stalls[0].previous.is_none());
+    let current = stalls[0].current.as_ref().unwrap();
+    assert_eq!(current.cause, WriteStallCause::ImmMemtables(2));
+    assert!(!current.stopped);
+    assert_eq!(stalls[1].previous.as_ref(), Some(current));
+    assert!(stalls[1].current.is_none());
+}
+
+#[test]
+fn test_background_error() {
+    let backend = FaultInjectionBackend::new(Arc::new(MemoryBackend::new()));
+    let listener = Arc::new(RecordingListener::default());
+    let mut options = LsmStorageOptions {
+        event_listeners: vec![listener.clone()],
+        ..simple_options(false)
+    };
+    options.backend = Arc::new(backend.clone());
+    let storage = MiniLsm::open(Path::new("/mini-lsm-listener-test"), options).unwrap();
+    for _ in 0..2 {
+        storage.put(b"key", b"value").unwrap();
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+    }
+    // The flush thread fails to write the SST.
+    backend.set_fail_writes(true);
+    let start = Instant::now();
+    loop {
+        let events = listener.take();
+        if let Some(Event::BackgroundError(reason, message)) = events.first() {
+            assert_eq!(*reason, BackgroundErrorReason::Flush);
+            assert!(!message.is_empty());
+            break;
+        }
+        assert!(events.is_empty(), "{:?}", events);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
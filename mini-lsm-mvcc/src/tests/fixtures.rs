@@ -0,0 +1,45 @@
+//! Options and helpers shared by the tests of the features of this crate.
+
+use bytes::Bytes;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::LsmStorageOptions,
+};
+
+pub fn kv(key: &str, value: &str) -> (Bytes, Bytes) {
+    (
+        Bytes::copy_from_slice(key.as_bytes()),
+        Bytes::copy_from_slice(value.as_bytes()),
+    )
+}
+
+/// Week 2 options without compaction.
+pub fn no_compaction_options(enable_wal: bool) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = enable_wal;
+    options
+}
+
+/// A simple leveled compaction that runs as soon as L0 has two SSTs.
+pub fn simple_compaction_options() -> CompactionOptions {
+    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    })
+}
+
+/// Week 2 options with `simple_compaction_options`.
+pub fn simple_options(enable_wal: bool) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(simple_compaction_options());
+    options.enable_wal = enable_wal;
+    options
+}
+
+/// Adds a `users` column family with the options of the default one.
+pub fn with_users_column_family(mut options: LsmStorageOptions) -> LsmStorageOptions {
+    let users = options.column_family_options();
+    options.column_families = vec![("users".to_string(), users)];
+    options
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    group_commit::{PendingWrite, WriteQueue},
+    lsm_storage::{MiniLsm, WriteBatchRecord, WriteOptions},
+    tests::fixtures::no_compaction_options,
+};
+
+#[test]
+fn test_concurrent_writes() {
+    const THREADS: usize = 8;
+    const KEYS: usize = 200;
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    let handles = (0..THREADS)
+        .map(|thread| {
+            let storage = Arc::clone(&storage);
+            std::thread::spawn(move || {
+                for i in 0..KEYS {
+                    let key = format!("{:02}-{:04}", thread, i);
+                    if i % 2 == 0 {
+                        storage.put(key.as_bytes(), b"value").unwrap();
+                    } else {
+                        storage
+                            .write_batch_opt(
+                                &[
+                                    WriteBatchRecord::Put(key.as_bytes(), b"value".as_slice()),
+                                    WriteBatchRecord::Put(b"last", key.as_bytes()),
+                                ],
+                                &WriteOptions {
+                                    sync: i % 10 == 1,
+                                    ..Default::default()
+                                },
+                            )
+                            .unwrap();
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // Each write was committed under its own timestamp.
+    assert_eq!(
+        storage.inner.mvcc().latest_commit_ts(),
+        (THREADS * KEYS) as u64
+    );
+    let check = |storage: &MiniLsm| {
+        for thread in 0..THREADS {
+            for i in 0..KEYS {
+                let key = format!("{:02}-{:04}", thread, i);
+                assert_eq!(
+                    storage.get(key.as_bytes()).unwrap(),
+                    Some(Bytes::from_static(b"value"))
+                );
+            }
+        }
+    };
+    check(&storage);
+    let last = storage.get(b"last").unwrap();
+    assert!(last.is_some());
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    check(&storage);
+    assert_eq!(storage.get(b"last").unwrap(), last);
+}
+
+#[test]
+fn test_sync_write() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    let sync = WriteOptions {
+        sync: true,
+        ..Default::default()
+    };
+    storage.put_opt(b"synced", b"1", &sync).unwrap();
+    storage
+        .delete_opt(b"synced", &WriteOptions::default())
+        .unwrap();
+    storage.put_opt(b"synced", b"2", &sync).unwrap();
+
+    // Copy the files of the open storage, as if the process had crashed.
+    let copy = tempdir().unwrap();
+    for entry in std::fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, copy.path().join(path.file_name().unwrap())).unwrap();
+    }
+    let recovered = MiniLsm::open(&copy, no_compaction_options(true)).unwrap();
+    assert_eq!(
+        recovered.get(b"synced").unwrap(),
+        Some(Bytes::from_static(b"2"))
+    );
+}
+
+#[test]
+fn test_leader_panic_fails_followers() {
+    let queue = Arc::new(WriteQueue::default());
+    let (release, released) = crossbeam_channel::bounded::<()>(0);
+    let first = {
+        let queue = queue.clone();
+        std::thread::spawn(move || {
+            queue.write(PendingWrite::new(Vec::new(), false), |_| {
+                released.recv().unwrap();
+                Ok(vec![1])
+            })
+        })
+    };
+    // The next two writes queue up behind the first one, and are committed as a group whose
+    // leader panics.
+    let writers = (0..2)
+        .map(|_| {
+            std::thread::sleep(Duration::from_millis(50));
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                queue.write(PendingWrite::new(Vec::new(), false), |_| {
+                    panic!("injected panic")
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    std::thread::sleep(Duration::from_millis(50));
+    release.send(()).unwrap();
+    assert_eq!(first.join().unwrap().unwrap(), 1);
+    let results = writers
+        .into_iter()
+        .map(|writer| writer.join())
+        .collect::<Vec<_>>();
+    assert!(results[0].is_err());
+    assert!(results[1].as_ref().unwrap().is_err());
+
+    // The queue is still usable.
+    let write = PendingWrite::new(Vec::new(), false);
+    assert_eq!(queue.write(write, |_| Ok(vec![2])).unwrap(), 2);
+}
@@ -0,0 +1,306 @@
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockBuilder, BlockIterator},
+    iterators::StorageIterator,
+    key::{KeyBytes, KeySlice},
+    lsm_storage::MiniLsm,
+    mem_table::MemTable,
+    range_tombstone::RangeTombstone,
+    table::{
+        bloom::Bloom, BlockMeta, FileObject, SsTable, SsTableBuilder, SsTableIterator,
+        SST_FORMAT_V3, SST_FORMAT_V4, SST_MAGIC,
+    },
+};
+
+use super::fixtures::no_compaction_options;
+use super::harness::check_iter_result_by_key_and_ts;
+
+fn large_value(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| seed.wrapping_add(i as u8)).collect()
+}
+
+#[test]
+fn test_block_large_key_value() {
+    let key = vec![b'k'; 70000];
+    let value = large_value(1, 300 << 10);
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(KeySlice::for_testing_from_slice_with_ts(&key, 1), &value));
+    assert!(!builder.add(KeySlice::for_testing_from_slice_with_ts(b"z", 1), b"v"));
+    let block = builder.build();
+    let decoded = Block::decode(&block.encode());
+    assert_eq!(block.offsets, decoded.offsets);
+    assert_eq!(block.data, decoded.data);
+    let iter = BlockIterator::create_and_seek_to_first(Arc::new(decoded));
+    assert_eq!(iter.key().for_testing_key_ref(), &key[..]);
+    assert_eq!(iter.value(), &value[..]);
+}
+
+#[test]
+fn test_sst_oversized_entry_own_block() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    let big = large_value(7, 200 << 10);
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"a", 1), b"1");
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"b", 1), &big);
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"c", 1), b"3");
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.num_of_blocks(), 3);
+    let sst = Arc::new(
+        SsTable::open(
+            1,
+            None,
+            FileObject::open(&dir.path().join("1.sst")).unwrap(),
+        )
+        .unwrap(),
+    );
+    check_iter_result_by_key_and_ts(
+        &mut SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+        vec![
+            ((Bytes::from("a"), 1), Bytes::from("1")),
+            ((Bytes::from("b"), 1), Bytes::from(big)),
+            ((Bytes::from("c"), 1), Bytes::from("3")),
+        ],
+    );
+}
+
+/// Writes a single-block SST in the legacy format with `u16` lengths and offsets.
+fn write_legacy_sst(path: &std::path::Path, data: Vec<((Bytes, u64), Bytes)>) {
+    let mut block = Vec::new();
+    let mut offsets = Vec::new();
+    for ((key, ts), value) in &data {
+        offsets.push(block.len() as u16);
+        block.put_u16(0);
+        block.put_u16(key.len() as u16);
+        block.put_slice(key);
+        block.put_u64(*ts);
+        block.put_u16(value.len() as u16);
+        block.put_slice(value);
+    }
+    for offset in &offsets {
+        block.put_u16(*offset);
+    }
+    block.put_u16(offsets.len() as u16);
+    let mut buf = Vec::new();
+    buf.put_slice(&block);
+    buf.put_u32(crc32fast::hash(&block));
+
+    let meta_offset = buf.len();
+    let ((first_key, first_ts), _) = data.first().unwrap();
+    let ((last_key, last_ts), _) = data.last().unwrap();
+    buf.put_u32(1);
+    let meta_begin = buf.len();
+    buf.put_u32(0);
+    buf.put_u16(first_key.len() as u16);
+    buf.put_slice(first_key);
+    buf.put_u64(*first_ts);
+    buf.put_u16(last_key.len() as u16);
+    buf.put_slice(last_key);
+    buf.put_u64(*last_ts);
+    buf.put_u64(data.iter().map(|((_, ts), _)| *ts).max().unwrap());
+    let checksum = crc32fast::hash(&buf[meta_begin..]);
+    buf.put_u32(checksum);
+    buf.put_u32(meta_offset as u32);
+
+    let key_hashes = data
+        .iter()
+        .map(|((key, _), _)| farmhash::fingerprint32(key))
+        .collect::<Vec<_>>();
+    let bloom = Bloom::build_from_key_hashes(
+        &key_hashes,
+        Bloom::bloom_bits_per_key(key_hashes.len(), 0.01),
+    );
+    let bloom_offset = buf.len();
+    bloom.encode(&mut buf);
+    buf.put_u32(bloom_offset as u32);
+    std::fs::write(path, buf).unwrap();
+}
+
+#[test]
+fn test_legacy_sst_readable() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    write_legacy_sst(
+        &path,
+        vec![
+            ((Bytes::from("key1"), 3), Bytes::from("value1")),
+            ((Bytes::from("key2"), 2), Bytes::from("value2")),
+            ((Bytes::from("key3"), 1), Bytes::new()),
+        ],
+    );
+    let sst = Arc::new(SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.max_ts(), 3);
+    check_iter_result_by_key_and_ts(
+        &mut SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap(),
+        vec![
+            ((Bytes::from("key1"), 3), Bytes::from("value1")),
+            ((Bytes::from("key2"), 2), Bytes::from("value2")),
+            ((Bytes::from("key3"), 1), Bytes::new()),
+        ],
+    );
+    let iter = SsTableIterator::create_and_seek_to_key(
+        sst,
+        KeySlice::for_testing_from_slice_with_ts(b"key2", 5),
+    )
+    .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"key2");
+}
+
+/// Writes an SST in the v3 or v4 format. v3 has no range tombstones and v4 has no prefix bloom
+/// filter.
+fn write_old_sst(path: &std::path::Path, data: &[(Bytes, Bytes)], version: u32) {
+    let mut buf = Vec::new();
+    let mut meta = Vec::new();
+    for chunk in data.chunks(4) {
+        let mut builder = BlockBuilder::new(4096);
+        for (key, value) in chunk {
+            assert!(builder.add(KeySlice::for_testing_from_slice_with_ts(key, 1), value));
+        }
+        let block = builder.build().encode();
+        meta.push(BlockMeta {
+            offset: buf.len(),
+            first_key: KeyBytes::from_bytes_with_ts(chunk[0].0.clone(), 1),
+            last_key: KeyBytes::from_bytes_with_ts(chunk.last().unwrap().0.clone(), 1),
+        });
+        buf.put_slice(&block);
+        buf.put_u32(crc32fast::hash(&block));
+    }
+    let meta_offset = buf.len();
+    BlockMeta::encode_block_meta(&meta, 1, &mut buf);
+    buf.put_u32(meta_offset as u32);
+    let key_hashes = data
+        .iter()
+        .map(|(key, _)| farmhash::fingerprint32(key))
+        .collect::<Vec<_>>();
+    let bloom = Bloom::build_from_key_hashes(
+        &key_hashes,
+        Bloom::bloom_bits_per_key(key_hashes.len(), 0.01),
+    );
+    let bloom_offset = buf.len();
+    bloom.encode(&mut buf);
+    buf.put_u32(bloom_offset as u32);
+    // No value log references.
+    let refs_offset = buf.len();
+    buf.put_u32(0);
+    buf.put_u32(crc32fast::hash(&0u32.to_be_bytes()));
+    buf.put_u32(refs_offset as u32);
+    if version >= SST_FORMAT_V4 {
+        let range_tombstones_offset = buf.len();
+        RangeTombstone::encode_list(&[], &mut buf);
+        buf.put_u32(range_tombstones_offset as u32);
+    }
+    buf.put_u32(version);
+    buf.put_u32(SST_MAGIC);
+    std::fs::write(path, buf).unwrap();
+}
+
+fn check_old_sst_readable(version: u32) {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let data = (0..20)
+        .map(|i| {
+            (
+                Bytes::from(format!("key{:02}", i)),
+                Bytes::from(format!("value{}", i)),
+            )
+        })
+        .collect::<Vec<_>>();
+    write_old_sst(&path, &data, version);
+    let sst = Arc::new(SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+    assert!(sst.range_tombstones().is_empty());
+    check_iter_result_by_key_and_ts(
+        &mut SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+        data.into_iter()
+            .map(|(key, value)| ((key, 1), value))
+            .collect(),
+    );
+}
+
+#[test]
+fn test_v3_sst_readable() {
+    check_old_sst_readable(SST_FORMAT_V3);
+}
+
+#[test]
+fn test_v4_sst_readable() {
+    check_old_sst_readable(SST_FORMAT_V4);
+}
+
+#[test]
+fn test_wal_large_value() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let value = large_value(3, 300 << 10);
+    {
+        let memtable = MemTable::create_with_wal(1, &path).unwrap();
+        memtable
+            .put(KeySlice::for_testing_from_slice_with_ts(b"key", 1), &value)
+            .unwrap();
+        memtable.sync_wal().unwrap();
+    }
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    assert_eq!(
+        memtable.get(KeySlice::for_testing_from_slice_with_ts(b"key", 1)),
+        Some(Bytes::from(value))
+    );
+}
+
+#[test]
+fn test_legacy_wal_readable() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let mut buf = Vec::new();
+    for (key, ts, value) in [(&b"key1"[..], 1, &b"value1"[..]), (b"key2", 2, b"")] {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.write_u16(key.len() as u16);
+        hasher.write(key);
+        hasher.write_u64(ts);
+        hasher.write_u16(value.len() as u16);
+        hasher.write(value);
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(key);
+        buf.put_u64(ts);
+        buf.put_u16(value.len() as u16);
+        buf.put_slice(value);
+        buf.put_u32(hasher.finalize());
+    }
+    std::fs::write(&path, buf).unwrap();
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    assert_eq!(
+        memtable.get(KeySlice::for_testing_from_slice_with_ts(b"key1", 1)),
+        Some(Bytes::from("value1"))
+    );
+    assert_eq!(
+        memtable.get(KeySlice::for_testing_from_slice_with_ts(b"key2", 2)),
+        Some(Bytes::new())
+    );
+}
+
+#[test]
+fn test_integration_large_value() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(true);
+    let value1 = large_value(1, 500 << 10);
+    let value2 = large_value(2, 100 << 10);
+    {
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        storage.put(b"doc1", &value1).unwrap();
+        storage.put(b"doc2", b"small").unwrap();
+        storage.force_flush().unwrap();
+        storage.put(b"doc3", &value2).unwrap();
+        assert_eq!(
+            storage.get(b"doc1").unwrap(),
+            Some(Bytes::from(value1.clone()))
+        );
+        storage.close().unwrap();
+    }
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"doc1").unwrap(), Some(Bytes::from(value1)));
+    assert_eq!(storage.get(b"doc2").unwrap(), Some(Bytes::from("small")));
+    assert_eq!(storage.get(b"doc3").unwrap(), Some(Bytes::from(value2)));
+}
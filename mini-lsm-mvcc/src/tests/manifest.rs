@@ -0,0 +1,266 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    backend::LocalBackend,
+    compact::{
+        CompactionTask, LeveledCompactionTask, SimpleLeveledCompactionTask, TieredCompactionTask,
+    },
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    manifest::{Manifest, ManifestRecord},
+    recovery::RecoveryMode,
+    tests::fixtures::{no_compaction_options, with_users_column_family},
+};
+
+fn manifest_files(path: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("MANIFEST"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn current_manifest(path: &Path) -> String {
+    std::fs::read_to_string(path.join("CURRENT"))
+        .unwrap()
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn test_manifest_rotation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        with_users_column_family(LsmStorageOptions {
+            max_manifest_size: 256,
+            ..no_compaction_options(true)
+        }),
+    )
+    .unwrap();
+    let users = storage.column_family("users").unwrap();
+    assert_eq!(
+        manifest_files(dir.path()),
+        vec![current_manifest(dir.path())]
+    );
+    let first_manifest = current_manifest(dir.path());
+    for i in 0..20 {
+        let key = format!("key_{:03}", i);
+        storage.put(key.as_bytes(), b"default").unwrap();
+        storage.put_cf(&users, key.as_bytes(), b"users").unwrap();
+        storage.force_flush().unwrap();
+    }
+    // The manifest has been rotated, and the old one removed.
+    assert_ne!(current_manifest(dir.path()), first_manifest);
+    assert_eq!(
+        manifest_files(dir.path()),
+        vec![current_manifest(dir.path())]
+    );
+    assert!(storage.inner.manifest().size() <= 256);
+
+    // Only flush the default column family, so that the WAL is still used by `users`.
+    storage.put(b"a", b"1").unwrap();
+    storage.put_cf(&users, b"a", b"2").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    storage.put(b"b", b"3").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Opening the DB rewrites the manifest.
+    let before_open = current_manifest(dir.path());
+    let storage = MiniLsm::open(
+        &dir,
+        with_users_column_family(LsmStorageOptions {
+            max_manifest_size: 256,
+            ..no_compaction_options(true)
+        }),
+    )
+    .unwrap();
+    let users = storage.column_family("users").unwrap();
+    assert_ne!(current_manifest(dir.path()), before_open);
+    assert_eq!(
+        manifest_files(dir.path()),
+        vec![current_manifest(dir.path())]
+    );
+    // The flushed memtable of the default column family is not replayed from the WAL, only the
+    // one of the last WAL is.
+    assert_eq!(storage.inner.state.read().imm_memtables.len(), 1);
+    assert_eq!(users.state.read().imm_memtables.len(), 1);
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 21);
+    assert_eq!(users.state.read().l0_sstables.len(), 20);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("3")));
+    assert_eq!(
+        storage.get_cf(&users, b"a").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    for i in 0..20 {
+        let key = format!("key_{:03}", i);
+        assert_eq!(
+            storage.get_cf(&users, key.as_bytes()).unwrap(),
+            Some(Bytes::from("users"))
+        );
+    }
+}
+
+#[test]
+fn test_manifest_record_encoding() {
+    let dir = tempdir().unwrap();
+    let records = vec![
+        ManifestRecord::Flush(1),
+        ManifestRecord::NewMemtable(300),
+        ManifestRecord::Compaction(
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level: None,
+                upper_level_sst_ids: vec![1, 2],
+                lower_level: 3,
+                lower_level_sst_ids: vec![],
+                is_lower_level_bottom_level: true,
+            }),
+            vec![4, 5],
+        ),
+        ManifestRecord::Compaction(
+            CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level: Some(1),
+                upper_level_sst_ids: vec![6],
+                lower_level: 2,
+                lower_level_sst_ids: vec![7, 8],
+                is_lower_level_bottom_level: false,
+            }),
+            vec![9],
+        ),
+        ManifestRecord::CreateColumnFamily(1, "users".to_string()),
+        ManifestRecord::ColumnFamilyFlush(1, 300, 301),
+        ManifestRecord::ColumnFamilyCompaction(
+            1,
+            CompactionTask::Tiered(TieredCompactionTask {
+                tiers: vec![(301, vec![301]), (10, vec![10, 11])],
+                bottom_tier_included: true,
+            }),
+            vec![12],
+        ),
+        ManifestRecord::ColumnFamilyCompaction(
+            1,
+            CompactionTask::ForceFullCompaction {
+                l0_sstables: vec![13],
+                l1_sstables: vec![14, 1 << 40],
+            },
+            vec![],
+        ),
+        ManifestRecord::ColumnFamilySnapshot(1, vec![15], vec![(1, vec![16, 17]), (2, vec![])]),
+        ManifestRecord::ColumnFamilyWalFlushed(1, 300),
+    ];
+    let expected = records
+        .iter()
+        .map(|record| serde_json::to_string(record).unwrap())
+        .collect::<Vec<_>>();
+    drop(Manifest::create(Arc::new(LocalBackend), &dir, records).unwrap());
+    let manifest = dir.path().join(current_manifest(dir.path()));
+    assert_eq!(&std::fs::read(&manifest).unwrap()[..4], b"MLMF");
+
+    let (manifest, recovered, _) = Manifest::recover(
+        Arc::new(LocalBackend),
+        &dir,
+        RecoveryMode::AbsoluteConsistency,
+    )
+    .unwrap();
+    let recovered = recovered
+        .iter()
+        .map(|record| serde_json::to_string(record).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(recovered, expected);
+    // Records appended later are read back as well.
+    manifest
+        .add_record_when_init(ManifestRecord::NewMemtable(400))
+        .unwrap();
+    drop(manifest);
+    assert_eq!(
+        Manifest::recover(
+            Arc::new(LocalBackend),
+            &dir,
+            RecoveryMode::AbsoluteConsistency
+        )
+        .unwrap()
+        .1
+        .len(),
+        expected.len() + 1
+    );
+}
+
+/// Rewrites the manifest of a closed DB in the JSON format of databases created before manifests
+/// were rotated.
+fn downgrade_manifest(path: &Path) {
+    let (_, records, _) = Manifest::recover(
+        Arc::new(LocalBackend),
+        path,
+        RecoveryMode::AbsoluteConsistency,
+    )
+    .unwrap();
+    let mut buf = Vec::new();
+    for record in records {
+        let json = serde_json::to_vec(&record).unwrap();
+        buf.extend((json.len() as u64).to_be_bytes());
+        buf.extend(&json);
+        buf.extend(crc32fast::hash(&json).to_be_bytes());
+    }
+    std::fs::remove_file(path.join(current_manifest(path))).unwrap();
+    std::fs::remove_file(path.join("CURRENT")).unwrap();
+    std::fs::write(path.join("MANIFEST"), buf).unwrap();
+}
+
+#[test]
+fn test_manifest_legacy_and_leftover_files() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        with_users_column_family(LsmStorageOptions {
+            max_manifest_size: 1 << 20,
+            ..no_compaction_options(true)
+        }),
+    )
+    .unwrap();
+    let users = storage.column_family("users").unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put_cf(&users, b"a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"3").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    downgrade_manifest(dir.path());
+    // An SST whose flush did not finish.
+    std::fs::write(dir.path().join("99999.sst"), b"garbage").unwrap();
+
+    // The DB is upgraded to a binary manifest when it is opened.
+    let storage = MiniLsm::open(
+        &dir,
+        with_users_column_family(LsmStorageOptions {
+            max_manifest_size: 1 << 20,
+            ..no_compaction_options(true)
+        }),
+    )
+    .unwrap();
+    let users = storage.column_family("users").unwrap();
+    assert_eq!(
+        manifest_files(dir.path()),
+        vec![current_manifest(dir.path())]
+    );
+    let manifest = dir.path().join(current_manifest(dir.path()));
+    assert_eq!(&std::fs::read(manifest).unwrap()[..4], b"MLMF");
+    assert!(!dir.path().join("99999.sst").exists());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(
+        storage.get_cf(&users, b"a").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("3")));
+}
@@ -0,0 +1,109 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::MiniLsm,
+    prefix_extractor::PrefixExtractor,
+    tests::fixtures::{kv, no_compaction_options},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+const TENANT_EXTRACTOR: PrefixExtractor = PrefixExtractor::Delimited {
+    delimiter: b'/',
+    count: 1,
+};
+
+#[test]
+fn test_prefix_extractor() {
+    assert_eq!(
+        TENANT_EXTRACTOR.extract(b"tenant/entity/1"),
+        Some(&b"tenant/"[..])
+    );
+    assert_eq!(TENANT_EXTRACTOR.extract(b"tenant"), None);
+    let extractor = PrefixExtractor::Delimited {
+        delimiter: b'/',
+        count: 2,
+    };
+    assert_eq!(
+        extractor.extract(b"tenant/entity/1"),
+        Some(&b"tenant/entity/"[..])
+    );
+    assert_eq!(extractor.extract(b"tenant/entity"), None);
+    let extractor = PrefixExtractor::FixedLength(3);
+    assert_eq!(extractor.extract(b"abcd"), Some(&b"abc"[..]));
+    assert_eq!(extractor.extract(b"ab"), None);
+}
+
+#[test]
+fn test_scan_prefix() {
+    let dir = tempdir().unwrap();
+    let mut options = no_compaction_options(true);
+    options.prefix_extractor = Some(TENANT_EXTRACTOR);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for key in ["a/1", "a/2", "c/1", "c/2"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    // The key range of this SST covers tenant `c`, but it has no key of that tenant.
+    for key in ["b/1", "d/1", "noprefix"] {
+        storage.put(key.as_bytes(), b"2").unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.put(b"c/3", b"3").unwrap();
+    storage.delete(b"c/1").unwrap();
+
+    let expected = vec![kv("c/2", "1"), kv("c/3", "3")];
+    // The second SST is skipped by the prefix scan.
+    let range_iters = storage
+        .scan(Bound::Included(b"c/"), Bound::Excluded(b"c0"))
+        .unwrap()
+        .num_active_iterators();
+    let prefix_iters = storage.scan_prefix(b"c/").unwrap().num_active_iterators();
+    assert_eq!(prefix_iters + 1, range_iters);
+    check_lsm_iter_result_by_key(&mut storage.scan_prefix(b"c/").unwrap(), expected.clone());
+
+    // A prefix longer than the extracted one still uses the bloom filter.
+    check_lsm_iter_result_by_key(
+        &mut storage.scan_prefix(b"c/2").unwrap(),
+        vec![kv("c/2", "1")],
+    );
+    // Keys without a prefix are still found by scanning all SSTs.
+    check_lsm_iter_result_by_key(
+        &mut storage.scan_prefix(b"no").unwrap(),
+        vec![kv("noprefix", "2")],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage.scan_prefix(b"").unwrap(),
+        vec![
+            kv("a/1", "1"),
+            kv("a/2", "1"),
+            kv("b/1", "2"),
+            kv("c/2", "1"),
+            kv("c/3", "3"),
+            kv("d/1", "2"),
+            kv("noprefix", "2"),
+        ],
+    );
+    storage.close().unwrap();
+
+    // The prefix bloom filters are persisted and only used with the same extractor.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let state = storage.inner.state.read().clone();
+    let skipped = state
+        .sstables
+        .values()
+        .filter(|sst| !sst.may_contain_prefix(&TENANT_EXTRACTOR, b"c/"))
+        .count();
+    assert_eq!(skipped, 1);
+    assert!(state
+        .sstables
+        .values()
+        .all(|sst| sst.may_contain_prefix(&PrefixExtractor::FixedLength(2), b"c/")));
+    check_lsm_iter_result_by_key(&mut storage.scan_prefix(b"c/").unwrap(), expected.clone());
+    storage.close().unwrap();
+    options.prefix_extractor = Some(PrefixExtractor::FixedLength(1));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_lsm_iter_result_by_key(&mut storage.scan_prefix(b"c/").unwrap(), expected);
+}
@@ -0,0 +1,142 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{MiniLsm, WriteBatchRecord},
+    table::SsTableIterator,
+    tests::fixtures::{kv, no_compaction_options},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+fn put_keys(storage: &MiniLsm, value: &str) {
+    for key in ["a", "b", "c", "d", "e"] {
+        storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn test_delete_range_memtable() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    put_keys(&storage, "1");
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(b"b", b"d").unwrap();
+    storage.put(b"c", b"2").unwrap();
+
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "1"), kv("c", "2"), kv("d", "1"), kv("e", "1")],
+    );
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.get(b"d").unwrap(), Some(Bytes::from("1")));
+
+    // A transaction that started before the deletion still sees the keys.
+    assert_eq!(snapshot.get(b"b").unwrap(), Some(Bytes::from("1")));
+    check_lsm_iter_result_by_key(
+        &mut snapshot
+            .scan(Bound::Included(b"b"), Bound::Excluded(b"d"))
+            .unwrap(),
+        vec![kv("b", "1"), kv("c", "1")],
+    );
+}
+
+#[test]
+fn test_delete_range_across_ssts() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    put_keys(&storage, "1");
+    storage.force_flush().unwrap();
+    // The tombstone is flushed into an SST of its own.
+    storage.delete_range(b"a", b"c").unwrap();
+    storage.force_flush().unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"a"[..], &b"2"[..]),
+            WriteBatchRecord::DelRange(b"d", b"z"),
+        ])
+        .unwrap();
+
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "2"), kv("c", "1")],
+    );
+    storage.force_flush().unwrap();
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "2"), kv("c", "1")],
+    );
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"e").unwrap(), None);
+}
+
+#[test]
+fn test_delete_range_recover() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(true);
+    {
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        put_keys(&storage, "1");
+        storage.force_flush().unwrap();
+        storage.delete_range(b"b", b"e").unwrap();
+        storage.put(b"d", b"2").unwrap();
+        storage.close().unwrap();
+    }
+    let expected = vec![kv("a", "1"), kv("d", "2"), kv("e", "1")];
+    {
+        // Recover the tombstone from the WAL.
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            expected.clone(),
+        );
+        storage.force_flush().unwrap();
+        storage.close().unwrap();
+    }
+    // Recover the tombstone from the SST.
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+#[test]
+fn test_delete_range_compaction() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    put_keys(&storage, "1");
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(b"b", b"d").unwrap();
+    storage.force_flush().unwrap();
+
+    // The transaction still needs the covered versions.
+    storage.force_full_compaction().unwrap();
+    assert_eq!(snapshot.get(b"c").unwrap(), Some(Bytes::from("1")));
+    drop(snapshot);
+
+    storage.force_full_compaction().unwrap();
+    let state = storage.inner.state.read().clone();
+    let mut keys = Vec::new();
+    for sst in state.sstables.values() {
+        assert!(sst.range_tombstones().is_empty());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            keys.push(Bytes::copy_from_slice(iter.key().key_ref()));
+            iter.next().unwrap();
+        }
+    }
+    keys.sort();
+    assert_eq!(keys, vec!["a", "d", "e"]);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "1"), kv("d", "1"), kv("e", "1")],
+    );
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::LsmStorageInner,
+    rate_limiter::{IoOp, IoPriority, RateLimiter, RateLimiterMode},
+    tests::fixtures::simple_options,
+};
+
+#[test]
+fn test_rate_limit() {
+    // A burst is a tenth of the rate, and is available right away.
+    let limiter = RateLimiter::new(100_000, RateLimiterMode::WritesOnly);
+    let start = Instant::now();
+    limiter.request(60_000, IoPriority::Low, IoOp::Write);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    assert_eq!(limiter.total_bytes_through(IoPriority::Low), 60_000);
+    assert_eq!(limiter.total_bytes_through(IoPriority::High), 0);
+
+    // Reads are not limited in this mode.
+    let start = Instant::now();
+    limiter.request(1 << 30, IoPriority::Low, IoOp::Read);
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert_eq!(limiter.total_bytes_through(IoPriority::Low), 60_000);
+}
+
+#[test]
+fn test_set_bytes_per_second() {
+    let limiter = Arc::new(RateLimiter::new(1_000, RateLimiterMode::AllIo));
+    let request = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || limiter.request(1_000_000, IoPriority::Low, IoOp::Read))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!request.is_finished());
+    // The waiting request speeds up.
+    limiter.set_bytes_per_second(10_000_000);
+    assert_eq!(limiter.bytes_per_second(), 10_000_000);
+    let start = Instant::now();
+    request.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_high_priority_first() {
+    let limiter = Arc::new(RateLimiter::new(100_000, RateLimiterMode::WritesOnly));
+    // Use up the first burst.
+    limiter.request(10_000, IoPriority::Low, IoOp::Write);
+    let spawn = |priority| {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || {
+            limiter.request(50_000, priority, IoOp::Write);
+            Instant::now()
+        })
+    };
+    let low = spawn(IoPriority::Low);
+    std::thread::sleep(Duration::from_millis(20));
+    let high = spawn(IoPriority::High);
+    let (low, high) = (low.join().unwrap(), high.join().unwrap());
+    assert!(high < low);
+}
+
+#[test]
+fn test_rate_limit_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_options(false);
+    let limiter = Arc::new(RateLimiter::new(100 << 20, RateLimiterMode::WritesOnly));
+    options.rate_limiter = Some(limiter.clone());
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for round in 0..2 {
+        for i in 0..100 {
+            storage
+                .put(format!("key_{:03}", i).as_bytes(), &[round; 100])
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    let flushed = limiter.total_bytes_through(IoPriority::High);
+    assert!(flushed >= 2 * 100 * 100, "{}", flushed);
+    assert_eq!(limiter.total_bytes_through(IoPriority::Low), 0);
+
+    storage.trigger_compaction(storage.default_cf()).unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    let compacted = limiter.total_bytes_through(IoPriority::Low);
+    assert!(
+        compacted >= 100 * 100 && compacted < flushed,
+        "{}",
+        compacted
+    );
+}
+
+#[test]
+fn test_rate_limit_before_each_write() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_options(false);
+    // Slow enough that not even the first block can be written.
+    let limiter = Arc::new(RateLimiter::new(1, RateLimiterMode::WritesOnly));
+    options.rate_limiter = Some(limiter.clone());
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for i in 0..100 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), &[0; 100])
+            .unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    let sst_id = storage.state.read().imm_memtables[0].id();
+    let flush = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.force_flush_next_imm_memtable())
+    };
+    let path = storage.path_of_sst(sst_id);
+    while !path.exists() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!flush.is_finished());
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+    limiter.set_bytes_per_second(100 << 20);
+    flush.join().unwrap().unwrap();
+    let sst_size = std::fs::metadata(&path).unwrap().len();
+    assert!(sst_size >= 100 * 100, "{}", sst_size);
+    assert_eq!(limiter.total_bytes_through(IoPriority::High), sst_size);
+}
@@ -0,0 +1,245 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    recovery::RecoveryMode,
+    tests::fixtures::no_compaction_options,
+};
+
+/// The WALs in the directory, from oldest to newest.
+fn wal_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wal"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// The size of a WAL batch of one record with a one-byte key and value.
+const RECORD_SIZE: u64 = 35;
+/// The offset of the key in a WAL batch of one record.
+const RECORD_KEY_OFFSET: usize = 25;
+/// The offset of the length of the records in a WAL batch.
+const BATCH_LEN_OFFSET: usize = 12;
+const WAL_HEADER_SIZE: u64 = 8;
+
+#[test]
+fn test_torn_wal_tail() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // The last record is half-written.
+    let wal = wal_files(dir.path()).pop().unwrap();
+    let file = OpenOptions::new().write(true).open(&wal).unwrap();
+    file.set_len(WAL_HEADER_SIZE + RECORD_SIZE + 10).unwrap();
+    drop(file);
+
+    assert!(MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            recovery_mode: RecoveryMode::AbsoluteConsistency,
+            ..no_compaction_options(true)
+        }
+    )
+    .is_err());
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    let report = storage.recovery_report();
+    assert_eq!(report.dropped_tails.len(), 1);
+    assert_eq!(report.dropped_tails[0].path, wal);
+    assert_eq!(
+        report.dropped_tails[0].offset,
+        WAL_HEADER_SIZE + RECORD_SIZE
+    );
+    assert_eq!(report.dropped_tails[0].len, 10);
+    assert!(report.dropped_wals.is_empty());
+    storage.put(b"c", b"3").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // The damaged tail was truncated, so the WAL is intact now.
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            recovery_mode: RecoveryMode::AbsoluteConsistency,
+            ..no_compaction_options(true)
+        },
+    )
+    .unwrap();
+    assert!(storage.recovery_report().is_clean());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("3")));
+}
+
+#[test]
+fn test_corrupted_batch_length() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // The length of the first batch points past the end of the WAL.
+    let wal = wal_files(dir.path()).pop().unwrap();
+    let mut data = std::fs::read(&wal).unwrap();
+    let offset = WAL_HEADER_SIZE as usize + BATCH_LEN_OFFSET;
+    for byte in &mut data[offset..offset + 4] {
+        *byte ^= 0xff;
+    }
+    std::fs::write(&wal, &data).unwrap();
+
+    // The valid batches after it are not a damaged tail.
+    assert!(MiniLsm::open(&dir, no_compaction_options(true)).is_err());
+    assert_eq!(std::fs::read(&wal).unwrap(), data);
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            recovery_mode: RecoveryMode::PointInTimeRecovery,
+            ..no_compaction_options(true)
+        },
+    )
+    .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), None);
+    let report = storage.recovery_report();
+    assert_eq!(report.dropped_tails.len(), 1);
+    assert_eq!(report.dropped_tails[0].offset, WAL_HEADER_SIZE);
+    assert_eq!(report.dropped_tails[0].len, RECORD_SIZE * 3);
+}
+
+#[test]
+fn test_point_in_time_recovery() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"d", b"4").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Corrupt the key of the second record of the first WAL.
+    let wals = wal_files(dir.path());
+    assert_eq!(wals.len(), 2);
+    let mut data = std::fs::read(&wals[0]).unwrap();
+    let offset = WAL_HEADER_SIZE + RECORD_SIZE;
+    data[offset as usize + RECORD_KEY_OFFSET] ^= 0xff;
+    std::fs::write(&wals[0], &data).unwrap();
+
+    // Only a damaged tail is tolerated by default.
+    assert!(MiniLsm::open(&dir, no_compaction_options(true)).is_err());
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            recovery_mode: RecoveryMode::PointInTimeRecovery,
+            ..no_compaction_options(true)
+        },
+    )
+    .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), None);
+    assert_eq!(storage.get(b"d").unwrap(), None);
+    let report = storage.recovery_report();
+    assert_eq!(report.dropped_tails.len(), 1);
+    assert_eq!(report.dropped_tails[0].path, wals[0]);
+    assert_eq!(report.dropped_tails[0].offset, offset);
+    assert_eq!(report.dropped_tails[0].len, RECORD_SIZE * 2);
+    assert_eq!(report.dropped_wals, vec![wals[1].clone()]);
+    assert!(!wals[1].exists());
+}
+
+#[test]
+fn test_torn_write_batch() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"b"[..], &b"2"[..]),
+            WriteBatchRecord::Del(b"a"),
+            WriteBatchRecord::Put(b"c", b"3"),
+        ])
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Only the batch header and the first record of the batch made it to disk.
+    let wal = wal_files(dir.path()).pop().unwrap();
+    let batch_offset = WAL_HEADER_SIZE + RECORD_SIZE;
+    let torn_len = 16 + 15;
+    assert!(std::fs::metadata(&wal).unwrap().len() > batch_offset + torn_len);
+    let file = OpenOptions::new().write(true).open(&wal).unwrap();
+    file.set_len(batch_offset + torn_len).unwrap();
+    drop(file);
+
+    // Bytes follow the header of the batch, so it is not a damaged tail, but the whole batch is
+    // dropped when recovering to a point in time.
+    assert!(MiniLsm::open(&dir, no_compaction_options(true)).is_err());
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            recovery_mode: RecoveryMode::PointInTimeRecovery,
+            ..no_compaction_options(true)
+        },
+    )
+    .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), None);
+    let report = storage.recovery_report();
+    assert_eq!(report.dropped_tails.len(), 1);
+    assert_eq!(report.dropped_tails[0].offset, batch_offset);
+    assert_eq!(report.dropped_tails[0].len, torn_len);
+}
+
+#[test]
+fn test_torn_manifest_tail() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let current = std::fs::read_to_string(dir.path().join("CURRENT")).unwrap();
+    let manifest = dir.path().join(current.trim());
+    let len = std::fs::metadata(&manifest).unwrap().len();
+    let mut file = OpenOptions::new().append(true).open(&manifest).unwrap();
+    file.write_all(&[0, 0, 0, 100, 1, 2, 3]).unwrap();
+    drop(file);
+
+    assert!(MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            recovery_mode: RecoveryMode::AbsoluteConsistency,
+            ..no_compaction_options(true)
+        }
+    )
+    .is_err());
+    let storage = MiniLsm::open(&dir, no_compaction_options(true)).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    let report = storage.recovery_report();
+    assert_eq!(report.dropped_tails.len(), 1);
+    assert_eq!(report.dropped_tails[0].path, manifest);
+    assert_eq!(report.dropped_tails[0].offset, len);
+    assert_eq!(report.dropped_tails[0].len, 7);
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+use crate::{
+    iterators::{merge_iterator::MergeIterator, DoubleEndedStorageIterator, StorageIterator},
+    key::KeySlice,
+    lsm_storage::MiniLsm,
+    mem_table::MemTable,
+    table::{SsTableBuilder, SsTableIterator},
+    tests::fixtures::{no_compaction_options, simple_options},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+/// Walks the iterator in random directions and compares it with the expected entries.
+fn check_random_walk<I>(iter: &mut I, expected: &[(Bytes, Bytes)], seed: u64)
+where
+    I: for<'a> DoubleEndedStorageIterator<KeyType<'a> = &'a [u8]>,
+{
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    for _ in 0..20 {
+        let mut pos = if rng.gen_bool(0.5) {
+            iter.seek_to_first().unwrap();
+            0
+        } else {
+            iter.seek_to_last().unwrap();
+            expected.len() as isize - 1
+        };
+        for _ in 0..50 {
+            if pos < 0 || pos >= expected.len() as isize {
+                assert!(!iter.is_valid());
+                break;
+            }
+            let (key, value) = &expected[pos as usize];
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), &key[..]);
+            assert_eq!(iter.value(), &value[..]);
+            if rng.gen_bool(0.5) {
+                iter.next().unwrap();
+                pos += 1;
+            } else {
+                iter.prev().unwrap();
+                pos -= 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sst_reverse_iter() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key_of(i), 1),
+            format!("value_{:03}", i).as_bytes(),
+        );
+    }
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst.clone()).unwrap();
+    for i in (0..100).rev() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), &key_of(i)[..]);
+        assert_eq!(iter.value(), format!("value_{:03}", i).as_bytes());
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Change the direction in the middle of the table.
+    let mut iter = SsTableIterator::create_and_seek_to_key(
+        sst,
+        KeySlice::for_testing_from_slice_with_ts(&key_of(50), 1),
+    )
+    .unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key().key_ref(), &key_of(49)[..]);
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), &key_of(51)[..]);
+}
+
+#[test]
+fn test_merge_iterator_change_direction() {
+    let memtables = (0..3).map(MemTable::create).collect::<Vec<_>>();
+    let mut expected = BTreeMap::new();
+    for i in 0..30 {
+        // Overlapping keys are taken from the first memtable.
+        for (idx, memtable) in memtables.iter().enumerate().rev() {
+            if i % (idx + 2) == 0 {
+                let value = format!("value_{}_{}", i, idx);
+                memtable
+                    .for_testing_put_slice(&key_of(i), value.as_bytes())
+                    .unwrap();
+                expected.insert(Bytes::from(key_of(i)), Bytes::from(value));
+            }
+        }
+    }
+    let iters = memtables
+        .iter()
+        .map(|memtable| {
+            Box::new(memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded))
+        })
+        .collect();
+    let mut iter = MergeIterator::create(iters);
+    let expected = expected.into_iter().collect::<Vec<_>>();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut pos = expected.len() - 1;
+    iter.seek_to_last().unwrap();
+    for _ in 0..200 {
+        assert_eq!(iter.key().key_ref(), &expected[pos].0[..]);
+        assert_eq!(iter.value(), &expected[pos].1[..]);
+        if (rng.gen_bool(0.5) && pos > 0) || pos == expected.len() - 1 {
+            iter.prev().unwrap();
+            pos -= 1;
+        } else {
+            iter.next().unwrap();
+            pos += 1;
+        }
+    }
+}
+
+#[test]
+fn test_lsm_reverse_iter() {
+    let dir = tempdir().unwrap();
+    let options = simple_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut expected = BTreeMap::new();
+    let mut snapshots = Vec::new();
+    for round in 0..4 {
+        for i in 0..50 {
+            let key = key_of(i);
+            if (i + round) % 5 == 0 {
+                storage.delete(&key).unwrap();
+                expected.remove(&key);
+            } else if (i + round) % 3 != 0 {
+                let value = format!("value_{}_{}", i, round).into_bytes();
+                storage.put(&key, &value).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        if round == 3 {
+            storage.delete_range(&key_of(20), &key_of(25)).unwrap();
+            expected.retain(|key, _| key < &key_of(20) || key >= &key_of(25));
+        }
+        snapshots.push((storage.new_txn().unwrap(), expected.clone()));
+        if round != 3 {
+            storage.force_flush().unwrap();
+        }
+    }
+
+    for (seed, (txn, expected)) in snapshots.iter().enumerate() {
+        let expected = expected
+            .iter()
+            .map(|(key, value)| (Bytes::from(key.clone()), Bytes::from(value.clone())))
+            .collect::<Vec<_>>();
+        let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        iter.seek_to_last().unwrap();
+        for (key, value) in expected.iter().rev() {
+            assert_eq!(iter.key(), &key[..]);
+            assert_eq!(iter.value(), &value[..]);
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+        check_random_walk(&mut iter, &expected, seed as u64);
+
+        // The last entries before a key.
+        let lower = key_of(10);
+        let upper = key_of(40);
+        let mut iter = txn
+            .scan(Bound::Excluded(&lower), Bound::Excluded(&upper))
+            .unwrap();
+        let expected = expected
+            .into_iter()
+            .filter(|(key, _)| key > &lower[..] && key < &upper[..])
+            .collect::<Vec<_>>();
+        check_random_walk(&mut iter, &expected, seed as u64);
+    }
+}
+
+#[test]
+fn test_txn_reverse_iter() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..20 {
+        storage.put(&key_of(i), b"lsm").unwrap();
+    }
+    storage.force_flush().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(5), b"txn");
+    txn.delete(&key_of(6));
+    txn.put(&key_of(25), b"txn");
+    let mut expected = (0..20)
+        .filter(|i| *i != 6)
+        .map(|i| {
+            let value = if i == 5 { "txn" } else { "lsm" };
+            (Bytes::from(key_of(i)), Bytes::from(value))
+        })
+        .collect::<Vec<_>>();
+    expected.push((Bytes::from(key_of(25)), Bytes::from("txn")));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    check_random_walk(&mut iter, &expected, 42);
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::{DoubleEndedStorageIterator, SeekableStorageIterator, StorageIterator},
+    lsm_storage::MiniLsm,
+    tests::fixtures::simple_options,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn open_storage_with_data(
+    dir: &tempfile::TempDir,
+) -> (std::sync::Arc<MiniLsm>, BTreeMap<Bytes, Bytes>) {
+    let options = simple_options(false);
+    let storage = MiniLsm::open(dir, options).unwrap();
+    let mut expected = BTreeMap::new();
+    for round in 0..3 {
+        for i in (0..100).step_by(2) {
+            let key = Bytes::from(key_of(i));
+            if (i / 2 + round) % 4 == 0 {
+                storage.delete(&key).unwrap();
+                expected.remove(&key);
+            } else {
+                let value = Bytes::from(format!("value_{}_{}", i, round));
+                storage.put(&key, &value).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        if round != 2 {
+            storage.force_flush().unwrap();
+        }
+    }
+    (storage, expected)
+}
+
+#[test]
+fn test_seek() {
+    let dir = tempdir().unwrap();
+    let (storage, expected) = open_storage_with_data(&dir);
+    let lower = key_of(20);
+    let upper = key_of(80);
+    let mut iter = storage
+        .scan(Bound::Excluded(&lower), Bound::Included(&upper))
+        .unwrap();
+    for target in [0, 20, 21, 22, 45, 50, 79, 80, 81, 99, 50, 10] {
+        let target = key_of(target);
+        iter.seek(&target).unwrap();
+        let mut entries = expected
+            .range::<[u8], _>((Bound::Excluded(&lower[..]), Bound::Included(&upper[..])))
+            .filter(|(key, _)| key[..] >= target[..]);
+        for _ in 0..5 {
+            match entries.next() {
+                Some((key, value)) => {
+                    assert!(iter.is_valid());
+                    assert_eq!(iter.key(), &key[..]);
+                    assert_eq!(iter.value(), &value[..]);
+                    iter.next().unwrap();
+                }
+                None => {
+                    assert!(!iter.is_valid());
+                    break;
+                }
+            }
+        }
+    }
+
+    // Move backward after seeking.
+    iter.seek(&key_of(51)).unwrap();
+    iter.prev().unwrap();
+    let (key, _) = expected
+        .range(..Bytes::from(key_of(51)))
+        .next_back()
+        .unwrap();
+    assert_eq!(iter.key(), &key[..]);
+}
+
+#[test]
+fn test_seek_paginate() {
+    let dir = tempdir().unwrap();
+    let (storage, expected) = open_storage_with_data(&dir);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut keys = Vec::new();
+    let mut next_page = Vec::new();
+    loop {
+        iter.seek(&next_page).unwrap();
+        if !iter.is_valid() {
+            break;
+        }
+        for _ in 0..7 {
+            if !iter.is_valid() {
+                break;
+            }
+            keys.push(Bytes::copy_from_slice(iter.key()));
+            iter.next().unwrap();
+        }
+        // The smallest key after the last one on the page.
+        next_page = keys.last().unwrap().to_vec();
+        next_page.push(0);
+    }
+    assert_eq!(keys, expected.keys().cloned().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_txn_seek() {
+    let dir = tempdir().unwrap();
+    let (storage, _) = open_storage_with_data(&dir);
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"key_031", b"txn");
+    txn.delete(b"key_032");
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek(b"key_031").unwrap();
+    assert_eq!(iter.key(), b"key_031");
+    assert_eq!(iter.value(), b"txn");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"key_034");
+    iter.seek(b"key_032").unwrap();
+    assert_eq!(iter.key(), b"key_034");
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"key_031");
+}
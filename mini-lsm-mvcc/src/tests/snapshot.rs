@@ -0,0 +1,100 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::MiniLsm,
+    tests::fixtures::{kv, no_compaction_options},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+#[test]
+fn test_snapshot_fixed_view() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    assert_eq!(snapshot.read_ts(), storage.inner.mvcc().latest_commit_ts());
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    storage.force_flush().unwrap();
+
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(snapshot.get(b"c").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "1"), kv("b", "1")],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![kv("a", "2"), kv("c", "2")],
+    );
+}
+
+#[test]
+fn test_snapshot_releases_watermark() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    let read_ts = snapshot.read_ts();
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.inner.mvcc().watermark(), read_ts);
+
+    // An iterator keeps the snapshot's versions alive after the handle is dropped.
+    let iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    drop(snapshot);
+    assert_eq!(storage.inner.mvcc().watermark(), read_ts);
+    drop(iter);
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+}
+
+#[test]
+fn test_time_travel_reads() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let ts1 = storage.inner.mvcc().latest_commit_ts();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    let ts2 = storage.inner.mvcc().latest_commit_ts();
+
+    // Nothing has been garbage collected yet.
+    assert_eq!(storage.get_at(b"a", ts1).unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get_at(b"a", ts2).unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.get_at(b"a", 0).unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_at(Bound::Unbounded, Bound::Unbounded, ts1)
+            .unwrap(),
+        vec![kv("a", "1"), kv("b", "1")],
+    );
+    assert!(storage.get_at(b"a", ts2 + 1).is_err());
+    assert!(storage.snapshot_at(ts2 + 1).is_err());
+
+    // A snapshot keeps its history through compaction.
+    let snapshot = storage.snapshot_at(ts1).unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get_at(b"b", ts1).unwrap(), Some(Bytes::from("1")));
+    drop(snapshot);
+
+    // Without readers, compaction drops the old versions and they can no longer be read.
+    storage.force_full_compaction().unwrap();
+    assert!(storage.get_at(b"a", ts1).is_err());
+    assert!(storage
+        .scan_at(Bound::Unbounded, Bound::Unbounded, ts1)
+        .is_err());
+    assert_eq!(storage.get_at(b"a", ts2).unwrap(), Some(Bytes::from("2")));
+}
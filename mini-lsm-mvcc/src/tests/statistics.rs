@@ -0,0 +1,99 @@
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::MiniLsm,
+    statistics::{HistogramType, Statistics, Ticker},
+    tests::fixtures::no_compaction_options,
+};
+
+#[test]
+fn test_statistics() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(true);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let statistics = storage.statistics();
+    for i in 0..100 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    assert_eq!(statistics.histogram(HistogramType::Write).count, 100);
+    storage.sync().unwrap();
+    assert_eq!(statistics.ticker(Ticker::WalSyncs), 1);
+
+    storage.force_flush().unwrap();
+    let flushed = {
+        let state = storage.inner.state.read();
+        state.sstables[&state.l0_sstables[0]].table_size()
+    };
+    assert_eq!(statistics.ticker(Ticker::BytesFlushed), flushed);
+
+    // The first read of a block misses the block cache, and the second one hits it.
+    storage.get(b"key_050").unwrap().unwrap();
+    assert_eq!(statistics.ticker(Ticker::BlockCacheMiss), 1);
+    assert_eq!(statistics.ticker(Ticker::BlockCacheHit), 0);
+    storage.get(b"key_050").unwrap().unwrap();
+    assert_eq!(statistics.ticker(Ticker::BlockCacheMiss), 1);
+    assert_eq!(statistics.ticker(Ticker::BlockCacheHit), 1);
+    assert_eq!(statistics.ticker(Ticker::BloomFilterPositive), 2);
+    // A missing key within the key range of the SST is ruled out by its bloom filter.
+    for i in 0..100 {
+        storage
+            .get(format!("key_{:03}_missing", i).as_bytes())
+            .unwrap();
+    }
+    let useful = statistics.ticker(Ticker::BloomFilterUseful);
+    assert!(useful > 90, "{}", useful);
+    assert_eq!(statistics.histogram(HistogramType::Get).count, 102);
+
+    storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(statistics.histogram(HistogramType::Scan).count, 1);
+
+    storage.put(b"key_000", b"new_value").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    let compacted = {
+        let state = storage.inner.state.read();
+        state
+            .levels
+            .iter()
+            .flat_map(|(_, ids)| ids)
+            .map(|id| state.sstables[id].table_size())
+            .sum::<u64>()
+    };
+    assert_eq!(
+        statistics.bytes_compacted().values().sum::<u64>(),
+        compacted
+    );
+
+    let dump = statistics.to_string();
+    assert!(dump.contains(&format!(
+        "flush.bytes COUNT : {}",
+        statistics.ticker(Ticker::BytesFlushed)
+    )));
+    assert!(dump.contains("get.micros"));
+}
+
+#[test]
+fn test_histogram() {
+    let statistics = Statistics::default();
+    let start = Instant::now();
+    for micros in 1..=100 {
+        statistics.record_latency(
+            HistogramType::Get,
+            start.checked_sub(Duration::from_micros(micros)).unwrap(),
+        );
+    }
+    let data = statistics.histogram(HistogramType::Get);
+    assert_eq!(data.count, 100);
+    assert!(data.max >= 100);
+    assert!(data.sum >= 5050);
+    // The percentiles are rounded up to a power of two.
+    assert!((50..=127).contains(&data.p50), "{}", data.p50);
+    assert!((99..=255).contains(&data.p99), "{}", data.p99);
+    assert!(data.p50 < data.p99);
+    assert_eq!(statistics.histogram(HistogramType::Scan).count, 0);
+}
@@ -0,0 +1,208 @@
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compaction_filter::{CompactionDecision, CompactionFilter},
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    ttl::{Clock, TtlOptions},
+};
+
+use super::fixtures::no_compaction_options;
+use super::harness::{check_lsm_iter_result_by_key, construct_merge_iterator_over_storage};
+
+const SHORT_TTL: Duration = Duration::from_millis(100);
+const LONG_TTL: Duration = Duration::from_secs(3600);
+
+/// A clock that only moves when it is advanced.
+#[derive(Debug, Default)]
+struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    fn advance(&self, by: Duration) {
+        self.0.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+fn open(
+    dir: &tempfile::TempDir,
+    default_ttl: Option<Duration>,
+) -> (Arc<MiniLsm>, Arc<ManualClock>) {
+    let clock = Arc::new(ManualClock::default());
+    let options = LsmStorageOptions {
+        ttl: Some(TtlOptions {
+            default_ttl,
+            clock: Some(clock.clone()),
+        }),
+        ..no_compaction_options(false)
+    };
+    (MiniLsm::open(dir, options).unwrap(), clock)
+}
+
+/// The keys of all versions stored in the SSTs.
+fn stored_keys(storage: &MiniLsm) -> Vec<Bytes> {
+    let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key().key_ref()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_put_with_ttl() {
+    let dir = tempdir().unwrap();
+    let (storage, clock) = open(&dir, None);
+    storage.put(b"a", b"1").unwrap();
+    storage.put_with_ttl(b"b", b"2", SHORT_TTL).unwrap();
+    storage.put_with_ttl(b"c", b"3", LONG_TTL).unwrap();
+    storage.put(b"d", b"4").unwrap();
+    storage.put_with_ttl(b"d", b"5", SHORT_TTL).unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.get(b"d").unwrap(), Some(Bytes::from("5")));
+
+    clock.advance(SHORT_TTL);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("3")));
+    // An expired version hides the older ones.
+    assert_eq!(storage.get(b"d").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("c"), Bytes::from("3")),
+        ],
+    );
+
+    storage.put(b"b", b"6").unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("6")));
+}
+
+#[test]
+fn test_default_ttl() {
+    let dir = tempdir().unwrap();
+    let (storage, clock) = open(&dir, Some(SHORT_TTL));
+    storage.put(b"a", b"1").unwrap();
+    storage.put_with_ttl(b"b", b"2", LONG_TTL).unwrap();
+    clock.advance(SHORT_TTL);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_ttl_disabled() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.put_with_ttl(b"a", b"1", LONG_TTL).is_err());
+    assert_eq!(storage.get(b"a").unwrap(), None);
+}
+
+#[test]
+fn test_compaction_drops_expired_keys() {
+    let dir = tempdir().unwrap();
+    let (storage, clock) = open(&dir, None);
+    storage.put_with_ttl(b"a", b"1", SHORT_TTL).unwrap();
+    storage.put_with_ttl(b"b", b"2", LONG_TTL).unwrap();
+    let txn = storage.new_txn().unwrap();
+    storage.put_with_ttl(b"c", b"3", SHORT_TTL).unwrap();
+    storage.force_flush().unwrap();
+    clock.advance(SHORT_TTL);
+
+    // `c` is above the watermark of the transaction, and is only dropped once it ends.
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        stored_keys(&storage),
+        vec![Bytes::from("b"), Bytes::from("c")]
+    );
+    assert_eq!(txn.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), None);
+
+    drop(txn);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(stored_keys(&storage), vec![Bytes::from("b")]);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[derive(Debug)]
+struct UppercaseFilter;
+
+impl CompactionFilter for UppercaseFilter {
+    fn filter(&self, _level: usize, _key: &[u8], _ts: u64, value: &[u8]) -> CompactionDecision {
+        CompactionDecision::ChangeValue(value.to_ascii_uppercase().into())
+    }
+}
+
+#[test]
+fn test_compaction_filter_keeps_expiry() {
+    let dir = tempdir().unwrap();
+    let (storage, clock) = open(&dir, None);
+    storage.add_compaction_filter(Arc::new(UppercaseFilter));
+    storage.put_with_ttl(b"a", b"a", SHORT_TTL).unwrap();
+    storage.put(b"b", b"b").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("A")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("B")));
+
+    clock.advance(SHORT_TTL);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("B")));
+}
+
+#[test]
+fn test_reopen_without_ttl() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        ttl: Some(TtlOptions::default()),
+        // Rotates the manifest on every record.
+        max_manifest_size: 1,
+        ..no_compaction_options(false)
+    };
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put_with_ttl(b"a", b"1", LONG_TTL).unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    assert!(MiniLsm::open(&dir, no_compaction_options(false)).is_err());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_reopen_with_ttl() {
+    let dir = tempdir().unwrap();
+    let options = no_compaction_options(false);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // Long enough to be misread as a value with an expiry time.
+    storage.put(b"a", b"value_without_expiry").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let ttl_options = LsmStorageOptions {
+        ttl: Some(TtlOptions::default()),
+        ..no_compaction_options(false)
+    };
+    assert!(MiniLsm::open(&dir, ttl_options).is_err());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(b"a").unwrap(),
+        Some(Bytes::from("value_without_expiry"))
+    );
+}
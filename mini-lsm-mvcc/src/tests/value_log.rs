@@ -0,0 +1,243 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    backend::LocalBackend,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{SsTableBuilder, SsTableIterator},
+    tests::fixtures::no_compaction_options,
+    value_log::{ValueLog, ValueLogOptions},
+};
+
+fn large_value(key: usize, version: u8) -> Vec<u8> {
+    format!("value_{:03}_{}_", key, version)
+        .repeat(200)
+        .into_bytes()
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn vlog_files(path: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".vlog"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_sst_value_pointers() {
+    let dir = tempdir().unwrap();
+    let value_log = Arc::new(ValueLog::new(
+        Arc::new(LocalBackend),
+        dir.path(),
+        Some(ValueLogOptions {
+            value_threshold: 1024,
+            gc_live_ratio: 0.5,
+        }),
+    ));
+    let mut builder = SsTableBuilder::new(4096);
+    builder.set_value_log(value_log.new_writer(2));
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"a", 1), b"small");
+    builder.add(
+        KeySlice::for_testing_from_slice_with_ts(b"b", 1),
+        &large_value(1, 0),
+    );
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"c", 1), b"");
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert_eq!(vlog_files(dir.path()), vec!["00002.vlog"]);
+    assert_eq!(sst.value_log_refs().len(), 1);
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let mut values = Vec::new();
+    while iter.is_valid() {
+        assert!(!iter.is_value_pointer());
+        values.push(Bytes::copy_from_slice(iter.value()));
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        values,
+        vec![
+            Bytes::from("small"),
+            Bytes::from(large_value(1, 0)),
+            Bytes::new()
+        ]
+    );
+
+    // Compaction reads the pointers without the values.
+    let mut iter = SsTableIterator::create_and_seek_to_first_raw(sst).unwrap();
+    iter.next().unwrap();
+    assert!(iter.is_value_pointer());
+    assert!(iter.value().len() < 100);
+}
+
+#[test]
+fn test_value_log_read_and_recover() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions {
+        value_log_options: Some(ValueLogOptions {
+            value_threshold: 1024,
+            gc_live_ratio: 0.5,
+        }),
+        ..no_compaction_options(false)
+    };
+    options.enable_wal = true;
+    {
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for i in 0..10 {
+            storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+        }
+        storage.put(b"small", b"value").unwrap();
+        storage.force_flush().unwrap();
+        assert_eq!(vlog_files(dir.path()).len(), 1);
+        assert_eq!(
+            storage.get(&key_of(3)).unwrap(),
+            Some(Bytes::from(large_value(3, 0)))
+        );
+        storage.close().unwrap();
+    }
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..10 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(large_value(i, 0)))
+        );
+    }
+    assert_eq!(storage.get(b"small").unwrap(), Some(Bytes::from("value")));
+    let mut iter = storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    for i in 0..10 {
+        assert_eq!(iter.key(), &key_of(i)[..]);
+        assert_eq!(iter.value(), &large_value(i, 0)[..]);
+        iter.next().unwrap();
+    }
+}
+
+#[test]
+fn test_value_log_compaction_keeps_values_in_place() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            value_log_options: Some(ValueLogOptions {
+                value_threshold: 1024,
+                gc_live_ratio: 0.1,
+            }),
+            ..no_compaction_options(false)
+        },
+    )
+    .unwrap();
+    for i in 0..10 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in 10..20 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let files = vlog_files(dir.path());
+    assert_eq!(files.len(), 2);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(vlog_files(dir.path()), files);
+    for i in 0..20 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(large_value(i, 0)))
+        );
+    }
+}
+
+#[test]
+fn test_value_log_gc_respects_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            value_log_options: Some(ValueLogOptions {
+                value_threshold: 1024,
+                gc_live_ratio: 0.5,
+            }),
+            ..no_compaction_options(false)
+        },
+    )
+    .unwrap();
+    for i in 0..10 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let old_files = vlog_files(dir.path());
+    let snapshot = storage.new_txn().unwrap();
+    for i in 0..10 {
+        storage.put(&key_of(i), &large_value(i, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+
+    // The old versions are still visible to the transaction.
+    storage.force_full_compaction().unwrap();
+    assert!(vlog_files(dir.path()).contains(&old_files[0]));
+    for i in 0..10 {
+        assert_eq!(
+            snapshot.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(large_value(i, 0)))
+        );
+    }
+
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert!(!vlog_files(dir.path()).contains(&old_files[0]));
+    for i in 0..10 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(large_value(i, 1)))
+        );
+    }
+}
+
+#[test]
+fn test_value_log_gc_relocates_live_values() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions {
+            value_log_options: Some(ValueLogOptions {
+                value_threshold: 1024,
+                gc_live_ratio: 0.6,
+            }),
+            ..no_compaction_options(false)
+        },
+    )
+    .unwrap();
+    for i in 0..10 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let old_files = vlog_files(dir.path());
+    for i in 0..5 {
+        storage.put(&key_of(i), &large_value(i, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+
+    // Half of the first file becomes garbage, so it is marked for relocation.
+    storage.force_full_compaction().unwrap();
+    assert!(vlog_files(dir.path()).contains(&old_files[0]));
+    // The next compaction copies the live values out of it.
+    storage.force_full_compaction().unwrap();
+    assert!(!vlog_files(dir.path()).contains(&old_files[0]));
+    for i in 0..10 {
+        let version = if i < 5 { 1 } else { 0 };
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(large_value(i, version)))
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tempfile::tempdir;
+
+use crate::{
+    event_listener::BackgroundErrorReason,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+    tests::fixtures::{no_compaction_options, simple_options},
+    write_stall::{WriteStall, WriteStallCause, WriteStallOptions},
+};
+
+/// Stalls writes well before the defaults would.
+const STALL_OPTIONS: WriteStallOptions = WriteStallOptions {
+    imm_memtable_slowdown_trigger: 2,
+    imm_memtable_stop_trigger: 3,
+    l0_slowdown_trigger: 3,
+    l0_stop_trigger: 4,
+    pending_compaction_bytes_slowdown: u64::MAX,
+    pending_compaction_bytes_stop: u64::MAX,
+    slowdown_delay: Duration::from_millis(1),
+};
+
+fn freeze(storage: &Arc<LsmStorageInner>) {
+    storage.put(b"other", b"value").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+}
+
+fn stall(stopped: bool, cause: WriteStallCause) -> Option<WriteStall> {
+    Some(WriteStall {
+        column_family: "default".to_string(),
+        cause,
+        stopped,
+    })
+}
+
+#[test]
+fn test_write_stall() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions {
+                write_stall_options: Some(STALL_OPTIONS),
+                ..simple_options(false)
+            },
+        )
+        .unwrap(),
+    );
+    let write_stall = || storage.write_controller.stall();
+
+    freeze(&storage);
+    assert_eq!(write_stall(), None);
+    freeze(&storage);
+    assert_eq!(
+        write_stall(),
+        stall(false, WriteStallCause::ImmMemtables(2))
+    );
+    // Delayed writes still go through.
+    storage.put(b"key", b"1").unwrap();
+    freeze(&storage);
+    assert_eq!(write_stall(), stall(true, WriteStallCause::ImmMemtables(3)));
+
+    // A blocked write goes through once a flush catches up.
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"key", b"2").unwrap())
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    storage.force_flush_next_imm_memtable().unwrap();
+    writer.join().unwrap();
+    assert_eq!(storage.get(b"key").unwrap().unwrap().as_ref(), b"2");
+
+    while !storage.state.read().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    assert_eq!(write_stall(), stall(false, WriteStallCause::L0Files(3)));
+    freeze(&storage);
+    storage.force_flush_next_imm_memtable().unwrap();
+    assert_eq!(write_stall(), stall(true, WriteStallCause::L0Files(4)));
+
+    storage.trigger_compaction(storage.default_cf()).unwrap();
+    assert_eq!(write_stall(), None);
+}
+
+#[test]
+fn test_blocked_write_fails_on_background_error() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions {
+                write_stall_options: Some(STALL_OPTIONS),
+                ..simple_options(false)
+            },
+        )
+        .unwrap(),
+    );
+    for _ in 0..3 {
+        freeze(&storage);
+    }
+    assert_eq!(
+        storage.write_controller.stall(),
+        stall(true, WriteStallCause::ImmMemtables(3))
+    );
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"key", b"value"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    // No flush runs after a fatal error, so the write would be blocked forever.
+    storage.report_background_error(
+        BackgroundErrorReason::Flush,
+        None,
+        &anyhow!("checksum mismatch"),
+    );
+    assert!(writer.join().unwrap().is_err());
+    assert_eq!(storage.get(b"key").unwrap(), None);
+}
+
+#[test]
+fn test_write_stall_without_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions {
+                write_stall_options: Some(STALL_OPTIONS),
+                ..no_compaction_options(false)
+            },
+        )
+        .unwrap(),
+    );
+    // L0 SSTs pile up without compaction, so they do not stall writes.
+    for _ in 0..5 {
+        freeze(&storage);
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    assert_eq!(storage.state.read().l0_sstables.len(), 5);
+    assert_eq!(storage.write_controller.stall(), None);
+}
+
+#[test]
+fn test_write_stall_options() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions {
+        write_stall_options: Some(STALL_OPTIONS),
+        ..simple_options(false)
+    };
+    options.num_memtable_limit = 3;
+    assert!(LsmStorageInner::open(&dir, options.clone()).is_err());
+    options.num_memtable_limit = 2;
+    options
+        .write_stall_options
+        .as_mut()
+        .unwrap()
+        .l0_stop_trigger = 2;
+    assert!(LsmStorageInner::open(&dir, options).is_err());
+}
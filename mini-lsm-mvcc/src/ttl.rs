@@ -0,0 +1,70 @@
+//! Per-key expiry. When `LsmStorageOptions::ttl` is set, every value is stored with the time it
+//! expires at, in milliseconds since the Unix epoch, appended as a big-endian u64. Delete
+//! tombstones stay empty. Expired keys are hidden from reads, and dropped by compactions once they
+//! are below the MVCC watermark.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// The expiry time of values that never expire.
+pub(crate) const NO_EXPIRY: u64 = u64::MAX;
+
+const EXPIRY_LEN: usize = std::mem::size_of::<u64>();
+
+/// The source of the current time, in milliseconds since the Unix epoch, that expiry times are
+/// computed from and compared with.
+pub trait Clock: Debug + Send + Sync {
+    fn now_millis(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TtlOptions {
+    /// The TTL of values written without one. They never expire if `None`.
+    pub default_ttl: Option<Duration>,
+    /// The clock used instead of the system clock, if any.
+    pub clock: Option<Arc<dyn Clock>>,
+}
+
+impl TtlOptions {
+    pub(crate) fn now_millis(&self) -> u64 {
+        match &self.clock {
+            Some(clock) => clock.now_millis(),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+
+    /// The expiry time of a value written now with `ttl`, or with the default TTL if `None`.
+    pub(crate) fn expiry_time(&self, ttl: Option<Duration>) -> u64 {
+        match ttl.or(self.default_ttl) {
+            Some(ttl) => self
+                .now_millis()
+                .saturating_add(ttl.as_millis().min(u64::MAX as u128) as u64),
+            None => NO_EXPIRY,
+        }
+    }
+}
+
+pub(crate) fn encode_value(value: &[u8], expiry: u64) -> Bytes {
+    let mut buf = BytesMut::with_capacity(value.len() + EXPIRY_LEN);
+    buf.put_slice(value);
+    buf.put_u64(expiry);
+    buf.freeze()
+}
+
+/// Splits a stored value into the value and its expiry time. Values too short to hold an expiry
+/// time are returned as they are, and never expire.
+pub(crate) fn decode_value(value: &[u8]) -> (&[u8], u64) {
+    match value.len().checked_sub(EXPIRY_LEN) {
+        Some(len) => {
+            let (value, expiry) = value.split_at(len);
+            (value, u64::from_be_bytes(expiry.try_into().unwrap()))
+        }
+        None => (value, NO_EXPIRY),
+    }
+}
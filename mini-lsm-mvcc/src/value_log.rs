@@ -0,0 +1,302 @@
+//! Key-value separation (WiscKey). Large values are moved out of the SSTs into append-only value
+//! log files when a memtable is flushed, and the SSTs only store a `ValuePointer` to them, so that
+//! compactions copy pointers instead of values.
+//!
+//! Every SST records how many bytes of each value log file it references. A value log file is
+//! deleted once no SST in the current state references it. Files that are mostly garbage are
+//! marked for relocation, and compaction then copies their live values into new files. Since
+//! relocation happens inside compaction, only the versions kept by the watermark rules are copied,
+//! and readers holding an older state keep the deleted files open until they are done.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::Mutex;
+
+use crate::backend::{StorageBackend, StorageFile};
+use crate::lsm_storage::LsmStorageInner;
+
+/// Magic number at the beginning of value log files.
+const VLOG_MAGIC: u32 = 0x4d4c_564c;
+/// Each record is `value | checksum`, and the file starts with a `magic | version` header.
+const VLOG_FORMAT_V1: u32 = 1;
+const VLOG_HEADER_SIZE: u64 = 8;
+const SIZEOF_CHECKSUM: u64 = std::mem::size_of::<u32>() as u64;
+
+#[derive(Debug, Clone)]
+pub struct ValueLogOptions {
+    /// Values of at least this many bytes are stored in the value log.
+    pub value_threshold: usize,
+    /// A value log file is relocated by compaction once less than this fraction of it is still
+    /// referenced by SSTs.
+    pub gc_live_ratio: f64,
+}
+
+/// Points to a value stored in a value log file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValuePointer {
+    pub file_id: usize,
+    /// Offset of the value in the file.
+    pub offset: u64,
+    /// Length of the value.
+    pub len: u32,
+}
+
+impl ValuePointer {
+    pub const ENCODED_LEN: usize = 20;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN {
+            bail!("invalid value pointer length {}", buf.len());
+        }
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u32(),
+        })
+    }
+
+    /// The number of bytes the pointed-to record takes in the value log file.
+    pub(crate) fn record_size(&self) -> u64 {
+        self.len as u64 + SIZEOF_CHECKSUM
+    }
+}
+
+/// An opened value log file.
+pub struct ValueLogFile {
+    file: Arc<dyn StorageFile>,
+    size: u64,
+}
+
+impl ValueLogFile {
+    fn open(backend: &dyn StorageBackend, path: &Path) -> Result<Self> {
+        let file = backend
+            .open(path)
+            .with_context(|| format!("failed to open value log {}", path.display()))?;
+        let size = file.size()?;
+        let mut header = [0; VLOG_HEADER_SIZE as usize];
+        file.read_at(&mut header, 0)?;
+        let mut header = &header[..];
+        if header.get_u32() != VLOG_MAGIC {
+            bail!("invalid value log {}", path.display());
+        }
+        let version = header.get_u32();
+        if version != VLOG_FORMAT_V1 {
+            bail!("unsupported value log format version {}", version);
+        }
+        Ok(Self { file, size })
+    }
+
+    /// Reads the value the pointer refers to.
+    pub fn read(&self, pointer: &ValuePointer) -> Result<Bytes> {
+        let mut data = vec![0; pointer.record_size() as usize];
+        self.file.read_at(&mut data, pointer.offset)?;
+        let len = pointer.len as usize;
+        if (&data[len..]).get_u32() != crc32fast::hash(&data[..len]) {
+            bail!("value log checksum mismatched");
+        }
+        data.truncate(len);
+        Ok(data.into())
+    }
+
+    /// The size of the file, excluding the header.
+    fn payload_size(&self) -> u64 {
+        self.size - VLOG_HEADER_SIZE
+    }
+}
+
+/// Buffers the values separated by an `SsTableBuilder`. Like the SST itself, the file is only
+/// written when the table is built.
+pub struct ValueLogWriter {
+    value_log: Arc<ValueLog>,
+    id: usize,
+    data: Vec<u8>,
+}
+
+impl ValueLogWriter {
+    pub(crate) fn value_log(&self) -> &Arc<ValueLog> {
+        &self.value_log
+    }
+
+    /// Whether a value of the given size should be stored in the value log.
+    pub(crate) fn should_separate(&self, value_len: usize) -> bool {
+        self.value_log
+            .options
+            .as_ref()
+            .is_some_and(|options| value_len >= options.value_threshold)
+    }
+
+    /// Appends a value and returns the pointer to it.
+    pub(crate) fn append(&mut self, value: &[u8]) -> ValuePointer {
+        let pointer = ValuePointer {
+            file_id: self.id,
+            offset: VLOG_HEADER_SIZE + self.data.len() as u64,
+            len: value.len() as u32,
+        };
+        self.data.put_slice(value);
+        self.data.put_u32(crc32fast::hash(value));
+        pointer
+    }
+
+    /// Writes the buffered values to disk, if there are any.
+    pub(crate) fn finish(self) -> Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        let file = self
+            .value_log
+            .backend
+            .create(&self.value_log.path_of_file(self.id))
+            .context("failed to create value log")?;
+        let mut buf = Vec::with_capacity(VLOG_HEADER_SIZE as usize + self.data.len());
+        buf.put_u32(VLOG_MAGIC);
+        buf.put_u32(VLOG_FORMAT_V1);
+        buf.put_slice(&self.data);
+        file.append(&buf)?;
+        file.sync()?;
+        Ok(())
+    }
+}
+
+/// Keeps track of the value log files of a storage engine.
+pub struct ValueLog {
+    backend: Arc<dyn StorageBackend>,
+    path: PathBuf,
+    pub(crate) options: Option<ValueLogOptions>,
+    /// Value log files that are currently open.
+    files: Mutex<HashMap<usize, Arc<ValueLogFile>>>,
+    /// Value log files whose values are copied into new files by compaction.
+    relocating: Mutex<HashSet<usize>>,
+}
+
+impl ValueLog {
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        path: impl AsRef<Path>,
+        options: Option<ValueLogOptions>,
+    ) -> Self {
+        Self {
+            backend,
+            path: path.as_ref().to_path_buf(),
+            options,
+            files: Mutex::new(HashMap::new()),
+            relocating: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn path_of_file(&self, id: usize) -> PathBuf {
+        LsmStorageInner::path_of_vlog_static(&self.path, id)
+    }
+
+    /// Creates a writer for a new value log file.
+    pub(crate) fn new_writer(self: &Arc<Self>, id: usize) -> ValueLogWriter {
+        ValueLogWriter {
+            value_log: self.clone(),
+            id,
+            data: Vec::new(),
+        }
+    }
+
+    /// Returns the value log file with the given id, opening it if necessary.
+    pub fn file(&self, id: usize) -> Result<Arc<ValueLogFile>> {
+        let mut files = self.files.lock();
+        if let Some(file) = files.get(&id) {
+            return Ok(file.clone());
+        }
+        let file = Arc::new(ValueLogFile::open(
+            self.backend.as_ref(),
+            &self.path_of_file(id),
+        )?);
+        files.insert(id, file.clone());
+        Ok(file)
+    }
+
+    /// Reads the value the pointer refers to.
+    pub fn read(&self, pointer: &ValuePointer) -> Result<Bytes> {
+        self.file(pointer.file_id)?.read(pointer)
+    }
+
+    /// Whether compaction should copy the values of this file into a new file.
+    pub(crate) fn is_relocating(&self, id: usize) -> bool {
+        self.relocating.lock().contains(&id)
+    }
+
+    /// Lists the ids of the value log files in the directory.
+    pub(crate) fn list_files(&self) -> Result<Vec<usize>> {
+        let mut ids = Vec::new();
+        for path in self.backend.list(&self.path)? {
+            if path.extension().is_some_and(|ext| ext == "vlog") {
+                if let Some(id) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Removes a value log file. Readers that still hold the file keep it open until they are done.
+    fn remove_file(&self, id: usize) -> Result<()> {
+        self.files.lock().remove(&id);
+        self.relocating.lock().remove(&id);
+        self.backend.delete(&self.path_of_file(id))?;
+        Ok(())
+    }
+}
+
+impl LsmStorageInner {
+    /// Deletes value log files that are no longer referenced by any SST, and marks files that are
+    /// mostly garbage for relocation. Returns the ids of the deleted files.
+    ///
+    /// Must not run concurrently with a compaction, as the files written by a compaction are only
+    /// referenced once its result is applied. Compactions run it once none of them is running.
+    /// Flushes hold the state lock while writing.
+    pub(crate) fn gc_value_log(&self) -> Result<Vec<usize>> {
+        let _state_lock = self.state_lock.lock();
+        let mut live_bytes = HashMap::<usize, u64>::new();
+        for cf in &self.column_families {
+            let snapshot = cf.state.read().clone();
+            for sst in snapshot.sstables.values() {
+                for (file_id, bytes) in sst.value_log_refs() {
+                    *live_bytes.entry(*file_id).or_default() += bytes;
+                }
+            }
+        }
+        let mut deleted = Vec::new();
+        for id in self.value_log.list_files()? {
+            match live_bytes.get(&id) {
+                None => {
+                    self.value_log.remove_file(id)?;
+                    deleted.push(id);
+                }
+                Some(&live) => {
+                    let Some(options) = &self.value_log.options else {
+                        continue;
+                    };
+                    let file = self.value_log.file(id)?;
+                    if (live as f64) < file.payload_size() as f64 * options.gc_live_ratio {
+                        self.value_log.relocating.lock().insert(id);
+                    }
+                }
+            }
+        }
+        if !deleted.is_empty() {
+            self.sync_dir()?;
+        }
+        Ok(deleted)
+    }
+}
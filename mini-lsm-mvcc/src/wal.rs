@@ -1,94 +1,397 @@
-use std::fs::{File, OpenOptions};
+use std::collections::BTreeMap;
 use std::hash::Hasher;
-use std::io::{BufWriter, Read, Write};
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut, Bytes};
 use crossbeam_skiplist::SkipMap;
-use parking_lot::Mutex;
 
+use crate::backend::{LocalBackend, StorageBackend, StorageFile};
 use crate::key::{KeyBytes, KeySlice};
+use crate::recovery::{DroppedTail, RecoveryMode};
 
+/// Magic number at the beginning of versioned WAL files. Files without it were written in the
+/// legacy format.
+const WAL_MAGIC: u32 = 0x4d4c_5741;
+/// The original format: key and value lengths are `u16`, no header.
+const WAL_FORMAT_LEGACY: u32 = 1;
+/// Key and value lengths are `u32`, and the file starts with a `magic | version` header.
+const WAL_FORMAT_V2: u32 = 2;
+/// Each record starts with its kind, so that range tombstones can be logged.
+const WAL_FORMAT_V3: u32 = 3;
+/// Each record carries the id of its column family after the kind, so that the memtables of all
+/// column families can share one WAL.
+const WAL_FORMAT_V4: u32 = 4;
+/// Records are logged in batches that share one commit timestamp and one checksum, so that a write
+/// batch is recovered all-or-nothing.
+const WAL_FORMAT_V5: u32 = 5;
+
+/// A key-value pair, or a deletion if the value is empty.
+const RECORD_PUT: u8 = 0;
+/// A range tombstone, where the key is the start and the value is the end of the range.
+const RECORD_DELETE_RANGE: u8 = 1;
+
+/// Takes `N` bytes from the front of the buffer, or returns `None` if it is shorter.
+fn take<const N: usize>(rbuf: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = rbuf.split_first_chunk::<N>()?;
+    *rbuf = rest;
+    Some(*head)
+}
+
+/// The column family, kind, key and value of a record read from a WAL.
+type WalRecord = (u32, u8, KeyBytes, Bytes);
+
+/// A batch read from a WAL. Files written before batches were introduced hold one record per batch.
+enum ParsedBatch {
+    /// The records of a batch.
+    Valid(Vec<WalRecord>),
+    /// A batch that does not match its checksum.
+    Corrupted,
+}
+
+/// The records of a write batch, which are logged under one commit timestamp and recovered
+/// all-or-nothing. A batch is encoded as `ts | count | len | records | checksum`, where the
+/// checksum covers everything before it, and each record as
+/// `kind | column family | key len | key | value len | value`.
+pub(crate) struct WalBatch {
+    ts: u64,
+    count: u32,
+    records: Vec<u8>,
+}
+
+impl WalBatch {
+    pub(crate) fn new(ts: u64) -> Self {
+        Self {
+            ts,
+            count: 0,
+            records: Vec::new(),
+        }
+    }
+
+    /// Adds a key-value pair of a column family, or a deletion if the value is empty.
+    pub(crate) fn put(&mut self, column_family: u32, key: &[u8], value: &[u8]) {
+        self.add(RECORD_PUT, column_family, key, value)
+    }
+
+    /// Adds a range tombstone deleting `start..end` of a column family.
+    pub(crate) fn delete_range(&mut self, column_family: u32, start: &[u8], end: &[u8]) {
+        self.add(RECORD_DELETE_RANGE, column_family, start, end)
+    }
+
+    fn add(&mut self, kind: u8, column_family: u32, key: &[u8], value: &[u8]) {
+        self.count += 1;
+        self.records.put_u8(kind);
+        self.records.put_u32(column_family);
+        self.records.put_u32(key.len() as u32);
+        self.records.put_slice(key);
+        self.records.put_u32(value.len() as u32);
+        self.records.put_slice(value);
+    }
+
+    /// Appends the encoded batch to the buffer, to be logged with `Wal::append`.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.put_u64(self.ts);
+        buf.put_u32(self.count);
+        buf.put_u32(self.records.len() as u32);
+        buf.put_slice(&self.records);
+        let checksum = crc32fast::hash(&buf[start..]);
+        buf.put_u32(checksum);
+    }
+}
+
+/// The recovered memtable contents of a column family: the key-value pairs and the range
+/// tombstones.
+pub(crate) type RecoveredMemTable = (SkipMap<KeyBytes, Bytes>, SkipMap<KeyBytes, Bytes>);
+
+/// A handle to a WAL file, which logs the records of one column family. Handles of other column
+/// families to the same file are created with `for_column_family`.
+#[derive(Clone)]
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    file: Arc<dyn StorageFile>,
+    column_family: u32,
 }
 
 impl Wal {
     pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::create_with_backend(&LocalBackend, path)
+    }
+
+    pub(crate) fn create_with_backend(
+        backend: &dyn StorageBackend,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let file = backend
+            .create(path.as_ref())
+            .context("failed to create WAL")?;
+        let mut header = Vec::with_capacity(8);
+        header.put_u32(WAL_MAGIC);
+        header.put_u32(WAL_FORMAT_V5);
+        file.append(&header)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+            file,
+            column_family: 0,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Returns a handle to the same file that logs the records of another column family.
+    pub(crate) fn for_column_family(&self, column_family: u32) -> Self {
+        Self {
+            file: self.file.clone(),
+            column_family,
+        }
+    }
+
+    /// Strips the header from the WAL content and returns the format version.
+    fn read_header(buf: &mut &[u8]) -> Result<u32> {
+        if buf.len() < 8 || (&buf[..4]).get_u32() != WAL_MAGIC {
+            return Ok(WAL_FORMAT_LEGACY);
+        }
+        buf.advance(4);
+        let version = buf.get_u32();
+        if !(WAL_FORMAT_V2..=WAL_FORMAT_V5).contains(&version) {
+            bail!("unsupported WAL format version {}", version);
+        }
+        Ok(version)
+    }
+
+    /// Replays the WAL into the skiplists of a memtable. Range tombstones are keyed by their start
+    /// key and map to their end key.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        range_tombstones: &SkipMap<KeyBytes, Bytes>,
+    ) -> Result<Self> {
+        let (wal, _) = Self::replay(
+            &LocalBackend,
+            path,
+            RecoveryMode::AbsoluteConsistency,
+            |column_family, kind, key, value| {
+                if column_family != 0 {
+                    bail!("unexpected record of column family {}", column_family);
+                }
+                match kind {
+                    RECORD_PUT => skiplist.insert(key, value),
+                    RECORD_DELETE_RANGE => range_tombstones.insert(key, value),
+                    _ => bail!("unknown WAL record kind {}", kind),
+                };
+                Ok(())
+            },
+        )?;
+        Ok(wal)
+    }
+
+    /// Replays a WAL shared by several column families, and returns the memtable contents of each
+    /// column family that has records in it, and the damaged tail dropped according to `mode`.
+    pub(crate) fn recover_column_families(
+        backend: &dyn StorageBackend,
+        path: impl AsRef<Path>,
+        mode: RecoveryMode,
+    ) -> Result<(Self, BTreeMap<u32, RecoveredMemTable>, Option<DroppedTail>)> {
+        let mut memtables = BTreeMap::<u32, RecoveredMemTable>::new();
+        let (wal, dropped_tail) =
+            Self::replay(backend, path, mode, |column_family, kind, key, value| {
+                let (skiplist, range_tombstones) = memtables.entry(column_family).or_default();
+                match kind {
+                    RECORD_PUT => skiplist.insert(key, value),
+                    RECORD_DELETE_RANGE => range_tombstones.insert(key, value),
+                    _ => bail!("unknown WAL record kind {}", kind),
+                };
+                Ok(())
+            })?;
+        Ok((wal, memtables, dropped_tail))
+    }
+
+    /// Parses the batch at the start of the buffer and advances it. Returns `None` if the buffer
+    /// ends within the header of the batch, or right after it. A length that points past the end
+    /// while more bytes follow the header is corrupted instead, so the batches after it are not
+    /// mistaken for a torn tail.
+    fn parse_batch(rbuf: &mut &[u8], version: u32) -> Option<ParsedBatch> {
+        if version < WAL_FORMAT_V5 {
+            return Some(match Self::parse_record(rbuf, version)? {
+                Some(record) => ParsedBatch::Valid(vec![record]),
+                None => ParsedBatch::Corrupted,
+            });
+        }
+        let start = *rbuf;
+        let ts = u64::from_be_bytes(take(rbuf)?);
+        let count = u32::from_be_bytes(take(rbuf)?);
+        let len = u32::from_be_bytes(take(rbuf)?) as usize;
+        if rbuf.remaining() < len + 4 {
+            return rbuf.has_remaining().then_some(ParsedBatch::Corrupted);
+        }
+        let (mut records, rest) = rbuf.split_at(len);
+        *rbuf = rest;
+        let checksum = u32::from_be_bytes(take(rbuf)?);
+        if crc32fast::hash(&start[..start.len() - rbuf.len() - 4]) != checksum {
+            return Some(ParsedBatch::Corrupted);
+        }
+        let mut batch = Vec::new();
+        for _ in 0..count {
+            match Self::parse_batch_record(&mut records, ts) {
+                Some(record) => batch.push(record),
+                None => return Some(ParsedBatch::Corrupted),
+            }
+        }
+        if records.has_remaining() {
+            return Some(ParsedBatch::Corrupted);
+        }
+        Some(ParsedBatch::Valid(batch))
+    }
+
+    /// Parses a record of a batch, whose checksum was already verified.
+    fn parse_batch_record(records: &mut &[u8], ts: u64) -> Option<WalRecord> {
+        let kind = u8::from_be_bytes(take(records)?);
+        let column_family = u32::from_be_bytes(take(records)?);
+        let mut get_bytes = || {
+            let len = u32::from_be_bytes(take(records)?) as usize;
+            if records.remaining() < len {
+                return None;
+            }
+            let data = Bytes::copy_from_slice(&records[..len]);
+            records.advance(len);
+            Some(data)
+        };
+        let key = get_bytes()?;
+        let value = get_bytes()?;
+        Some((
+            column_family,
+            kind,
+            KeyBytes::from_bytes_with_ts(key, ts),
+            value,
+        ))
+    }
+
+    /// Parses a record of a WAL written before batches were introduced at the start of the buffer
+    /// and advances it. Returns `None` if the buffer ends before the record does, and `Some(None)`
+    /// if the record does not match its checksum.
+    fn parse_record(rbuf: &mut &[u8], version: u32) -> Option<Option<WalRecord>> {
+        let mut hasher = crc32fast::Hasher::new();
+        let kind = if version >= WAL_FORMAT_V3 {
+            let kind = u8::from_be_bytes(take(rbuf)?);
+            hasher.write_u8(kind);
+            kind
+        } else {
+            RECORD_PUT
+        };
+        let column_family = if version >= WAL_FORMAT_V4 {
+            let column_family = u32::from_be_bytes(take(rbuf)?);
+            hasher.write_u32(column_family);
+            column_family
+        } else {
+            0
+        };
+        // Legacy records hash the lengths as `u16`, current ones as `u32`.
+        let get_bytes = |rbuf: &mut &[u8], hasher: &mut crc32fast::Hasher| {
+            let len = if version == WAL_FORMAT_LEGACY {
+                let len = u16::from_be_bytes(take(rbuf)?);
+                hasher.write_u16(len);
+                len as usize
+            } else {
+                let len = u32::from_be_bytes(take(rbuf)?);
+                hasher.write_u32(len);
+                len as usize
+            };
+            if rbuf.remaining() < len {
+                return None;
+            }
+            let data = Bytes::copy_from_slice(&rbuf[..len]);
+            hasher.write(&data);
+            rbuf.advance(len);
+            Some(data)
+        };
+        let key = get_bytes(rbuf, &mut hasher)?;
+        let ts = u64::from_be_bytes(take(rbuf)?);
+        hasher.write_u64(ts);
+        let value = get_bytes(rbuf, &mut hasher)?;
+        let checksum = u32::from_be_bytes(take(rbuf)?);
+        if hasher.finalize() != checksum {
+            return Some(None);
+        }
+        Some(Some((
+            column_family,
+            kind,
+            KeyBytes::from_bytes_with_ts(key, ts),
+            value,
+        )))
+    }
+
+    /// Calls `on_record` with the column family, kind, key and value of each record in the WAL. A
+    /// damaged tail is dropped if `mode` allows it, and returned. The records of a damaged batch
+    /// are never replayed.
+    fn replay(
+        backend: &dyn StorageBackend,
+        path: impl AsRef<Path>,
+        mode: RecoveryMode,
+        mut on_record: impl FnMut(u32, u8, KeyBytes, Bytes) -> Result<()>,
+    ) -> Result<(Self, Option<DroppedTail>)> {
         let path = path.as_ref();
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(path)
-            .context("failed to recover from WAL")?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
+        let file = backend.open(path).context("failed to recover from WAL")?;
+        let buf = file.read_all()?;
         let mut rbuf: &[u8] = buf.as_slice();
+        let version = Self::read_header(&mut rbuf)?;
+        let mut dropped_tail = None;
         while rbuf.has_remaining() {
-            let mut hasher = crc32fast::Hasher::new();
-            let key_len = rbuf.get_u16() as usize;
-            hasher.write_u16(key_len as u16);
-            let key = Bytes::copy_from_slice(&rbuf[..key_len]);
-            hasher.write(&key);
-            rbuf.advance(key_len);
-            let ts = rbuf.get_u64();
-            hasher.write_u64(ts);
-            let value_len = rbuf.get_u16() as usize;
-            hasher.write_u16(value_len as u16);
-            let value = Bytes::copy_from_slice(&rbuf[..value_len]);
-            hasher.write(&value);
-            rbuf.advance(value_len);
-            let checksum = rbuf.get_u32();
-            if hasher.finalize() != checksum {
-                bail!("checksum mismatch");
-            }
-            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            let offset = (buf.len() - rbuf.remaining()) as u64;
+            let (is_tail, reason) = match Self::parse_batch(&mut rbuf, version) {
+                Some(ParsedBatch::Valid(records)) => {
+                    for (column_family, kind, key, value) in records {
+                        on_record(column_family, kind, key, value)?;
+                    }
+                    continue;
+                }
+                Some(ParsedBatch::Corrupted) => (!rbuf.has_remaining(), "WAL checksum mismatch"),
+                None => (true, "incomplete WAL record"),
+            };
+            mode.check_damaged_record(path, offset, is_tail, reason)?;
+            dropped_tail = Some(DroppedTail::truncate(
+                file.as_ref(),
+                path,
+                offset,
+                buf.len() as u64,
+            )?);
+            break;
         }
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
-        })
+        Ok((
+            Self {
+                file,
+                column_family: 0,
+            },
+            dropped_tail,
+        ))
     }
 
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        let mut file = self.file.lock();
-        let mut buf: Vec<u8> =
-            Vec::with_capacity(key.raw_len() + value.len() + std::mem::size_of::<u16>());
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.write_u16(key.key_len() as u16);
-        buf.put_u16(key.key_len() as u16);
-        hasher.write(key.key_ref());
-        buf.put_slice(key.key_ref());
-        hasher.write_u64(key.ts());
-        buf.put_u64(key.ts());
-        hasher.write_u16(value.len() as u16);
-        buf.put_u16(value.len() as u16);
-        buf.put_slice(value);
-        hasher.write(value);
-        // add checksum: week 2 day 7
-        buf.put_u32(hasher.finalize());
-        file.write_all(&buf)?;
+        let mut batch = WalBatch::new(key.ts());
+        batch.put(self.column_family, key.key_ref(), value);
+        self.put_batch(&batch)
+    }
+
+    /// Logs a range tombstone deleting `start..end` at the timestamp of `start`.
+    pub fn put_range_tombstone(&self, start: KeySlice, end: &[u8]) -> Result<()> {
+        let mut batch = WalBatch::new(start.ts());
+        batch.delete_range(self.column_family, start.key_ref(), end);
+        self.put_batch(&batch)
+    }
+
+    fn put_batch(&self, batch: &WalBatch) -> Result<()> {
+        let mut buf = Vec::new();
+        batch.encode(&mut buf);
+        self.append(&buf, false)
+    }
+
+    /// Logs the batches encoded in the buffer with a single write, and syncs the file if `sync` is
+    /// set.
+    pub(crate) fn append(&self, buf: &[u8], sync: bool) -> Result<()> {
+        self.file.append(buf)?;
+        if sync {
+            self.file.sync()?;
+        }
         Ok(())
     }
 
     pub fn sync(&self) -> Result<()> {
-        let mut file = self.file.lock();
-        file.flush()?;
-        file.get_mut().sync_all()?;
-        Ok(())
+        self.file.sync()
     }
 }
@@ -0,0 +1,256 @@
+//! Backpressure on writes when flushes or compactions fall behind. Like RocksDB, writes are first
+//! delayed once a column family crosses a slowdown trigger, and then blocked until the background
+//! threads catch up once it crosses a stop trigger.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use parking_lot::{Condvar, Mutex};
+
+use crate::background_error::BackgroundErrorHandler;
+use crate::column_family::{ColumnFamily, ColumnFamilyOptions};
+use crate::compact::CompactionOptions;
+use crate::event_listener::{EventListener, WriteStallInfo};
+use crate::statistics::Statistics;
+
+#[derive(Debug, Clone)]
+pub struct WriteStallOptions {
+    /// Writes are delayed once a column family has this many immutable memtables.
+    pub imm_memtable_slowdown_trigger: usize,
+    /// Writes are blocked once a column family has this many immutable memtables. Must be larger
+    /// than the `num_memtable_limit` of each column family, which triggers the flush.
+    pub imm_memtable_stop_trigger: usize,
+    /// Writes are delayed once a column family has this many L0 SSTs.
+    pub l0_slowdown_trigger: usize,
+    /// Writes are blocked once a column family has this many L0 SSTs. Must be larger than the L0
+    /// compaction trigger of each column family.
+    pub l0_stop_trigger: usize,
+    /// Writes are delayed once the estimated bytes a column family needs to compact reach this.
+    pub pending_compaction_bytes_slowdown: u64,
+    /// Writes are blocked once the estimated bytes a column family needs to compact reach this.
+    pub pending_compaction_bytes_stop: u64,
+    /// How long each write is delayed while writes are slowed down.
+    pub slowdown_delay: Duration,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> Self {
+        Self {
+            imm_memtable_slowdown_trigger: 6,
+            imm_memtable_stop_trigger: 10,
+            l0_slowdown_trigger: 20,
+            l0_stop_trigger: 36,
+            pending_compaction_bytes_slowdown: 64 << 30,
+            pending_compaction_bytes_stop: 256 << 30,
+            slowdown_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+impl WriteStallOptions {
+    /// Checks that the stop triggers cannot block writes before a flush or compaction is triggered,
+    /// which would block them forever.
+    pub(crate) fn check_column_family(
+        &self,
+        name: &str,
+        options: &ColumnFamilyOptions,
+    ) -> Result<()> {
+        if self.imm_memtable_stop_trigger <= options.num_memtable_limit {
+            bail!(
+                "the immutable memtable stop trigger must be larger than the memtable limit of column family {}",
+                name
+            );
+        }
+        let l0_compaction_trigger = match &options.compaction_options {
+            CompactionOptions::Leveled(options) => options.level0_file_num_compaction_trigger,
+            CompactionOptions::Simple(options) => options.level0_file_num_compaction_trigger,
+            CompactionOptions::Tiered(_) | CompactionOptions::NoCompaction => 0,
+        };
+        if self.l0_stop_trigger <= l0_compaction_trigger {
+            bail!(
+                "the L0 stop trigger must be larger than the L0 compaction trigger of column family {}",
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// The stall of the first column family that crossed a stop trigger, or else of the first one
+    /// that crossed a slowdown trigger. The L0 and compaction triggers do not apply to column
+    /// families without compaction.
+    fn stall(&self, column_families: &[Arc<ColumnFamily>]) -> Option<WriteStall> {
+        let mut delayed = None;
+        for cf in column_families {
+            let state = cf.state.read().clone();
+            let imm_memtables = state.imm_memtables.len();
+            let mut checks = vec![(
+                WriteStallCause::ImmMemtables(imm_memtables),
+                imm_memtables >= self.imm_memtable_slowdown_trigger,
+                imm_memtables >= self.imm_memtable_stop_trigger,
+            )];
+            if !matches!(
+                cf.options.compaction_options,
+                CompactionOptions::NoCompaction
+            ) {
+                let l0_files = state.l0_sstables.len();
+                checks.push((
+                    WriteStallCause::L0Files(l0_files),
+                    l0_files >= self.l0_slowdown_trigger,
+                    l0_files >= self.l0_stop_trigger,
+                ));
+                let pending = cf.compaction_controller.pending_compaction_bytes(&state);
+                checks.push((
+                    WriteStallCause::PendingCompactionBytes(pending),
+                    pending >= self.pending_compaction_bytes_slowdown,
+                    pending >= self.pending_compaction_bytes_stop,
+                ));
+            }
+            for (cause, slowdown, stop) in checks {
+                if stop || (slowdown && delayed.is_none()) {
+                    let stall = WriteStall {
+                        column_family: cf.name().to_string(),
+                        cause,
+                        stopped: stop,
+                    };
+                    if stop {
+                        return Some(stall);
+                    }
+                    delayed = Some(stall);
+                }
+            }
+        }
+        delayed
+    }
+}
+
+/// What crossed a write stall trigger, with its current value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteStallCause {
+    ImmMemtables(usize),
+    L0Files(usize),
+    PendingCompactionBytes(u64),
+}
+
+/// Why writes are delayed or blocked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteStall {
+    pub column_family: String,
+    pub cause: WriteStallCause,
+    /// Whether writes are blocked rather than delayed.
+    pub stopped: bool,
+}
+
+impl fmt::Display for WriteStall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = if self.stopped { "blocked" } else { "delayed" };
+        let cause = match self.cause {
+            WriteStallCause::ImmMemtables(count) => format!("{} immutable memtables", count),
+            WriteStallCause::L0Files(count) => format!("{} L0 SSTs", count),
+            WriteStallCause::PendingCompactionBytes(bytes) => {
+                format!("{} bytes pending compaction", bytes)
+            }
+        };
+        write!(
+            f,
+            "writes {}: column family {} has {}",
+            action, self.column_family, cause
+        )
+    }
+}
+
+/// Delays and blocks writes according to the write stall of the column families, which is updated
+/// whenever their memtables or SSTs change.
+pub(crate) struct WriteController {
+    options: Option<WriteStallOptions>,
+    stall: Mutex<Option<WriteStall>>,
+    cv: Condvar,
+    listeners: Vec<Arc<dyn EventListener>>,
+    statistics: Arc<Statistics>,
+}
+
+impl WriteController {
+    pub(crate) fn new(
+        options: Option<WriteStallOptions>,
+        listeners: Vec<Arc<dyn EventListener>>,
+        statistics: Arc<Statistics>,
+    ) -> Self {
+        Self {
+            options,
+            stall: Mutex::new(None),
+            cv: Condvar::new(),
+            listeners,
+            statistics,
+        }
+    }
+
+    /// Recomputes the write stall, and wakes up the blocked writes and notifies the listeners if
+    /// it changed.
+    pub(crate) fn update(&self, column_families: &[Arc<ColumnFamily>]) {
+        let Some(options) = &self.options else {
+            return;
+        };
+        // Computed under the lock, so that an outdated stall never replaces a newer one.
+        let mut current = self.stall.lock();
+        let stall = options.stall(column_families);
+        if *current == stall {
+            return;
+        }
+        let previous = std::mem::replace(&mut *current, stall.clone());
+        self.cv.notify_all();
+        drop(current);
+        match &stall {
+            Some(stall) => log::warn!("{}", stall),
+            None => log::info!("writes are no longer stalled"),
+        }
+        let info = WriteStallInfo {
+            previous,
+            current: stall,
+        };
+        for listener in &self.listeners {
+            listener.on_stall_conditions_changed(&info);
+        }
+    }
+
+    pub(crate) fn stall(&self) -> Option<WriteStall> {
+        self.stall.lock().clone()
+    }
+
+    /// Delays a write while writes are slowed down, and blocks it while they are stopped. A blocked
+    /// write fails once a background error stops writes, as the DB may not catch up anymore.
+    pub(crate) fn wait(&self, background_error: &BackgroundErrorHandler) -> Result<()> {
+        let start = Instant::now();
+        let mut stall = self.stall.lock();
+        let mut stopped = false;
+        let result = loop {
+            match &*stall {
+                None => break Ok(()),
+                Some(WriteStall { stopped: true, .. }) => {
+                    // Checked under the lock, so that the wake-up of a new error cannot be missed.
+                    if let Err(e) = background_error.check_writable() {
+                        break Err(e);
+                    }
+                    stopped = true;
+                    self.cv.wait(&mut stall);
+                }
+                Some(_) => {
+                    drop(stall);
+                    std::thread::sleep(self.options.as_ref().unwrap().slowdown_delay);
+                    self.statistics.record_write_stall(stopped, start.elapsed());
+                    return Ok(());
+                }
+            }
+        };
+        if stopped {
+            self.statistics.record_write_stall(true, start.elapsed());
+        }
+        result
+    }
+
+    /// Wakes up the blocked writes to check for a background error.
+    pub(crate) fn wake_up(&self) {
+        let _stall = self.stall.lock();
+        self.cv.notify_all();
+    }
+}
@@ -34,6 +34,9 @@ struct Args {
     enable_wal: bool,
     #[arg(long)]
     serializable: bool,
+    /// Limit the write rate of flushes and compactions, in MB per second
+    #[arg(long)]
+    rate_limit_mb: Option<u64>,
 }
 
 struct ReplHandler {
@@ -113,6 +116,10 @@ impl ReplHandler {
                 self.lsm.dump_structure();
                 println!("dump success");
             }
+            Command::Stats => match wrapper::statistics(&self.lsm) {
+                Some(statistics) => print!("{}", statistics),
+                None => println!("statistics are not available"),
+            },
             Command::Flush => {
                 self.lsm.force_flush()?;
                 println!("flush success");
@@ -148,6 +155,7 @@ enum Command {
     },
 
     Dump,
+    Stats,
     Flush,
     FullCompaction,
     Quit,
@@ -221,6 +229,7 @@ impl Command {
                 get,
                 scan,
                 map(tag_no_case("dump"), |_| Command::Dump),
+                map(tag_no_case("stats"), |_| Command::Stats),
                 map(tag_no_case("flush"), |_| Command::Flush),
                 map(tag_no_case("full_compaction"), |_| Command::FullCompaction),
                 map(tag_no_case("quit"), |_| Command::Quit),
@@ -311,40 +320,35 @@ impl ReplBuilder {
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let lsm = MiniLsm::open(
-        args.path,
-        LsmStorageOptions {
-            block_size: 4096,
-            target_sst_size: 2 << 20, // 2MB
-            num_memtable_limit: 3,
-            compaction_options: match args.compaction {
-                CompactionStrategy::None => CompactionOptions::NoCompaction,
-                CompactionStrategy::Simple => {
-                    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
-                        size_ratio_percent: 200,
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                    })
-                }
-                CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
-                    num_tiers: 3,
-                    max_size_amplification_percent: 200,
-                    size_ratio: 1,
-                    min_merge_width: 2,
-                }),
-                CompactionStrategy::Leveled => {
-                    CompactionOptions::Leveled(LeveledCompactionOptions {
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                        base_level_size_mb: 128,
-                        level_size_multiplier: 2,
-                    })
-                }
-            },
-            enable_wal: args.enable_wal,
-            serializable: args.serializable,
-        },
-    )?;
+    // Start from the defaults, as some crates have more options than the CLI sets.
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.num_memtable_limit = 3;
+    options.compaction_options = match args.compaction {
+        CompactionStrategy::None => CompactionOptions::NoCompaction,
+        CompactionStrategy::Simple => CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+        }),
+        CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }),
+        CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 128,
+            level_size_multiplier: 2,
+        }),
+    };
+    options.enable_wal = args.enable_wal;
+    options.serializable = args.serializable;
+    if let Some(mb) = args.rate_limit_mb {
+        wrapper::set_rate_limit(&mut options, mb << 20)?;
+    }
+    let lsm = MiniLsm::open(args.path, options)?;
 
     let repl = ReplBuilder::new()
         .app_name("mini-lsm-cli")
@@ -2,5 +2,20 @@ pub mod mini_lsm_wrapper {
     pub use mini_lsm_starter::*;
 }
 
+/// The statistics of the DB as text, which this crate does not keep.
+#[allow(dead_code)]
+pub fn statistics(_lsm: &mini_lsm_wrapper::lsm_storage::MiniLsm) -> Option<String> {
+    None
+}
+
+/// Limits the write rate of flushes and compactions, which this crate does not support.
+#[allow(dead_code)]
+pub fn set_rate_limit(
+    _options: &mut mini_lsm_wrapper::lsm_storage::LsmStorageOptions,
+    _bytes_per_second: u64,
+) -> anyhow::Result<()> {
+    anyhow::bail!("rate limiting is not supported")
+}
+
 #[allow(dead_code)]
 fn main() {}